@@ -0,0 +1,486 @@
+// plc_simulator.rs - SIMULADOR DE PLC (FORMATO TSEND_C)
+// ============================================================================
+// Liga-se ao servidor TCP (porta 8502) como um S7-1500 e envia frames com o
+// layout UDT_TCP_Data (ou um layout JSON igual ao de get_packet_layout) a uma
+// frequência configurável. Permite desenvolver o painel sem o laboratório.
+// ============================================================================
+// USO:
+//   cargo run --bin plc_simulator -- [opções]
+//     --host 127.0.0.1       Servidor TCP
+//     --port 8502
+//     --rate 2               Frames por segundo (TSEND_C @ 2Hz)
+//     --scenario phases      phases | bits | counter
+//     --step-secs 5          Duração de cada fase/bit
+//     --script ficheiro.txt  Cenário em script (substitui --scenario)
+//     --layout layout.json   Layout do pacote (padrão: UDT_TCP_Data)
+//     --framed               Envia frames PLCF (magic + sequência + CRC32)
+//
+// CENÁRIOS:
+//   phases  - percorre as 6 fases da eclusa (phase_configs) ativando os bits
+//             de insert_default_bit_configs correspondentes
+//   bits    - liga um bit padrão de cada vez (ECLUSA_ATIVA, EMERGENCIA, ...)
+//   counter - Word/Int/Real a variar continuamente (teste de carga/gráficos)
+//
+// SCRIPT (uma instrução por linha, '#' = comentário, repete no fim):
+//   set Word[0].3 1        Bit 3 do Word[0]
+//   set Int[0] 250         Valor de uma variável
+//   set Real[10] 21.5
+//   clear                  Zera o frame
+//   wait 2.5               Pausa em segundos
+// ============================================================================
+
+#[allow(dead_code)]
+#[path = "../packet_layout.rs"]
+mod packet_layout;
+#[allow(dead_code)]
+#[path = "../framing.rs"]
+mod framing;
+
+use std::time::{Duration, Instant};
+use packet_layout::{ByteOrder, PacketLayout, SegmentType};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+// ============================================================================
+// FRAME
+// ============================================================================
+
+/// Frame binário com escrita por nome de variável (`Word[3]`, `Word[3].5`)
+struct Frame {
+    layout: PacketLayout,
+    data: Vec<u8>,
+}
+
+impl Frame {
+    fn new(layout: PacketLayout) -> Self {
+        let data = vec![0u8; layout.packet_size];
+        Self { layout, data }
+    }
+
+    fn clear(&mut self) {
+        self.data.fill(0);
+    }
+
+    /// Localiza `{segmento}[i]` e devolve (offset, tipo, ordem de bytes)
+    fn locate(&self, segment: &str, index: usize) -> Result<(usize, SegmentType, ByteOrder), String> {
+        let s = self.layout.segments.iter()
+            .find(|s| s.name == segment)
+            .ok_or_else(|| format!("Segmento desconhecido: {}", segment))?;
+        if index >= s.count {
+            return Err(format!("{}[{}] fora do segmento ({} elementos)", segment, index, s.count));
+        }
+        let offset = match s.data_type {
+            SegmentType::Bool => s.offset + index / 8,
+            _ => s.offset + index * s.stride(),
+        };
+        Ok((offset, s.data_type, s.byte_order))
+    }
+
+    fn set(&mut self, segment: &str, index: usize, value: f64) -> Result<(), String> {
+        let (offset, data_type, order) = self.locate(segment, index)?;
+        let bytes: Vec<u8> = match (data_type, order) {
+            (SegmentType::Word, ByteOrder::Big) => (value as u16).to_be_bytes().to_vec(),
+            (SegmentType::Word, ByteOrder::Little) => (value as u16).to_le_bytes().to_vec(),
+            (SegmentType::Int, ByteOrder::Big) => (value as i16).to_be_bytes().to_vec(),
+            (SegmentType::Int, ByteOrder::Little) => (value as i16).to_le_bytes().to_vec(),
+            (SegmentType::Real, ByteOrder::Big) => (value as f32).to_be_bytes().to_vec(),
+            (SegmentType::Real, ByteOrder::Little) => (value as f32).to_le_bytes().to_vec(),
+            (SegmentType::DInt | SegmentType::Time, ByteOrder::Big) => (value as i32).to_be_bytes().to_vec(),
+            (SegmentType::DInt | SegmentType::Time, ByteOrder::Little) => (value as i32).to_le_bytes().to_vec(),
+            (SegmentType::UDInt, ByteOrder::Big) => (value as u32).to_be_bytes().to_vec(),
+            (SegmentType::UDInt, ByteOrder::Little) => (value as u32).to_le_bytes().to_vec(),
+            (SegmentType::LReal, ByteOrder::Big) => value.to_be_bytes().to_vec(),
+            (SegmentType::LReal, ByteOrder::Little) => value.to_le_bytes().to_vec(),
+            (SegmentType::Bool, _) => {
+                let mask = 1u8 << (index % 8);
+                let byte = self.data[offset];
+                vec![if value != 0.0 { byte | mask } else { byte & !mask }]
+            }
+            (SegmentType::String | SegmentType::Dtl, _) => {
+                return Err(format!("{}[{}]: tipo {} não suportado pelo simulador", segment, index, data_type.as_str()));
+            }
+        };
+        self.data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    fn set_bit(&mut self, segment: &str, index: usize, bit: u8, on: bool) -> Result<(), String> {
+        let (offset, data_type, order) = self.locate(segment, index)?;
+        if !matches!(data_type, SegmentType::Word | SegmentType::Int) || bit > 15 {
+            return Err(format!("{}[{}].{} não é um bit válido", segment, index, bit));
+        }
+        let mut word = order.read_u16(&self.data[offset..]);
+        if on { word |= 1 << bit } else { word &= !(1 << bit) }
+        let bytes = match order {
+            ByteOrder::Big => word.to_be_bytes(),
+            ByteOrder::Little => word.to_le_bytes(),
+        };
+        self.data[offset..offset + 2].copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// Atribui `valor` a "Seg[i]" ou "Seg[i].b"
+    fn assign(&mut self, target: &str, value: f64) -> Result<(), String> {
+        let (segment, index, bit) = parse_target(target)?;
+        match bit {
+            Some(bit) => self.set_bit(&segment, index, bit, value != 0.0),
+            None => self.set(&segment, index, value),
+        }
+    }
+}
+
+/// "Word[3].5" -> ("Word", 3, Some(5))
+fn parse_target(target: &str) -> Result<(String, usize, Option<u8>), String> {
+    let invalid = || format!("Variável inválida: {} (ex: Word[0].3, Int[5], Real[10])", target);
+    let open = target.find('[').ok_or_else(invalid)?;
+    let close = target.find(']').ok_or_else(invalid)?;
+    let index = target[open + 1..close].parse().map_err(|_| invalid())?;
+    let bit = match target[close + 1..].strip_prefix('.') {
+        Some(b) => Some(b.parse().map_err(|_| invalid())?),
+        None if target[close + 1..].is_empty() => None,
+        None => return Err(invalid()),
+    };
+    Ok((target[..open].to_string(), index, bit))
+}
+
+// ============================================================================
+// CENÁRIOS
+// ============================================================================
+
+// Bits semeados por insert_default_bit_configs (word, bit)
+const ECLUSA_ATIVA: (usize, u8) = (0, 0);
+const PORTA_MONTANTE_ABERTA: (usize, u8) = (0, 3);
+const BARCO_PRESENTE_MONTANTE: (usize, u8) = (1, 0);
+const BARCO_PRESENTE_CALDEIRA: (usize, u8) = (1, 1);
+const EXCESSO_VELOCIDADE_MONTANTE: (usize, u8) = (2, 0);
+const SEMAFORO_VERMELHO: (usize, u8) = (2, 2);
+const SEMAFORO_VERDE: (usize, u8) = (2, 3);
+const BOMBA_AGUA_LIGADA: (usize, u8) = (3, 0);
+const NIVEL_AGUA_ALTO: (usize, u8) = (3, 1);
+
+const DEFAULT_BITS: &[(usize, u8, &str)] = &[
+    (0, 0, "ECLUSA_ATIVA"),
+    (0, 1, "EMERGENCIA"),
+    (0, 2, "MANUTENCAO"),
+    (0, 3, "PORTA_MONTANTE_ABERTA"),
+    (0, 4, "PORTA_JUSANTE_ABERTA"),
+    (1, 0, "BARCO_PRESENTE_MONTANTE"),
+    (1, 1, "BARCO_PRESENTE_CALDEIRA"),
+    (1, 2, "BARCO_PRESENTE_JUSANTE"),
+    (2, 0, "EXCESSO_VELOCIDADE_MONTANTE"),
+    (2, 1, "EXCESSO_VELOCIDADE_CALDEIRA"),
+    (2, 2, "SEMAFORO_VERMELHO"),
+    (2, 3, "SEMAFORO_VERDE"),
+    (3, 0, "BOMBA_AGUA_LIGADA"),
+    (3, 1, "NIVEL_AGUA_ALTO"),
+    (3, 2, "ILUMINACAO_LIGADA"),
+];
+
+/// Bits ativos em cada fase (phase_configs 1..6)
+fn phase_bits(phase: usize) -> &'static [(usize, u8)] {
+    match phase {
+        // SEM ECLUSAGEM
+        1 => &[SEMAFORO_VERMELHO],
+        // EM PREPARAÇÃO
+        2 => &[ECLUSA_ATIVA, SEMAFORO_VERMELHO, BOMBA_AGUA_LIGADA],
+        // AUTORIZAÇÃO ENTRAR - SEM EXCESSO VELOCIDADE
+        3 => &[ECLUSA_ATIVA, SEMAFORO_VERDE, PORTA_MONTANTE_ABERTA, BARCO_PRESENTE_MONTANTE],
+        // AUTORIZAÇÃO ENTRAR - COM EXCESSO VELOCIDADE
+        4 => &[ECLUSA_ATIVA, SEMAFORO_VERDE, PORTA_MONTANTE_ABERTA, BARCO_PRESENTE_MONTANTE, EXCESSO_VELOCIDADE_MONTANTE],
+        // APÓS ENTRADA DO BARCO - PORTA MONTANTE A FECHAR
+        5 => &[ECLUSA_ATIVA, SEMAFORO_VERMELHO, BARCO_PRESENTE_CALDEIRA],
+        // CICLO ECLUSAGEM AUTOMÁTICA MONTANTE
+        _ => &[ECLUSA_ATIVA, SEMAFORO_VERMELHO, BARCO_PRESENTE_CALDEIRA, BOMBA_AGUA_LIGADA],
+    }
+}
+
+enum Step {
+    Set(String, f64),
+    Clear,
+    Wait(Duration),
+}
+
+enum Scenario {
+    Phases { step: Duration },
+    Bits { step: Duration },
+    Counter,
+    Script { steps: Vec<Step>, next: usize, resume_at: Instant },
+}
+
+impl Scenario {
+    /// Atualiza o frame para o instante `elapsed`; devolve uma descrição
+    /// quando o estado muda de forma relevante (para log)
+    fn update(&mut self, frame: &mut Frame, elapsed: Duration) -> Result<Option<String>, String> {
+        match self {
+            Scenario::Phases { step } => {
+                let slot = (elapsed.as_millis() / step.as_millis().max(1)) as usize;
+                let phase = slot % 6 + 1;
+                let in_phase = (elapsed.as_secs_f64() % step.as_secs_f64()) / step.as_secs_f64();
+
+                let before = frame.data.clone();
+                for word in 0..4 {
+                    frame.set("Word", word, 0.0)?;
+                }
+                for &(word, bit) in phase_bits(phase) {
+                    frame.set_bit("Word", word, bit, true)?;
+                }
+                // Word[10]: semáforo para usePlcData (1 = verde)
+                frame.set("Word", 10, if matches!(phase, 3 | 4) { 1.0 } else { 0.0 })?;
+
+                // Nível (Int[0], cm) sobe durante o ciclo automático
+                let level = if phase == 6 { 200.0 + 300.0 * in_phase } else if phase == 1 { 200.0 } else { 500.0 };
+                frame.set("Int", 0, level)?;
+                if phase == 6 && in_phase > 0.8 {
+                    frame.set_bit("Word", NIVEL_AGUA_ALTO.0, NIVEL_AGUA_ALTO.1, true)?;
+                }
+                frame.set("Real", 10, 18.0 + (elapsed.as_secs_f64() / 30.0).sin())?;
+                frame.set("Real", 20, if phase == 6 { 12.5 } else { 0.0 })?;
+
+                let changed = frame.data[..8] != before[..8];
+                Ok(changed.then(|| format!("Fase {}", phase)))
+            }
+            Scenario::Bits { step } => {
+                let slot = (elapsed.as_millis() / step.as_millis().max(1)) as usize;
+                let (word, bit, name) = DEFAULT_BITS[slot % DEFAULT_BITS.len()];
+                let before = frame.data.clone();
+                for w in 0..4 {
+                    frame.set("Word", w, 0.0)?;
+                }
+                frame.set_bit("Word", word, bit, true)?;
+                let changed = frame.data[..8] != before[..8];
+                Ok(changed.then(|| format!("Word[{}].{} {}", word, bit, name)))
+            }
+            Scenario::Counter => {
+                let t = elapsed.as_secs_f64();
+                let segments: Vec<(String, usize)> = frame.layout.segments.iter()
+                    .map(|s| (s.name.clone(), s.count))
+                    .collect();
+                for (name, count) in segments {
+                    for i in 0..count {
+                        let value = match frame.locate(&name, i)?.1 {
+                            SegmentType::Word | SegmentType::UDInt => ((t * 2.0) as u64 + i as u64) as f64 % 65536.0,
+                            SegmentType::Int | SegmentType::DInt => ((t + i as f64) * 10.0).sin() * 1000.0,
+                            SegmentType::Real | SegmentType::LReal => i as f64 * 0.5 + (t + i as f64).sin() * 10.0,
+                            SegmentType::Bool => ((t as u64 + i as u64) % 2) as f64,
+                            SegmentType::Time => t * 1000.0,
+                            SegmentType::String | SegmentType::Dtl => continue,
+                        };
+                        frame.set(&name, i, value)?;
+                    }
+                }
+                Ok(None)
+            }
+            Scenario::Script { steps, next, resume_at } => {
+                let now = Instant::now();
+                let mut log = Vec::new();
+                while now >= *resume_at {
+                    if *next >= steps.len() {
+                        *next = 0; // Repetir
+                    }
+                    match &steps[*next] {
+                        Step::Set(target, value) => {
+                            frame.assign(target, *value)?;
+                            log.push(format!("set {} {}", target, value));
+                        }
+                        Step::Clear => {
+                            frame.clear();
+                            log.push("clear".to_string());
+                        }
+                        Step::Wait(d) => *resume_at = now + *d,
+                    }
+                    *next += 1;
+                }
+                Ok((!log.is_empty()).then(|| log.join("; ")))
+            }
+        }
+    }
+}
+
+fn parse_script(text: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg: &str| format!("Linha {}: {} ({})", n + 1, msg, line);
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let step = match parts.as_slice() {
+            ["set", target, value] => {
+                parse_target(target).map_err(|e| err(&e))?;
+                Step::Set(target.to_string(), value.parse().map_err(|_| err("valor inválido"))?)
+            }
+            ["clear"] => Step::Clear,
+            ["wait", secs] => {
+                let secs: f64 = secs.parse().map_err(|_| err("segundos inválidos"))?;
+                if !secs.is_finite() || secs < 0.0 {
+                    return Err(err("segundos inválidos"));
+                }
+                Step::Wait(Duration::from_secs_f64(secs))
+            }
+            _ => return Err(err("instrução desconhecida")),
+        };
+        steps.push(step);
+    }
+    if !steps.iter().any(|s| matches!(s, Step::Wait(d) if !d.is_zero())) {
+        return Err("Script precisa de pelo menos um 'wait' > 0".to_string());
+    }
+    Ok(steps)
+}
+
+// ============================================================================
+// MAIN
+// ============================================================================
+
+struct Options {
+    host: String,
+    port: u16,
+    rate: f64,
+    scenario: String,
+    step_secs: f64,
+    script: Option<String>,
+    layout: Option<String>,
+    framed: bool,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        host: "127.0.0.1".to_string(),
+        port: 8502,
+        rate: 2.0,
+        scenario: "phases".to_string(),
+        step_secs: 5.0,
+        script: None,
+        layout: None,
+        framed: false,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Falta valor para {}", arg));
+        match arg.as_str() {
+            "--host" => options.host = value()?,
+            "--port" => options.port = value()?.parse().map_err(|_| "Porta inválida")?,
+            "--rate" => options.rate = value()?.parse().map_err(|_| "Frequência inválida")?,
+            "--scenario" => options.scenario = value()?,
+            "--step-secs" => options.step_secs = value()?.parse().map_err(|_| "Duração inválida")?,
+            "--script" => options.script = Some(value()?),
+            "--layout" => options.layout = Some(value()?),
+            "--framed" => options.framed = true,
+            "--help" | "-h" => {
+                println!("Uso: plc_simulator [--host H] [--port P] [--rate HZ] [--scenario phases|bits|counter] \
+                          [--step-secs S] [--script FICHEIRO] [--layout FICHEIRO.json] [--framed]");
+                std::process::exit(0);
+            }
+            other => return Err(format!("Opção desconhecida: {}", other)),
+        }
+    }
+
+    if !(options.rate > 0.0 && options.rate <= 1000.0) {
+        return Err("Frequência deve estar entre 0 e 1000 Hz".to_string());
+    }
+    if !(options.step_secs > 0.0 && options.step_secs.is_finite()) {
+        return Err("--step-secs deve ser > 0".to_string());
+    }
+    Ok(options)
+}
+
+fn build_scenario(options: &Options) -> Result<Scenario, String> {
+    let step = Duration::from_secs_f64(options.step_secs);
+    if let Some(path) = &options.script {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        return Ok(Scenario::Script { steps: parse_script(&text)?, next: 0, resume_at: Instant::now() });
+    }
+    match options.scenario.as_str() {
+        "phases" => Ok(Scenario::Phases { step }),
+        "bits" => Ok(Scenario::Bits { step }),
+        "counter" => Ok(Scenario::Counter),
+        other => Err(format!("Cenário desconhecido: {} (phases | bits | counter)", other)),
+    }
+}
+
+fn load_layout(path: Option<&str>) -> Result<PacketLayout, String> {
+    let layout = match path {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            serde_json::from_str(&text).map_err(|e| format!("Layout inválido: {}", e))?
+        }
+        None => PacketLayout::default_udt(),
+    };
+    layout.validate()?;
+    Ok(layout)
+}
+
+#[tokio::main]
+async fn main() {
+    let setup = parse_args().and_then(|options| {
+        let layout = load_layout(options.layout.as_deref())?;
+        let scenario = build_scenario(&options)?;
+        Ok((options, layout, scenario))
+    });
+    let (options, layout, mut scenario) = match setup {
+        Ok(setup) => setup,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let address = format!("{}:{}", options.host, options.port);
+    println!("🧪 Simulador PLC -> {} | {} ({} bytes) @ {}Hz | cenário: {}{}",
+        address, layout.summary(), layout.packet_size, options.rate,
+        options.script.as_deref().unwrap_or(&options.scenario),
+        if options.framed { " | enquadrado (PLCF)" } else { "" });
+
+    let mut frame = Frame::new(layout);
+    let started = Instant::now();
+    let period = Duration::from_secs_f64(1.0 / options.rate);
+    let mut seq = 0u32;
+
+    loop {
+        let mut socket = match TcpStream::connect(&address).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("❌ Falha ao conectar {}: {} (nova tentativa em 2s)", address, e);
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                continue;
+            }
+        };
+        let _ = socket.set_nodelay(true);
+        println!("✅ Conectado a {}", address);
+
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut sent = 0u64;
+
+        loop {
+            interval.tick().await;
+
+            match scenario.update(&mut frame, started.elapsed()) {
+                Ok(Some(change)) => println!("🔀 {}", change),
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("❌ Erro no cenário: {}", e);
+                    std::process::exit(1);
+                }
+            }
+
+            let result = if options.framed {
+                seq = seq.wrapping_add(1);
+                socket.write_all(&framing::encode_frame(seq, &frame.data)).await
+            } else {
+                socket.write_all(&frame.data).await
+            };
+            if let Err(e) = result {
+                eprintln!("❌ Conexão perdida após {} frames: {}", sent, e);
+                break;
+            }
+            sent += 1;
+            if sent.is_multiple_of(100) {
+                println!("📤 {} frames enviados", sent);
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
@@ -0,0 +1,182 @@
+// s7_stub.rs - RESPONDEDOR S7 MÍNIMO PARA TESTES SEM HARDWARE
+// ============================================================================
+// Simula uma CPU S7 na porta ISO-on-TCP: aceita COTP CR, Setup Communication
+// e Read Var sobre um único DB. O conteúdo do DB segue o UDT_TCP_Data
+// (Word[65] + Int[65] + Real[257], 1288 bytes) e muda a cada segundo:
+//   Word[i] = i + t    Int[i] = -i    Real[i] = i * 0.5 + t
+//
+// Uso:
+//   cargo run --bin s7_stub
+//   Variáveis: S7_STUB_PORT (1102), S7_STUB_DB (1), S7_STUB_SIZE (1288),
+//              S7_STUB_PDU (480 - pequeno para obrigar a leituras em blocos)
+// Depois configurar no painel um PLC S7 em 127.0.0.1:1102, DB1.
+// ============================================================================
+
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+#[derive(Clone, Copy)]
+pub struct StubConfig {
+    pub db_number: u16,
+    pub db_size: usize,
+    pub pdu_size: u16,
+    pub started: Instant,
+}
+
+impl StubConfig {
+    /// Conteúdo atual do DB (big-endian, como no S7)
+    fn db_contents(&self) -> Vec<u8> {
+        let t = self.started.elapsed().as_secs();
+        let mut db = vec![0u8; self.db_size];
+        for i in 0..65usize {
+            let word = (i as u64 + t) as u16;
+            put(&mut db, i * 2, &word.to_be_bytes());
+            put(&mut db, 130 + i * 2, &(-(i as i16)).to_be_bytes());
+        }
+        for i in 0..257usize {
+            let real = i as f32 * 0.5 + t as f32;
+            put(&mut db, 260 + i * 4, &real.to_be_bytes());
+        }
+        db
+    }
+}
+
+fn put(db: &mut [u8], offset: usize, bytes: &[u8]) {
+    if offset + bytes.len() <= db.len() {
+        db[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let port: u16 = env_or("S7_STUB_PORT", 1102);
+    let config = StubConfig {
+        db_number: env_or("S7_STUB_DB", 1),
+        db_size: env_or("S7_STUB_SIZE", 1288),
+        pdu_size: env_or("S7_STUB_PDU", 480),
+        started: Instant::now(),
+    };
+
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    println!("🧪 S7 stub em 0.0.0.0:{} (DB{}, {} bytes, PDU {})",
+        port, config.db_number, config.db_size, config.pdu_size);
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        println!("🔗 Cliente S7 ligado: {}", addr);
+        tokio::spawn(async move {
+            match handle_client(socket, config).await {
+                Ok(()) => println!("👋 Cliente S7 desligado: {}", addr),
+                Err(e) => println!("❌ Cliente S7 {}: {}", addr, e),
+            }
+        });
+    }
+}
+
+pub async fn handle_client(mut socket: TcpStream, config: StubConfig) -> std::io::Result<()> {
+    let mut pdu_size = config.pdu_size;
+
+    loop {
+        // ── TPKT ──
+        let mut header = [0u8; 4];
+        if socket.read_exact(&mut header).await.is_err() {
+            return Ok(());
+        }
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        if header[0] != 0x03 || length < 7 {
+            return Err(std::io::Error::other("TPKT inválido"));
+        }
+        let mut payload = vec![0u8; length - 4];
+        socket.read_exact(&mut payload).await?;
+
+        match payload[1] {
+            // COTP Connection Request -> Connection Confirm
+            0xE0 => {
+                if payload.len() < 6 {
+                    return Err(std::io::Error::other("COTP CR truncado"));
+                }
+                let mut reply = payload.clone();
+                reply[1] = 0xD0;
+                reply[2..4].copy_from_slice(&payload[4..6]); // Referência destino = origem do pedido
+                reply[4..6].copy_from_slice(&[0x00, 0x01]);
+                send_tpkt(&mut socket, &reply).await?;
+            }
+            // COTP DT com S7comm
+            0xF0 => {
+                let s7 = &payload[3..];
+                if s7.len() < 10 || s7[0] != 0x32 || s7[1] != 0x01 {
+                    return Err(std::io::Error::other("Pedido S7 inválido"));
+                }
+                let pdu_ref = [s7[4], s7[5]];
+                let param_len = u16::from_be_bytes([s7[6], s7[7]]) as usize;
+                let Some(params) = s7.get(10..10 + param_len).filter(|p| !p.is_empty()) else {
+                    return Err(std::io::Error::other("Pedido S7 truncado"));
+                };
+
+                let (reply_params, reply_data) = match params[0] {
+                    0xF0 => {
+                        if params.len() < 8 {
+                            return Err(std::io::Error::other("Setup Communication truncado"));
+                        }
+                        let requested = u16::from_be_bytes([params[6], params[7]]);
+                        pdu_size = pdu_size.min(requested);
+                        let mut p = params[..6].to_vec();
+                        p.extend_from_slice(&pdu_size.to_be_bytes());
+                        (p, Vec::new())
+                    }
+                    0x04 => (vec![0x04, 0x01], read_var(params, &config)),
+                    other => {
+                        return Err(std::io::Error::other(format!("Função S7 não suportada: {:#04x}", other)));
+                    }
+                };
+
+                let mut reply = vec![0x02, 0xF0, 0x80, 0x32, 0x03, 0x00, 0x00, pdu_ref[0], pdu_ref[1]];
+                reply.extend_from_slice(&(reply_params.len() as u16).to_be_bytes());
+                reply.extend_from_slice(&(reply_data.len() as u16).to_be_bytes());
+                reply.extend_from_slice(&[0x00, 0x00]); // Sem erro
+                reply.extend_from_slice(&reply_params);
+                reply.extend_from_slice(&reply_data);
+                send_tpkt(&mut socket, &reply).await?;
+            }
+            other => {
+                return Err(std::io::Error::other(format!("PDU COTP não suportado: {:#04x}", other)));
+            }
+        }
+    }
+}
+
+/// Item de dados da resposta a um Read Var (um único item, área DB)
+fn read_var(params: &[u8], config: &StubConfig) -> Vec<u8> {
+    // Código 0x0A = objeto não existe, 0x05 = endereço fora do alcance
+    if params.len() < 14 || params[10] != 0x84 {
+        return vec![0x0A, 0x00, 0x00, 0x00];
+    }
+    let length = u16::from_be_bytes([params[6], params[7]]) as usize;
+    let db_number = u16::from_be_bytes([params[8], params[9]]);
+    let address = u32::from_be_bytes([0, params[11], params[12], params[13]]) as usize / 8;
+
+    if db_number != config.db_number {
+        return vec![0x0A, 0x00, 0x00, 0x00];
+    }
+    if address + length > config.db_size {
+        return vec![0x05, 0x00, 0x00, 0x00];
+    }
+
+    let db = config.db_contents();
+    let mut data = vec![0xFF, 0x04];
+    data.extend_from_slice(&((length * 8) as u16).to_be_bytes());
+    data.extend_from_slice(&db[address..address + length]);
+    data
+}
+
+async fn send_tpkt(socket: &mut TcpStream, payload: &[u8]) -> std::io::Result<()> {
+    let mut frame = vec![0x03, 0x00];
+    frame.extend_from_slice(&((4 + payload.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(payload);
+    socket.write_all(&frame).await
+}
@@ -0,0 +1,489 @@
+// capture.rs - GRAVAÇÃO E REPRODUÇÃO DE FRAMES PLC EM BRUTO
+// ============================================================================
+// Para reproduzir no escritório o que um painel recebeu em obra:
+//   - Gravação (opt-in): cada frame bruto recebido é escrito num ficheiro de
+//     captura com timestamp de receção e IP de origem. Os ficheiros rodam por
+//     tamanho e os mais antigos são apagados.
+//   - Reprodução: lê uma captura e volta a passar cada frame por
+//     parse_plc_packet + broadcast, à velocidade original ou acelerada.
+// ============================================================================
+// FORMATO (.plccap, big-endian):
+//   Cabeçalho: "PLCCAP01" (8 bytes)
+//   Registo:   timestamp µs (u64) | tamanho IP (u8) | IP | tamanho frame (u32) | frame
+// ============================================================================
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::packet_layout::MAX_PACKET_SIZE;
+use crate::tcp_server::TcpServer;
+
+const FILE_MAGIC: &[u8; 8] = b"PLCCAP01";
+const FILE_EXTENSION: &str = "plccap";
+const RECORD_QUEUE_SIZE: usize = 1024;
+
+/// Pausa mínima entre frames numa reprodução em ciclo (frames com o mesmo
+/// timestamp ou velocidade muito alta não podem pôr o loop a rodar em vazio)
+const MIN_REPEAT_FRAME_DELAY: Duration = Duration::from_millis(1);
+
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 50 * 1024 * 1024;
+pub const DEFAULT_MAX_FILES: usize = 20;
+
+/// Frame capturado (em memória)
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    pub timestamp_us: u64,
+    pub source_ip: String,
+    pub data: Vec<u8>,
+}
+
+/// Estado exposto pela API
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureStatus {
+    pub recording: bool,
+    pub directory: String,
+    pub current_file: Option<String>,
+    pub frames_written: u64,
+    pub frames_dropped: u64,
+    pub max_file_bytes: u64,
+    pub max_files: usize,
+    pub replaying: Option<String>,
+}
+
+/// Ficheiro de captura existente
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureFileInfo {
+    pub name: String,
+    pub size: u64,
+    pub modified: String,
+}
+
+struct Recorder {
+    tx: mpsc::Sender<CapturedFrame>,
+    task: JoinHandle<()>,
+    max_file_bytes: u64,
+    max_files: usize,
+}
+
+/// Gravador/reprodutor de capturas (um por servidor)
+pub struct FrameCapture {
+    dir: PathBuf,
+    recorder: Mutex<Option<Recorder>>,
+    current_file: Arc<Mutex<Option<String>>>,
+    frames_written: Arc<AtomicU64>,
+    frames_dropped: AtomicU64,
+    replay: Mutex<Option<(JoinHandle<()>, String)>>,
+}
+
+impl FrameCapture {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            recorder: Mutex::new(None),
+            current_file: Arc::new(Mutex::new(None)),
+            frames_written: Arc::new(AtomicU64::new(0)),
+            frames_dropped: AtomicU64::new(0),
+            replay: Mutex::new(None),
+        }
+    }
+
+    // ====================================================================
+    // GRAVAÇÃO
+    // ====================================================================
+
+    /// Inicia a gravação (reinicia se já estiver ativa)
+    pub fn start_recording(&self, max_file_bytes: u64, max_files: usize) -> Result<(), String> {
+        if max_file_bytes < MAX_PACKET_SIZE as u64 {
+            return Err(format!("Tamanho máximo do ficheiro deve ser ≥ {} bytes", MAX_PACKET_SIZE));
+        }
+        if max_files == 0 {
+            return Err("Número máximo de ficheiros deve ser ≥ 1".to_string());
+        }
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Erro ao criar {}: {}", self.dir.display(), e))?;
+
+        self.stop_recording();
+
+        let (tx, rx) = mpsc::channel(RECORD_QUEUE_SIZE);
+        let task = tokio::spawn(write_loop(
+            self.dir.clone(),
+            rx,
+            max_file_bytes,
+            max_files,
+            self.current_file.clone(),
+            self.frames_written.clone(),
+        ));
+
+        *self.recorder.lock().unwrap() = Some(Recorder { tx, task, max_file_bytes, max_files });
+        println!("⏺️ Gravação de frames iniciada em {} ({} MB x {} ficheiros)",
+            self.dir.display(), max_file_bytes / (1024 * 1024), max_files);
+        Ok(())
+    }
+
+    /// Pára a gravação (os frames já em fila são escritos antes de fechar)
+    pub fn stop_recording(&self) -> bool {
+        match self.recorder.lock().unwrap().take() {
+            Some(recorder) => {
+                // Ao largar o sender o write_loop esvazia a fila e termina
+                drop(recorder.tx);
+                drop(recorder.task);
+                println!("⏹️ Gravação de frames parada");
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Enfileira um frame para gravação (não bloqueia; descarta se a fila estiver cheia)
+    pub fn record(&self, source_ip: &str, data: &[u8]) {
+        let guard = self.recorder.lock().unwrap();
+        if let Some(recorder) = guard.as_ref() {
+            let frame = CapturedFrame {
+                timestamp_us: now_micros(),
+                source_ip: source_ip.to_string(),
+                data: data.to_vec(),
+            };
+            if recorder.tx.try_send(frame).is_err() {
+                self.frames_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn status(&self) -> CaptureStatus {
+        let recorder = self.recorder.lock().unwrap();
+        CaptureStatus {
+            recording: recorder.is_some(),
+            directory: self.dir.display().to_string(),
+            current_file: self.current_file.lock().unwrap().clone(),
+            frames_written: self.frames_written.load(Ordering::Relaxed),
+            frames_dropped: self.frames_dropped.load(Ordering::Relaxed),
+            max_file_bytes: recorder.as_ref().map(|r| r.max_file_bytes).unwrap_or(DEFAULT_MAX_FILE_BYTES),
+            max_files: recorder.as_ref().map(|r| r.max_files).unwrap_or(DEFAULT_MAX_FILES),
+            replaying: self.replay.lock().unwrap().as_ref()
+                .filter(|(handle, _)| !handle.is_finished())
+                .map(|(_, name)| name.clone()),
+        }
+    }
+
+    /// Lista as capturas existentes (mais recentes primeiro)
+    pub fn list_files(&self) -> Result<Vec<CaptureFileInfo>, String> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut files: Vec<CaptureFileInfo> = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().and_then(|x| x.to_str()) == Some(FILE_EXTENSION))
+            .filter_map(|e| {
+                let meta = e.metadata().ok()?;
+                let modified: chrono::DateTime<chrono::Local> = meta.modified().ok()?.into();
+                Some(CaptureFileInfo {
+                    name: e.file_name().to_string_lossy().to_string(),
+                    size: meta.len(),
+                    modified: modified.to_rfc3339(),
+                })
+            })
+            .collect();
+        files.sort_by(|a, b| b.name.cmp(&a.name));
+        Ok(files)
+    }
+
+    /// Resolve o nome de uma captura dentro do diretório (sem caminhos)
+    fn resolve_file(&self, name: &str) -> Result<PathBuf, String> {
+        let valid = !name.is_empty()
+            && !name.contains(['/', '\\'])
+            && !name.starts_with('.')
+            && Path::new(name).extension().and_then(|x| x.to_str()) == Some(FILE_EXTENSION);
+        if !valid {
+            return Err(format!("Nome de captura inválido: {}", name));
+        }
+        let path = self.dir.join(name);
+        if !path.is_file() {
+            return Err(format!("Captura não encontrada: {}", name));
+        }
+        Ok(path)
+    }
+
+    // ====================================================================
+    // REPRODUÇÃO
+    // ====================================================================
+
+    /// Reproduz uma captura pelo parser/broadcast do servidor.
+    /// `speed` = 1.0 tempo original, 10.0 = 10x mais rápido, 0 = sem pausas
+    /// (só sem `repeat`: em ciclo nunca terminaria).
+    pub async fn start_replay(&self, server: Arc<TcpServer>, name: &str, speed: f64, repeat: bool) -> Result<usize, String> {
+        if !speed.is_finite() || speed < 0.0 {
+            return Err(format!("Velocidade inválida: {}", speed));
+        }
+        if speed == 0.0 && repeat {
+            return Err("Velocidade 0 (sem pausas) não pode ser usada em ciclo".to_string());
+        }
+        let path = self.resolve_file(name)?;
+        let bytes = tokio::fs::read(&path).await.map_err(|e| e.to_string())?;
+        let frames = decode_capture(&bytes)?;
+        let count = frames.len();
+        if count == 0 {
+            return Err(format!("Captura vazia: {}", name));
+        }
+
+        self.stop_replay();
+
+        let label = name.to_string();
+        let handle = tokio::spawn(async move {
+            println!("▶️ Reprodução de '{}' iniciada ({} frames, {}x)", label, frames.len(), speed);
+            loop {
+                let mut previous: Option<u64> = None;
+                for frame in &frames {
+                    let mut delay = Duration::ZERO;
+                    if let Some(prev) = previous {
+                        let delta = frame.timestamp_us.saturating_sub(prev);
+                        if speed > 0.0 && delta > 0 {
+                            delay = Duration::from_micros((delta as f64 / speed) as u64);
+                        }
+                    }
+                    if repeat {
+                        delay = delay.max(MIN_REPEAT_FRAME_DELAY);
+                    }
+                    if delay > Duration::ZERO {
+                        tokio::time::sleep(delay).await;
+                    } else {
+                        // Sem pausa: deixar correr as outras tarefas do runtime
+                        tokio::task::yield_now().await;
+                    }
+                    previous = Some(frame.timestamp_us);
+
+                    if let Err(e) = server.replay_frame(&frame.source_ip, &frame.data).await {
+                        eprintln!("⚠️ Reprodução '{}': frame de {} ignorado: {}", label, frame.source_ip, e);
+                    }
+                }
+                if !repeat {
+                    break;
+                }
+            }
+            println!("⏏️ Reprodução de '{}' terminada", label);
+        });
+
+        *self.replay.lock().unwrap() = Some((handle, name.to_string()));
+        Ok(count)
+    }
+
+    pub fn stop_replay(&self) -> bool {
+        match self.replay.lock().unwrap().take() {
+            Some((handle, name)) => {
+                handle.abort();
+                println!("⏏️ Reprodução de '{}' parada", name);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64
+}
+
+// ============================================================================
+// FICHEIROS
+// ============================================================================
+
+/// Tarefa de escrita: consome a fila e roda os ficheiros por tamanho
+async fn write_loop(
+    dir: PathBuf,
+    mut rx: mpsc::Receiver<CapturedFrame>,
+    max_file_bytes: u64,
+    max_files: usize,
+    current_file: Arc<Mutex<Option<String>>>,
+    frames_written: Arc<AtomicU64>,
+) {
+    let mut writer: Option<(BufWriter<File>, u64)> = None;
+    let mut own_file: Option<String> = None;
+
+    while let Some(frame) = rx.recv().await {
+        let record = encode_record(&frame);
+
+        let needs_rotation = match &writer {
+            Some((_, size)) => size + record.len() as u64 > max_file_bytes,
+            None => true,
+        };
+        if needs_rotation {
+            if let Some((mut w, _)) = writer.take() {
+                let _ = w.flush().await;
+            }
+            match open_capture_file(&dir, max_files).await {
+                Ok((w, name)) => {
+                    *current_file.lock().unwrap() = Some(name.clone());
+                    own_file = Some(name);
+                    writer = Some((w, FILE_MAGIC.len() as u64));
+                }
+                Err(e) => {
+                    eprintln!("❌ Erro ao abrir ficheiro de captura: {}", e);
+                    continue;
+                }
+            }
+        }
+
+        if let Some((w, size)) = writer.as_mut() {
+            // Flush por frame: uma captura de obra tem de sobreviver a um crash
+            let result = async {
+                w.write_all(&record).await?;
+                w.flush().await
+            }.await;
+            match result {
+                Ok(()) => {
+                    *size += record.len() as u64;
+                    frames_written.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    eprintln!("❌ Erro ao escrever captura: {}", e);
+                    writer = None;
+                }
+            }
+        }
+    }
+
+    if let Some((mut w, _)) = writer {
+        let _ = w.flush().await;
+    }
+    // Uma nova gravação pode já ter aberto outro ficheiro
+    let mut current = current_file.lock().unwrap();
+    if *current == own_file {
+        *current = None;
+    }
+}
+
+/// Cria um novo ficheiro de captura e apaga os mais antigos acima do limite
+async fn open_capture_file(dir: &Path, max_files: usize) -> std::io::Result<(BufWriter<File>, String)> {
+    let stamp = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f").to_string();
+    // Nunca truncar uma captura existente: no mesmo milissegundo acrescenta "_N"
+    // (depois do "." na ordem alfabética, que continua a ser a cronológica)
+    let mut n = 1;
+    let (file, name) = loop {
+        let name = if n == 1 {
+            format!("capture_{}.{}", stamp, FILE_EXTENSION)
+        } else {
+            format!("capture_{}_{}.{}", stamp, n, FILE_EXTENSION)
+        };
+        match tokio::fs::OpenOptions::new().write(true).create_new(true).open(dir.join(&name)).await {
+            Ok(file) => break (file, name),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    };
+    let mut file = BufWriter::new(file);
+    file.write_all(FILE_MAGIC).await?;
+    file.flush().await?;
+
+    // Nomes com timestamp: ordem alfabética = ordem cronológica
+    let mut existing: Vec<PathBuf> = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|x| x.to_str()) == Some(FILE_EXTENSION) {
+            existing.push(path);
+        }
+    }
+    existing.sort();
+    while existing.len() > max_files {
+        let oldest = existing.remove(0);
+        if tokio::fs::remove_file(&oldest).await.is_ok() {
+            println!("🗑️ Captura antiga removida: {}", oldest.display());
+        }
+    }
+
+    println!("📼 Nova captura: {}", name);
+    Ok((file, name))
+}
+
+fn encode_record(frame: &CapturedFrame) -> Vec<u8> {
+    let ip = &frame.source_ip.as_bytes()[..frame.source_ip.len().min(u8::MAX as usize)];
+    let mut record = Vec::with_capacity(8 + 1 + ip.len() + 4 + frame.data.len());
+    record.extend_from_slice(&frame.timestamp_us.to_be_bytes());
+    record.push(ip.len() as u8);
+    record.extend_from_slice(ip);
+    record.extend_from_slice(&(frame.data.len() as u32).to_be_bytes());
+    record.extend_from_slice(&frame.data);
+    record
+}
+
+/// Descodifica um ficheiro de captura. Um registo final truncado
+/// (ex: crash a meio da escrita) é ignorado.
+fn decode_capture(bytes: &[u8]) -> Result<Vec<CapturedFrame>, String> {
+    if bytes.len() < FILE_MAGIC.len() || &bytes[..FILE_MAGIC.len()] != FILE_MAGIC {
+        return Err("Ficheiro não é uma captura PLC (cabeçalho inválido)".to_string());
+    }
+
+    let mut frames = Vec::new();
+    let mut pos = FILE_MAGIC.len();
+    while pos + 9 <= bytes.len() {
+        let timestamp_us = u64::from_be_bytes(bytes[pos..pos + 8].try_into().unwrap());
+        let ip_len = bytes[pos + 8] as usize;
+        let ip_end = pos + 9 + ip_len;
+        if ip_end + 4 > bytes.len() {
+            break;
+        }
+        let data_len = u32::from_be_bytes(bytes[ip_end..ip_end + 4].try_into().unwrap()) as usize;
+        if data_len > MAX_PACKET_SIZE {
+            return Err(format!("Registo corrompido no byte {} ({} bytes)", pos, data_len));
+        }
+        let data_end = ip_end + 4 + data_len;
+        if data_end > bytes.len() {
+            break;
+        }
+
+        frames.push(CapturedFrame {
+            timestamp_us,
+            source_ip: String::from_utf8_lossy(&bytes[pos + 9..ip_end]).to_string(),
+            data: bytes[ip_end + 4..data_end].to_vec(),
+        });
+        pos = data_end;
+    }
+
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(timestamp_us: u64, data: &[u8]) -> CapturedFrame {
+        CapturedFrame { timestamp_us, source_ip: "192.168.0.10".to_string(), data: data.to_vec() }
+    }
+
+    #[test]
+    fn round_trip_ignores_truncated_tail() {
+        let mut bytes = FILE_MAGIC.to_vec();
+        bytes.extend(encode_record(&frame(1, &[1, 2, 3])));
+        bytes.extend(encode_record(&frame(2, &[])));
+        let last = encode_record(&frame(3, &[4, 5, 6, 7]));
+        bytes.extend_from_slice(&last[..last.len() - 2]);
+
+        let frames = decode_capture(&bytes).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].timestamp_us, 1);
+        assert_eq!(frames[0].source_ip, "192.168.0.10");
+        assert_eq!(frames[0].data, vec![1, 2, 3]);
+        assert!(frames[1].data.is_empty());
+    }
+
+    #[test]
+    fn rejects_oversize_record_and_bad_magic() {
+        let mut bytes = FILE_MAGIC.to_vec();
+        bytes.extend(encode_record(&frame(1, &[])));
+        let len_pos = bytes.len() - 4;
+        bytes[len_pos..].copy_from_slice(&(MAX_PACKET_SIZE as u32 + 1).to_be_bytes());
+        assert!(decode_capture(&bytes).is_err());
+
+        assert!(decode_capture(b"PLCCAP02").is_err());
+        assert!(decode_capture(b"PLC").is_err());
+        assert!(decode_capture(FILE_MAGIC).unwrap().is_empty());
+    }
+}
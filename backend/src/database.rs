@@ -1,5 +1,11 @@
 ﻿use sqlx::{Pool, Sqlite, SqlitePool, Row};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::modbus_client::{ModbusDevice, ModbusRange, RegisterType};
+use crate::s7_client::S7Device;
+use crate::packet_layout::{ByteOrder, ClockTimezone, LayoutSegment, LayoutSignature, PacketLayout, SegmentType};
+use crate::playlist::{Playlist, PlaylistItem};
+use crate::proof_of_play::{self, AdGap, VideoPlay, PLAY_PLAYING};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TextConfig {
@@ -19,6 +25,7 @@ pub struct PhaseConfig {
     pub enabled: bool,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DisplayConfig {
     pub id: i64,
@@ -72,6 +79,144 @@ pub struct VideoConfig {
     pub display_order: i32,   // Ordem de exibiÃ§Ã£o
 }
 
+/// PLC registado no inventário (substitui a antiga tabela plc_names)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlcDevice {
+    #[serde(default)]
+    pub id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub location: String,
+    pub expected_ip: String,          // IP (TSEND_C) ou chave de sessão (modbus://..., s7://...)
+    #[serde(default)]
+    pub layout_name: Option<String>,  // Layout esperado (packet_layouts.name)
+    #[serde(default)]
+    pub expected_online: bool,        // Avisar se não ligar dentro do período de tolerância
+}
+
+/// Painel físico (ecrã montante, jusante, sala de controlo...) com o seu subconjunto
+/// de bits, vídeos e textos. Listas a None = todos os itens da configuração global.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Panel {
+    pub id: String,                      // Identificador em panel.html?panel=<id>
+    pub name: String,
+    #[serde(default = "default_panel_width")]
+    pub width: i32,                      // Resolução (px)
+    #[serde(default = "default_panel_height")]
+    pub height: i32,
+    #[serde(default)]
+    pub plc: Option<String>,             // PLC que alimenta o painel (IP, nome ou conn_id)
+    #[serde(default)]
+    pub idle_text_key: Option<String>,   // Texto mostrado sem bits ativos
+    #[serde(default)]
+    pub bit_ids: Option<Vec<i64>>,       // bit_configs.id
+    #[serde(default)]
+    pub video_ids: Option<Vec<i64>>,     // video_configs.id
+    #[serde(default)]
+    pub text_keys: Option<Vec<String>>,  // text_configs.key
+    #[serde(default)]
+    pub playlist_ids: Option<Vec<i64>>,  // playlists.id
+}
+
+fn default_panel_width() -> i32 { 1920 }
+fn default_panel_height() -> i32 { 1080 }
+
+/// Resolução máxima aceite por painel
+pub const MAX_PANEL_RESOLUTION: i32 = 16384;
+
+impl Panel {
+    pub fn validate(&self) -> Result<(), String> {
+        let id = self.id.trim();
+        if id.is_empty() || id.len() > 32 || !id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_') {
+            return Err(format!("Id de painel inválido: '{}' (a-z, 0-9, - e _, até 32 caracteres)", self.id));
+        }
+        if id == crate::display_engine::DEFAULT_PANEL_ID {
+            return Err(format!("Id de painel reservado: {}", id));
+        }
+        if self.name.trim().is_empty() {
+            return Err("Nome do painel não pode ser vazio".to_string());
+        }
+        for (label, value) in [("Largura", self.width), ("Altura", self.height)] {
+            if !(1..=MAX_PANEL_RESOLUTION).contains(&value) {
+                return Err(format!("{} do painel inválida: {} (1..{})", label, value, MAX_PANEL_RESOLUTION));
+            }
+        }
+        if let (Some(key), Some(keys)) = (&self.idle_text_key, &self.text_keys) {
+            if !keys.contains(key) {
+                return Err(format!("Texto de repouso {} não está nos textos do painel", key));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PlcDevice {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Nome do PLC não pode ser vazio".to_string());
+        }
+        let ip = self.expected_ip.trim();
+        let is_session_key = ip.starts_with("modbus://") || ip.starts_with("s7://");
+        if ip.parse::<std::net::IpAddr>().is_err() && !is_session_key {
+            return Err(format!("IP esperado inválido: {}", self.expected_ip));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockedPlc {
+    pub ip: String,
+    pub reason: String,
+    pub blocked_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllowlistEntry {
+    pub id: i64,
+    pub entry: String,        // IP ("192.168.1.33") ou rede CIDR ("192.168.1.0/24")
+    pub description: String,
+    pub created_at: String,
+}
+
+/// Sessão de comunicação de um PLC (uma linha por conexão)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlcSession {
+    pub id: i64,
+    pub ip: String,
+    pub plc_name: String,
+    pub conn_id: i64,
+    pub source: String,                   // "tsend", "modbus", "s7"
+    pub connected_at: String,             // RFC3339 UTC
+    pub last_seen_at: String,             // Atualizado periodicamente (recuperação após queda)
+    pub disconnected_at: Option<String>,  // None = sessão ainda aberta
+    pub end_reason: Option<String>,       // "normal", "timeout", "error", "watchdog", "forced", "replaced", "server_stopped", "interrupted"
+    pub bytes: i64,
+    pub packets: i64,
+}
+
+/// Disponibilidade de comunicação de um PLC num dia (hora local)
+#[derive(Debug, Clone, Serialize)]
+pub struct PlcAvailabilityDay {
+    pub date: String,                 // "YYYY-MM-DD"
+    pub ip: String,
+    pub plc_name: String,
+    pub connected_secs: i64,
+    pub period_secs: i64,             // 86400, ou o tempo decorrido no dia atual
+    pub availability_pct: f64,
+    pub sessions: i64,                // Sessões ativas em algum momento do dia
+    pub disconnects: i64,             // Sessões terminadas no dia por motivo anormal
+}
+
+/// Formato fixo (ms + 'Z') para que as comparações de texto no SQLite sejam cronológicas
+fn session_timestamp(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+fn parse_session_timestamp(value: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(value).ok().map(|t| t.with_timezone(&chrono::Utc))
+}
+
 pub struct Database {
     pool: Pool<Sqlite>,
 }
@@ -230,6 +375,316 @@ impl Database {
         .execute(&pool)
         .await?;
 
+        // Layout do pacote PLC (UDT) - segmentos tipados configuráveis
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS packet_layouts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL,
+                packet_size INTEGER NOT NULL,
+                active BOOLEAN NOT NULL DEFAULT 0,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS packet_layout_segments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                layout_id INTEGER NOT NULL REFERENCES packet_layouts(id) ON DELETE CASCADE,
+                seq INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                data_type TEXT NOT NULL,
+                count INTEGER NOT NULL,
+                offset INTEGER NOT NULL,
+                byte_order TEXT NOT NULL DEFAULT 'big',
+                UNIQUE(layout_id, seq)
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // Inventário de PLCs: nome, localização, IP e layout esperados
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS plc_devices (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL,
+                location TEXT NOT NULL DEFAULT '',
+                expected_ip TEXT UNIQUE NOT NULL,
+                layout_name TEXT,
+                expected_online BOOLEAN NOT NULL DEFAULT 0,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // Migração: plc_names (só nome por IP) -> plc_devices
+        let has_plc_names = sqlx::query("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'plc_names'")
+            .fetch_optional(&pool)
+            .await?
+            .is_some();
+        if has_plc_names {
+            sqlx::query(
+                r#"
+                INSERT OR IGNORE INTO plc_devices (name, expected_ip)
+                SELECT name, ip FROM plc_names
+                "#,
+            )
+            .execute(&pool)
+            .await?;
+            sqlx::query("DROP TABLE plc_names").execute(&pool).await?;
+            println!("🔄 Migração: plc_names -> plc_devices");
+        }
+
+        // Histórico de sessões de comunicação (relatórios de disponibilidade)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS plc_sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                ip TEXT NOT NULL,
+                plc_name TEXT NOT NULL DEFAULT '',
+                conn_id INTEGER NOT NULL,
+                source TEXT NOT NULL,
+                connected_at TEXT NOT NULL,
+                last_seen_at TEXT NOT NULL,
+                disconnected_at TEXT,
+                end_reason TEXT,
+                bytes INTEGER NOT NULL DEFAULT 0,
+                packets INTEGER NOT NULL DEFAULT 0
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_plc_sessions_connected_at ON plc_sessions (connected_at)")
+            .execute(&pool)
+            .await?;
+
+        // Equipamentos Modbus TCP (polling) e respetivas faixas de registos
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS modbus_devices (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL,
+                host TEXT NOT NULL,
+                port INTEGER NOT NULL DEFAULT 502,
+                unit_id INTEGER NOT NULL DEFAULT 1,
+                poll_interval_ms INTEGER NOT NULL DEFAULT 500,
+                enabled BOOLEAN NOT NULL DEFAULT 1,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS modbus_ranges (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                device_id INTEGER NOT NULL REFERENCES modbus_devices(id) ON DELETE CASCADE,
+                seq INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                register_type TEXT NOT NULL,
+                start_address INTEGER NOT NULL,
+                data_type TEXT NOT NULL,
+                count INTEGER NOT NULL,
+                byte_order TEXT NOT NULL DEFAULT 'big',
+                UNIQUE(device_id, seq)
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // PLCs bloqueados (disconnect_client) - sobrevivem a reinícios
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS plc_blacklist (
+                ip TEXT PRIMARY KEY,
+                reason TEXT NOT NULL DEFAULT '',
+                blocked_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // Allowlist: IPs/redes autorizados quando o modo allowlist está ativo
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS plc_allowlist (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                entry TEXT UNIQUE NOT NULL,
+                description TEXT NOT NULL DEFAULT '',
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // PLCs S7 consultados em polling (ISO-on-TCP, leitura de DB)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS s7_devices (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL,
+                host TEXT NOT NULL,
+                port INTEGER NOT NULL DEFAULT 102,
+                rack INTEGER NOT NULL DEFAULT 0,
+                slot INTEGER NOT NULL DEFAULT 1,
+                db_number INTEGER NOT NULL,
+                start_byte INTEGER NOT NULL DEFAULT 0,
+                length INTEGER NOT NULL DEFAULT 0,
+                poll_interval_ms INTEGER NOT NULL DEFAULT 500,
+                enabled BOOLEAN NOT NULL DEFAULT 1,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // Banda morta por tag no stream SSE em modo delta ("Real[3]" ou segmento inteiro "Real")
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS sse_deadbands (
+                tag TEXT PRIMARY KEY,
+                deadband REAL NOT NULL
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // Painéis (montante, jusante, sala de controlo) e os seus itens
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS panels (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                width INTEGER NOT NULL DEFAULT 1920,
+                height INTEGER NOT NULL DEFAULT 1080,
+                plc TEXT,
+                idle_text_key TEXT,
+                filter_bits BOOLEAN NOT NULL DEFAULT 0,
+                filter_videos BOOLEAN NOT NULL DEFAULT 0,
+                filter_texts BOOLEAN NOT NULL DEFAULT 0,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // kind: 'bit' (bit_configs.id), 'video' (video_configs.id), 'text' (text_configs.key)
+        // ou 'playlist' (playlists.id)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS panel_items (
+                panel_id TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                item TEXT NOT NULL,
+                PRIMARY KEY (panel_id, kind, item),
+                FOREIGN KEY (panel_id) REFERENCES panels(id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // Playlists de publicidade com validade, dias da semana e janela horária
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS playlists (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL,
+                enabled BOOLEAN NOT NULL DEFAULT 1,
+                priority INTEGER NOT NULL DEFAULT 0,
+                valid_from TEXT,
+                valid_until TEXT,
+                days TEXT NOT NULL DEFAULT '',
+                start_time TEXT,
+                end_time TEXT,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS playlist_items (
+                playlist_id INTEGER NOT NULL,
+                position INTEGER NOT NULL,
+                video_id INTEGER NOT NULL,
+                weight INTEGER NOT NULL DEFAULT 1,
+                PRIMARY KEY (playlist_id, position),
+                FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+
+        // Registo de exibição (proof of play): instantes UTC do relógio do servidor
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS video_plays (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                panel TEXT NOT NULL,
+                video_id INTEGER NOT NULL,
+                video_name TEXT NOT NULL,
+                playlist_id INTEGER,
+                source TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'playing',
+                reason TEXT NOT NULL DEFAULT '',
+                started_at TEXT NOT NULL,
+                ended_at TEXT,
+                seconds REAL
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_video_plays_started ON video_plays(started_at)")
+            .execute(&pool)
+            .await?;
+
+        // Períodos com a publicidade suprimida pelo bit de controle de vídeo
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS ad_gaps (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                panel TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                ended_at TEXT NOT NULL,
+                seconds REAL NOT NULL
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_ad_gaps_started ON ad_gaps(started_at)")
+            .execute(&pool)
+            .await?;
+
         // Inserir dados padrão para as fases da eclusa
         let db = Database { pool };
         
@@ -249,11 +704,61 @@ impl Database {
             .execute(&db.pool)
             .await
             .ok(); // Ignora erro se coluna já existe
+
+        // Migração: assinatura (campo de versão) dos layouts de pacote
+        sqlx::query("ALTER TABLE packet_layouts ADD COLUMN signature_offset INTEGER")
+            .execute(&db.pool)
+            .await
+            .ok(); // Ignora erro se coluna já existe
+
+        sqlx::query("ALTER TABLE packet_layouts ADD COLUMN signature_value INTEGER")
+            .execute(&db.pool)
+            .await
+            .ok(); // Ignora erro se coluna já existe
+
+        // Migração: segmento Dtl com o carimbo temporal do PLC
+        sqlx::query("ALTER TABLE packet_layouts ADD COLUMN timestamp_segment TEXT")
+            .execute(&db.pool)
+            .await
+            .ok(); // Ignora erro se coluna já existe
+
+        // Migração: fuso do relógio do PLC (DTL sem fuso)
+        sqlx::query("ALTER TABLE packet_layouts ADD COLUMN clock_timezone TEXT NOT NULL DEFAULT 'utc'")
+            .execute(&db.pool)
+            .await
+            .ok(); // Ignora erro se coluna já existe
+
+        // Migração: comprimento máximo dos segmentos String (S7 STRING[n])
+        sqlx::query("ALTER TABLE packet_layout_segments ADD COLUMN max_length INTEGER")
+            .execute(&db.pool)
+            .await
+            .ok(); // Ignora erro se coluna já existe
+
+        // Migração: faixa válida e valor de substituição (qualidade das variáveis)
+        sqlx::query("ALTER TABLE packet_layout_segments ADD COLUMN valid_min REAL")
+            .execute(&db.pool)
+            .await
+            .ok(); // Ignora erro se coluna já existe
+        sqlx::query("ALTER TABLE packet_layout_segments ADD COLUMN valid_max REAL")
+            .execute(&db.pool)
+            .await
+            .ok(); // Ignora erro se coluna já existe
+        sqlx::query("ALTER TABLE packet_layout_segments ADD COLUMN substitute REAL")
+            .execute(&db.pool)
+            .await
+            .ok(); // Ignora erro se coluna já existe
+
+        // Migração: subconjunto de playlists por painel
+        sqlx::query("ALTER TABLE panels ADD COLUMN filter_playlists BOOLEAN NOT NULL DEFAULT 0")
+            .execute(&db.pool)
+            .await
+            .ok(); // Ignora erro se coluna já existe
         
         db.insert_default_phases().await?;
         db.insert_default_texts().await?;
         db.insert_default_display_configs().await?;
         db.insert_default_bit_configs().await?;
+        db.insert_default_packet_layout().await?;
         // NÃO inserir vídeos de exemplo - usuário quer começar vazio
         // db.insert_default_video_configs().await?;
 
@@ -389,11 +894,25 @@ impl Database {
         Ok(())
     }
 
-    async fn insert_default_video_configs(&self) -> Result<(), sqlx::Error> {
-        let videos = vec![
+    async fn insert_default_packet_layout(&self) -> Result<(), sqlx::Error> {
+        let count = sqlx::query("SELECT COUNT(*) as total FROM packet_layouts")
+            .fetch_one(&self.pool)
+            .await?
+            .get::<i64, _>("total");
+
+        if count == 0 {
+            self.save_packet_layout(&PacketLayout::default_udt(), true).await?;
+        }
+
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    async fn insert_default_video_configs(&self) -> Result<(), sqlx::Error> {
+        let videos = vec![
             ("Publicidade EDP Verde", "videos/edp_verde.mp4", 30, true, 10, "Energia renovÃ¡vel e sustentÃ¡vel da EDP"),
             ("SeguranÃ§a NavegaÃ§Ã£o", "videos/seguranca.mp4", 25, true, 20, "InstruÃ§Ãµes de seguranÃ§a para navegaÃ§Ã£o na eclusa"),
-            ("Turismo RÃ©gua", "videos/turismo_regua.mp4", 45, true, 5, "PromoÃ§Ã£o turÃ­stica da regiÃ£o de RÃ©gua"),
+            ("Turismo RÃ©gua", "videos/turismo_regua.mp4", 45, true, 5, "PromoÃ§Ã£o turÃ\u{AD}stica da regiÃ£o de RÃ©gua"),
         ];
 
         for (name, file_path, duration, enabled, priority, description) in videos {
@@ -522,6 +1041,7 @@ impl Database {
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub async fn get_all_display_configs(&self) -> Result<Vec<DisplayConfig>, sqlx::Error> {
         let rows = sqlx::query("SELECT id, key, value, data_type FROM display_configs ORDER BY key")
             .fetch_all(&self.pool)
@@ -594,6 +1114,7 @@ impl Database {
         }))
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn add_bit_config(&self, word_index: i32, bit_index: i32, name: &str, message: &str, message_off: &str, enabled: bool, priority: i32, color: &str, font_size: i32, position: &str, font_family: &str, font_weight: &str, text_shadow: bool, letter_spacing: i32, use_template: bool, message_template: &str, action_type: &str, video_id: Option<i64>) -> Result<i64, sqlx::Error> {
         let result = sqlx::query(
             r#"
@@ -625,6 +1146,7 @@ impl Database {
         Ok(result.last_insert_rowid())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_bit_config(&self, word_index: i32, bit_index: i32, name: &str, message: &str, message_off: &str, enabled: bool, priority: i32, color: &str, font_size: i32, position: &str, font_family: &str, font_weight: &str, text_shadow: bool, letter_spacing: i32, use_template: bool, message_template: &str, action_type: &str, video_id: Option<i64>) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
@@ -663,32 +1185,11 @@ impl Database {
             .bind(bit_index)
             .execute(&self.pool)
             .await?;
+        self.prune_panel_items().await?;
         
         Ok(())
     }
 
-    // MÃ©todo para processar dados PLC e retornar mensagens ativas baseadas nos bits
-    pub async fn process_plc_bits(&self, word_data: &[u16]) -> Result<Vec<(BitConfig, bool)>, sqlx::Error> {
-        let bit_configs = self.get_all_bit_configs().await?;
-        let mut active_bits = Vec::new();
-
-        for bit_config in bit_configs {
-            if !bit_config.enabled || bit_config.word_index < 0 || bit_config.word_index >= word_data.len() as i32 {
-                continue;
-            }
-
-            let word_value = word_data[bit_config.word_index as usize];
-            let bit_value = (word_value >> bit_config.bit_index) & 1 == 1;
-            
-            active_bits.push((bit_config, bit_value));
-        }
-
-        // Ordenar por prioridade (maior prioridade primeiro)
-        active_bits.sort_by(|a, b| b.0.priority.cmp(&a.0.priority));
-        
-        Ok(active_bits)
-    }
-
     // MÃ©todos para gerenciar vÃ­deos
     pub async fn get_all_videos(&self) -> Result<Vec<VideoConfig>, sqlx::Error> {
         let rows = sqlx::query("SELECT id, name, file_path, duration, enabled, priority, description, COALESCE(display_order, 0) as display_order FROM video_configs ORDER BY display_order, priority DESC, name")
@@ -756,6 +1257,7 @@ impl Database {
         Ok(id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_video(&self, id: i64, name: &str, file_path: &str, duration: i32, enabled: bool, priority: i32, description: &str, display_order: i32) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
@@ -778,11 +1280,22 @@ impl Database {
         Ok(())
     }
 
+    /// Só o caminho (migração para caminhos relativos à biblioteca)
+    pub async fn update_video_file_path(&self, id: i64, file_path: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE video_configs SET file_path = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(file_path)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     pub async fn delete_video(&self, id: i64) -> Result<(), sqlx::Error> {
         sqlx::query("DELETE FROM video_configs WHERE id = ?")
             .bind(id)
             .execute(&self.pool)
             .await?;
+        self.prune_panel_items().await?;
         
         Ok(())
     }
@@ -816,6 +1329,7 @@ impl Database {
         sqlx::query("DELETE FROM video_configs")
             .execute(&self.pool)
             .await?;
+        self.prune_panel_items().await?;
         Ok(())
     }
 
@@ -838,36 +1352,1110 @@ impl Database {
         Ok(())
     }
 
-    // Função para verificar se os vídeos devem ser exibidos baseado no bit PLC
-    pub async fn should_show_videos(&self, plc_data: &[u16]) -> Result<bool, sqlx::Error> {
-        // Obter configurações do bit de controle
+    /// Bit de controle dos vídeos (word, bit): a 1 no PLC o painel roda os vídeos
+    pub async fn get_video_control_bit(&self) -> Result<(i32, i32), sqlx::Error> {
         let word_index = self.get_display_config("video_control_word_index").await?
             .and_then(|v| v.parse::<i32>().ok())
             .unwrap_or(5); // Default: Word[5]
-            
         let bit_index = self.get_display_config("video_control_bit_index").await?
             .and_then(|v| v.parse::<i32>().ok())
             .unwrap_or(3); // Default: Bit 3
-        
-        // Verificar se o índice da word é válido
-        if word_index < 0 || word_index as usize >= plc_data.len() {
-            return Ok(false);
+        Ok((word_index, bit_index))
+    }
+
+    // ===== LAYOUT DO PACOTE PLC =====
+    /// Layout ativo = layout por omissão (quando a conexão não é identificada)
+    pub async fn get_active_packet_layout(&self) -> Result<Option<PacketLayout>, sqlx::Error> {
+        let row = sqlx::query("SELECT * FROM packet_layouts WHERE active = 1 ORDER BY id LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(self.load_packet_layout(&row).await?)),
+            None => Ok(None),
         }
-        
-        // Obter valor do bit
-        let word_value = plc_data[word_index as usize];
-        let bit_value = (word_value >> bit_index) & 1 == 1;
-        
-        Ok(bit_value)
     }
 
-    // Função para obter vídeos habilitados para exibição
-    pub async fn get_videos_for_display(&self, plc_data: &[u16]) -> Result<Vec<VideoConfig>, sqlx::Error> {
-        if self.should_show_videos(plc_data).await? {
-            self.get_enabled_videos().await
+    /// Todos os layouts registados (o servidor TCP escolhe um por conexão)
+    pub async fn get_packet_layouts(&self) -> Result<Vec<PacketLayout>, sqlx::Error> {
+        let rows = sqlx::query("SELECT * FROM packet_layouts ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut layouts = Vec::with_capacity(rows.len());
+        for row in &rows {
+            layouts.push(self.load_packet_layout(row).await?);
+        }
+        Ok(layouts)
+    }
+
+    async fn load_packet_layout(&self, row: &sqlx::sqlite::SqliteRow) -> Result<PacketLayout, sqlx::Error> {
+        let layout_id: i64 = row.get("id");
+        let segment_rows = sqlx::query("SELECT name, data_type, count, offset, byte_order, max_length, valid_min, valid_max, substitute FROM packet_layout_segments WHERE layout_id = ? ORDER BY seq")
+            .bind(layout_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut segments = Vec::with_capacity(segment_rows.len());
+        for r in segment_rows {
+            let data_type: String = r.get("data_type");
+            let byte_order: String = r.get("byte_order");
+            segments.push(LayoutSegment {
+                name: r.get("name"),
+                data_type: SegmentType::parse(&data_type)
+                    .ok_or_else(|| sqlx::Error::Decode(format!("Tipo de segmento inválido: {}", data_type).into()))?,
+                count: r.get::<i64, _>("count") as usize,
+                offset: r.get::<i64, _>("offset") as usize,
+                byte_order: ByteOrder::parse(&byte_order)
+                    .ok_or_else(|| sqlx::Error::Decode(format!("Ordem de bytes inválida: {}", byte_order).into()))?,
+                max_length: r.get::<Option<i64>, _>("max_length").map(|n| n as usize),
+                valid_min: r.get("valid_min"),
+                valid_max: r.get("valid_max"),
+                substitute: r.get("substitute"),
+            });
+        }
+
+        let signature_offset: Option<i64> = row.get("signature_offset");
+        let signature_value: Option<i64> = row.get("signature_value");
+        let signature = match (signature_offset, signature_value) {
+            (Some(offset), Some(value)) => Some(LayoutSignature { offset: offset as usize, value: value as u16 }),
+            _ => None,
+        };
+
+        let clock_timezone: String = row.get("clock_timezone");
+        let clock_timezone = ClockTimezone::parse(&clock_timezone)
+            .ok_or_else(|| sqlx::Error::Decode(format!("Fuso do relógio inválido: {}", clock_timezone).into()))?;
+
+        Ok(PacketLayout {
+            name: row.get("name"),
+            packet_size: row.get::<i64, _>("packet_size") as usize,
+            segments,
+            signature,
+            timestamp_segment: row.get("timestamp_segment"),
+            clock_timezone,
+        })
+    }
+
+    /// Grava o layout (substitui segmentos se já existir).
+    /// Com `activate` passa a ser o layout por omissão.
+    pub async fn save_packet_layout(&self, layout: &PacketLayout, activate: bool) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO packet_layouts (name, packet_size, active, signature_offset, signature_value, timestamp_segment, clock_timezone)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(name) DO UPDATE SET
+                packet_size = excluded.packet_size,
+                active = MAX(active, excluded.active),
+                signature_offset = excluded.signature_offset,
+                signature_value = excluded.signature_value,
+                timestamp_segment = excluded.timestamp_segment,
+                clock_timezone = excluded.clock_timezone,
+                updated_at = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(&layout.name)
+        .bind(layout.packet_size as i64)
+        .bind(activate)
+        .bind(layout.signature.map(|s| s.offset as i64))
+        .bind(layout.signature.map(|s| s.value as i64))
+        .bind(&layout.timestamp_segment)
+        .bind(layout.clock_timezone.as_string())
+        .execute(&mut *tx)
+        .await?;
+
+        let layout_id = sqlx::query("SELECT id FROM packet_layouts WHERE name = ?")
+            .bind(&layout.name)
+            .fetch_one(&mut *tx)
+            .await?
+            .get::<i64, _>("id");
+
+        if activate {
+            sqlx::query("UPDATE packet_layouts SET active = 0 WHERE id != ?")
+                .bind(layout_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query("DELETE FROM packet_layout_segments WHERE layout_id = ?")
+            .bind(layout_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for (seq, segment) in layout.segments.iter().enumerate() {
+            sqlx::query(
+                r#"
+                INSERT INTO packet_layout_segments (layout_id, seq, name, data_type, count, offset, byte_order, max_length, valid_min, valid_max, substitute)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(layout_id)
+            .bind(seq as i64)
+            .bind(&segment.name)
+            .bind(segment.data_type.as_str())
+            .bind(segment.count as i64)
+            .bind(segment.offset as i64)
+            .bind(segment.byte_order.as_str())
+            .bind(segment.max_length.map(|n| n as i64))
+            .bind(segment.valid_min)
+            .bind(segment.valid_max)
+            .bind(segment.substitute)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(layout_id)
+    }
+
+    // ===== EQUIPAMENTOS MODBUS TCP =====
+    pub async fn get_modbus_devices(&self) -> Result<Vec<ModbusDevice>, sqlx::Error> {
+        let rows = sqlx::query("SELECT id, name, host, port, unit_id, poll_interval_ms, enabled FROM modbus_devices ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut devices = Vec::with_capacity(rows.len());
+        for row in rows {
+            let device_id: i64 = row.get("id");
+            let range_rows = sqlx::query("SELECT name, register_type, start_address, data_type, count, byte_order FROM modbus_ranges WHERE device_id = ? ORDER BY seq")
+                .bind(device_id)
+                .fetch_all(&self.pool)
+                .await?;
+
+            let mut ranges = Vec::with_capacity(range_rows.len());
+            for r in range_rows {
+                let register_type: String = r.get("register_type");
+                let data_type: String = r.get("data_type");
+                let byte_order: String = r.get("byte_order");
+                ranges.push(ModbusRange {
+                    name: r.get("name"),
+                    register_type: RegisterType::parse(&register_type)
+                        .ok_or_else(|| sqlx::Error::Decode(format!("Tipo de registo inválido: {}", register_type).into()))?,
+                    start_address: r.get::<i64, _>("start_address") as u16,
+                    data_type: SegmentType::parse(&data_type)
+                        .ok_or_else(|| sqlx::Error::Decode(format!("Tipo de segmento inválido: {}", data_type).into()))?,
+                    count: r.get::<i64, _>("count") as usize,
+                    byte_order: ByteOrder::parse(&byte_order)
+                        .ok_or_else(|| sqlx::Error::Decode(format!("Ordem de bytes inválida: {}", byte_order).into()))?,
+                });
+            }
+
+            devices.push(ModbusDevice {
+                id: device_id,
+                name: row.get("name"),
+                host: row.get("host"),
+                port: row.get::<i64, _>("port") as u16,
+                unit_id: row.get::<i64, _>("unit_id") as u8,
+                poll_interval_ms: row.get::<i64, _>("poll_interval_ms") as u64,
+                enabled: row.get::<i64, _>("enabled") != 0,
+                ranges,
+            });
+        }
+
+        Ok(devices)
+    }
+
+    /// Insere (id = 0) ou atualiza um equipamento Modbus, substituindo as faixas
+    pub async fn save_modbus_device(&self, device: &ModbusDevice) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let device_id = if device.id > 0 {
+            sqlx::query(
+                r#"
+                UPDATE modbus_devices
+                SET name = ?, host = ?, port = ?, unit_id = ?, poll_interval_ms = ?, enabled = ?, updated_at = CURRENT_TIMESTAMP
+                WHERE id = ?
+                "#,
+            )
+            .bind(&device.name)
+            .bind(&device.host)
+            .bind(device.port as i64)
+            .bind(device.unit_id as i64)
+            .bind(device.poll_interval_ms as i64)
+            .bind(device.enabled as i64)
+            .bind(device.id)
+            .execute(&mut *tx)
+            .await?;
+            device.id
         } else {
-            Ok(Vec::new())
+            sqlx::query(
+                r#"
+                INSERT INTO modbus_devices (name, host, port, unit_id, poll_interval_ms, enabled)
+                VALUES (?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&device.name)
+            .bind(&device.host)
+            .bind(device.port as i64)
+            .bind(device.unit_id as i64)
+            .bind(device.poll_interval_ms as i64)
+            .bind(device.enabled as i64)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_rowid()
+        };
+
+        sqlx::query("DELETE FROM modbus_ranges WHERE device_id = ?")
+            .bind(device_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for (seq, range) in device.ranges.iter().enumerate() {
+            sqlx::query(
+                r#"
+                INSERT INTO modbus_ranges (device_id, seq, name, register_type, start_address, data_type, count, byte_order)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(device_id)
+            .bind(seq as i64)
+            .bind(&range.name)
+            .bind(range.register_type.as_str())
+            .bind(range.start_address as i64)
+            .bind(range.data_type.as_str())
+            .bind(range.count as i64)
+            .bind(range.byte_order.as_str())
+            .execute(&mut *tx)
+            .await?;
         }
+
+        tx.commit().await?;
+        Ok(device_id)
+    }
+
+    pub async fn delete_modbus_device(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM modbus_ranges WHERE device_id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM modbus_devices WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // ===== PLCs S7 (ISO-on-TCP) =====
+    pub async fn get_s7_devices(&self) -> Result<Vec<S7Device>, sqlx::Error> {
+        let rows = sqlx::query("SELECT id, name, host, port, rack, slot, db_number, start_byte, length, poll_interval_ms, enabled FROM s7_devices ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|row| S7Device {
+            id: row.get("id"),
+            name: row.get("name"),
+            host: row.get("host"),
+            port: row.get::<i64, _>("port") as u16,
+            rack: row.get::<i64, _>("rack") as u8,
+            slot: row.get::<i64, _>("slot") as u8,
+            db_number: row.get::<i64, _>("db_number") as u16,
+            start_byte: row.get::<i64, _>("start_byte") as u32,
+            length: row.get::<i64, _>("length") as usize,
+            poll_interval_ms: row.get::<i64, _>("poll_interval_ms") as u64,
+            enabled: row.get::<i64, _>("enabled") != 0,
+        }).collect())
+    }
+
+    /// Insere (id = 0) ou atualiza um PLC S7
+    pub async fn save_s7_device(&self, device: &S7Device) -> Result<i64, sqlx::Error> {
+        if device.id > 0 {
+            sqlx::query(
+                r#"
+                UPDATE s7_devices
+                SET name = ?, host = ?, port = ?, rack = ?, slot = ?, db_number = ?, start_byte = ?, length = ?,
+                    poll_interval_ms = ?, enabled = ?, updated_at = CURRENT_TIMESTAMP
+                WHERE id = ?
+                "#,
+            )
+            .bind(&device.name)
+            .bind(&device.host)
+            .bind(device.port as i64)
+            .bind(device.rack as i64)
+            .bind(device.slot as i64)
+            .bind(device.db_number as i64)
+            .bind(device.start_byte as i64)
+            .bind(device.length as i64)
+            .bind(device.poll_interval_ms as i64)
+            .bind(device.enabled as i64)
+            .bind(device.id)
+            .execute(&self.pool)
+            .await?;
+            Ok(device.id)
+        } else {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO s7_devices (name, host, port, rack, slot, db_number, start_byte, length, poll_interval_ms, enabled)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&device.name)
+            .bind(&device.host)
+            .bind(device.port as i64)
+            .bind(device.rack as i64)
+            .bind(device.slot as i64)
+            .bind(device.db_number as i64)
+            .bind(device.start_byte as i64)
+            .bind(device.length as i64)
+            .bind(device.poll_interval_ms as i64)
+            .bind(device.enabled as i64)
+            .execute(&self.pool)
+            .await?;
+            Ok(result.last_insert_rowid())
+        }
+    }
+
+    pub async fn delete_s7_device(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM s7_devices WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // ===== INVENTÁRIO DE PLCs =====
+    pub async fn get_plc_devices(&self) -> Result<Vec<PlcDevice>, sqlx::Error> {
+        let rows = sqlx::query("SELECT id, name, location, expected_ip, layout_name, expected_online FROM plc_devices ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|row| PlcDevice {
+            id: row.get("id"),
+            name: row.get("name"),
+            location: row.get("location"),
+            expected_ip: row.get("expected_ip"),
+            layout_name: row.get("layout_name"),
+            expected_online: row.get::<i64, _>("expected_online") != 0,
+        }).collect())
+    }
+
+    /// Insere (id = 0) ou atualiza um PLC do inventário
+    pub async fn save_plc_device(&self, device: &PlcDevice) -> Result<i64, sqlx::Error> {
+        let layout_name = device.layout_name.as_deref().map(str::trim).filter(|n| !n.is_empty());
+
+        if device.id > 0 {
+            sqlx::query(
+                r#"
+                UPDATE plc_devices
+                SET name = ?, location = ?, expected_ip = ?, layout_name = ?, expected_online = ?, updated_at = CURRENT_TIMESTAMP
+                WHERE id = ?
+                "#,
+            )
+            .bind(device.name.trim())
+            .bind(&device.location)
+            .bind(device.expected_ip.trim())
+            .bind(layout_name)
+            .bind(device.expected_online as i64)
+            .bind(device.id)
+            .execute(&self.pool)
+            .await?;
+            Ok(device.id)
+        } else {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO plc_devices (name, location, expected_ip, layout_name, expected_online)
+                VALUES (?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(device.name.trim())
+            .bind(&device.location)
+            .bind(device.expected_ip.trim())
+            .bind(layout_name)
+            .bind(device.expected_online as i64)
+            .execute(&self.pool)
+            .await?;
+            Ok(result.last_insert_rowid())
+        }
+    }
+
+    pub async fn delete_plc_device(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM plc_devices WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove um layout registado (nunca o ativo). Devolve false se não existir ou estiver ativo.
+    pub async fn delete_packet_layout(&self, name: &str) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM packet_layout_segments WHERE layout_id = (SELECT id FROM packet_layouts WHERE name = ? AND active = 0)")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query("DELETE FROM packet_layouts WHERE name = ? AND active = 0")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn packet_layout_exists(&self, name: &str) -> Result<bool, sqlx::Error> {
+        let row = sqlx::query("SELECT id FROM packet_layouts WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    // ===== BLACKLIST / ALLOWLIST DE PLCs =====
+    pub async fn get_blocked_plcs(&self) -> Result<Vec<BlockedPlc>, sqlx::Error> {
+        let rows = sqlx::query("SELECT ip, reason, blocked_at FROM plc_blacklist ORDER BY blocked_at DESC")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|row| BlockedPlc {
+            ip: row.get("ip"),
+            reason: row.get("reason"),
+            blocked_at: row.get("blocked_at"),
+        }).collect())
+    }
+
+    pub async fn block_plc(&self, ip: &str, reason: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO plc_blacklist (ip, reason, blocked_at)
+            VALUES (?, ?, CURRENT_TIMESTAMP)
+            "#,
+        )
+        .bind(ip)
+        .bind(reason)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn unblock_plc(&self, ip: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM plc_blacklist WHERE ip = ?")
+            .bind(ip)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_allowlist(&self) -> Result<Vec<AllowlistEntry>, sqlx::Error> {
+        let rows = sqlx::query("SELECT id, entry, description, created_at FROM plc_allowlist ORDER BY entry")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|row| AllowlistEntry {
+            id: row.get("id"),
+            entry: row.get("entry"),
+            description: row.get("description"),
+            created_at: row.get("created_at"),
+        }).collect())
+    }
+
+    pub async fn add_allowlist_entry(&self, entry: &str, description: &str) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query("INSERT INTO plc_allowlist (entry, description) VALUES (?, ?)")
+            .bind(entry)
+            .bind(description)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn delete_allowlist_entry(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM plc_allowlist WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // ===== HISTÓRICO DE SESSÕES =====
+    pub async fn open_plc_session(&self, ip: &str, plc_name: &str, conn_id: u64, source: &str) -> Result<i64, sqlx::Error> {
+        let now = session_timestamp(chrono::Utc::now());
+        let result = sqlx::query(
+            "INSERT INTO plc_sessions (ip, plc_name, conn_id, source, connected_at, last_seen_at) VALUES (?, ?, ?, ?, ?, ?)"
+        )
+        .bind(ip)
+        .bind(plc_name)
+        .bind(conn_id as i64)
+        .bind(source)
+        .bind(&now)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn touch_plc_session(&self, id: i64, bytes: u64, packets: u64) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE plc_sessions SET last_seen_at = ?, bytes = ?, packets = ? WHERE id = ? AND disconnected_at IS NULL")
+            .bind(session_timestamp(chrono::Utc::now()))
+            .bind(bytes as i64)
+            .bind(packets as i64)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn close_plc_session(&self, id: i64, reason: &str, bytes: u64, packets: u64) -> Result<(), sqlx::Error> {
+        let now = session_timestamp(chrono::Utc::now());
+        sqlx::query(
+            r#"
+            UPDATE plc_sessions
+            SET disconnected_at = ?, last_seen_at = ?, end_reason = ?, bytes = ?, packets = ?
+            WHERE id = ? AND disconnected_at IS NULL
+            "#
+        )
+        .bind(&now)
+        .bind(&now)
+        .bind(reason)
+        .bind(bytes as i64)
+        .bind(packets as i64)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Fecha sessões deixadas abertas por uma paragem abrupta do backend
+    /// (termina na última atualização conhecida)
+    pub async fn close_interrupted_sessions(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE plc_sessions SET disconnected_at = last_seen_at, end_reason = 'interrupted' WHERE disconnected_at IS NULL"
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    fn row_to_plc_session(row: &sqlx::sqlite::SqliteRow) -> PlcSession {
+        PlcSession {
+            id: row.get("id"),
+            ip: row.get("ip"),
+            plc_name: row.get("plc_name"),
+            conn_id: row.get("conn_id"),
+            source: row.get("source"),
+            connected_at: row.get("connected_at"),
+            last_seen_at: row.get("last_seen_at"),
+            disconnected_at: row.get("disconnected_at"),
+            end_reason: row.get("end_reason"),
+            bytes: row.get("bytes"),
+            packets: row.get("packets"),
+        }
+    }
+
+    pub async fn get_plc_sessions(&self, ip: Option<&str>, limit: i64) -> Result<Vec<PlcSession>, sqlx::Error> {
+        let rows = match ip {
+            Some(ip) => sqlx::query("SELECT * FROM plc_sessions WHERE ip = ? ORDER BY id DESC LIMIT ?")
+                .bind(ip)
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await?,
+            None => sqlx::query("SELECT * FROM plc_sessions ORDER BY id DESC LIMIT ?")
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await?,
+        };
+
+        Ok(rows.iter().map(Self::row_to_plc_session).collect())
+    }
+
+    /// Disponibilidade diária por PLC nos `days` dias (hora local) que terminam em `last_day`.
+    /// Inclui os PLCs com sessões no período e os PLCs registados como esperados.
+    pub async fn get_plc_availability(&self, last_day: chrono::NaiveDate, days: u32) -> Result<Vec<PlcAvailabilityDay>, sqlx::Error> {
+        use chrono::{Duration, Local, TimeZone, Utc};
+
+        let first_day = last_day - Duration::days(days.saturating_sub(1) as i64);
+        let day_start = |day: chrono::NaiveDate| {
+            let midnight = day.and_hms_opt(0, 0, 0).unwrap();
+            Local.from_local_datetime(&midnight).earliest()
+                .unwrap_or_else(|| Local.from_utc_datetime(&midnight))
+                .with_timezone(&Utc)
+        };
+        let range_start = day_start(first_day);
+        let range_end = day_start(last_day + Duration::days(1));
+        let now = Utc::now();
+
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM plc_sessions
+            WHERE connected_at < ? AND (disconnected_at IS NULL OR disconnected_at > ?)
+            ORDER BY connected_at
+            "#
+        )
+        .bind(session_timestamp(range_end))
+        .bind(session_timestamp(range_start))
+        .fetch_all(&self.pool)
+        .await?;
+        let sessions: Vec<PlcSession> = rows.iter().map(Self::row_to_plc_session).collect();
+
+        // PLCs a reportar: registados (nome do inventário) + vistos no período
+        let devices = self.get_plc_devices().await?;
+        let mut plcs: Vec<(String, String)> = devices.iter()
+            .filter(|d| d.expected_online)
+            .map(|d| (d.expected_ip.clone(), d.name.clone()))
+            .collect();
+        for session in &sessions {
+            if !plcs.iter().any(|(ip, _)| ip == &session.ip) {
+                let name = devices.iter()
+                    .find(|d| d.expected_ip == session.ip)
+                    .map(|d| d.name.clone())
+                    .unwrap_or_else(|| session.plc_name.clone());
+                plcs.push((session.ip.clone(), name));
+            }
+        }
+        plcs.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+
+        let mut report = Vec::new();
+        for day in first_day.iter_days().take_while(|d| *d <= last_day) {
+            let start = day_start(day);
+            let end = day_start(day + Duration::days(1)).min(now);
+            if end <= start {
+                continue; // Dia futuro
+            }
+            let period_secs = (end - start).num_seconds();
+
+            for (ip, name) in &plcs {
+                let mut connected_secs = 0;
+                let mut count = 0;
+                let mut disconnects = 0;
+
+                for session in sessions.iter().filter(|s| &s.ip == ip) {
+                    let Some(connected_at) = parse_session_timestamp(&session.connected_at) else { continue };
+                    let disconnected_at = session.disconnected_at.as_deref()
+                        .and_then(parse_session_timestamp)
+                        .unwrap_or(now);
+
+                    let overlap_start = connected_at.max(start);
+                    let overlap_end = disconnected_at.min(end);
+                    if overlap_end <= overlap_start {
+                        continue;
+                    }
+                    connected_secs += (overlap_end - overlap_start).num_seconds();
+                    count += 1;
+
+                    let abnormal = session.end_reason.as_deref().is_some_and(|r| r != "normal");
+                    if abnormal && session.disconnected_at.is_some() && disconnected_at >= start && disconnected_at < end {
+                        disconnects += 1;
+                    }
+                }
+
+                let connected_secs = connected_secs.min(period_secs);
+                report.push(PlcAvailabilityDay {
+                    date: day.format("%Y-%m-%d").to_string(),
+                    ip: ip.clone(),
+                    plc_name: name.clone(),
+                    connected_secs,
+                    period_secs,
+                    availability_pct: (connected_secs as f64 * 10000.0 / period_secs as f64).round() / 100.0,
+                    sessions: count,
+                    disconnects,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    // ===== PAINÉIS =====
+    pub async fn get_panels(&self) -> Result<Vec<Panel>, sqlx::Error> {
+        let rows = sqlx::query("SELECT id, name, width, height, plc, idle_text_key, filter_bits, filter_videos, filter_texts, filter_playlists FROM panels ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+        let items = sqlx::query("SELECT panel_id, kind, item FROM panel_items ORDER BY panel_id, kind, item")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut grouped: HashMap<(String, String), Vec<String>> = HashMap::new();
+        for row in items {
+            grouped.entry((row.get("panel_id"), row.get("kind"))).or_default().push(row.get("item"));
+        }
+
+        Ok(rows.into_iter().map(|row| {
+            let id: String = row.get("id");
+            let mut list = |kind: &str, filtered: bool| {
+                filtered.then(|| grouped.remove(&(id.clone(), kind.to_string())).unwrap_or_default())
+            };
+            let bit_ids = list("bit", row.get::<i64, _>("filter_bits") != 0)
+                .map(|ids| ids.iter().filter_map(|i| i.parse().ok()).collect());
+            let video_ids = list("video", row.get::<i64, _>("filter_videos") != 0)
+                .map(|ids| ids.iter().filter_map(|i| i.parse().ok()).collect());
+            let text_keys = list("text", row.get::<i64, _>("filter_texts") != 0);
+            let playlist_ids = list("playlist", row.get::<i64, _>("filter_playlists") != 0)
+                .map(|ids| ids.iter().filter_map(|i| i.parse().ok()).collect());
+            Panel {
+                id,
+                name: row.get("name"),
+                width: row.get("width"),
+                height: row.get("height"),
+                plc: row.get("plc"),
+                idle_text_key: row.get("idle_text_key"),
+                bit_ids,
+                video_ids,
+                text_keys,
+                playlist_ids,
+            }
+        }).collect())
+    }
+
+    /// Insere ou atualiza um painel (e substitui os seus itens)
+    pub async fn save_panel(&self, panel: &Panel) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let plc = panel.plc.as_deref().map(str::trim).filter(|p| !p.is_empty());
+        let idle_text_key = panel.idle_text_key.as_deref().filter(|k| !k.is_empty());
+
+        sqlx::query(
+            r#"
+            INSERT INTO panels (id, name, width, height, plc, idle_text_key, filter_bits, filter_videos, filter_texts, filter_playlists)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                width = excluded.width,
+                height = excluded.height,
+                plc = excluded.plc,
+                idle_text_key = excluded.idle_text_key,
+                filter_bits = excluded.filter_bits,
+                filter_videos = excluded.filter_videos,
+                filter_texts = excluded.filter_texts,
+                filter_playlists = excluded.filter_playlists,
+                updated_at = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(panel.id.trim())
+        .bind(panel.name.trim())
+        .bind(panel.width)
+        .bind(panel.height)
+        .bind(plc)
+        .bind(idle_text_key)
+        .bind(panel.bit_ids.is_some() as i64)
+        .bind(panel.video_ids.is_some() as i64)
+        .bind(panel.text_keys.is_some() as i64)
+        .bind(panel.playlist_ids.is_some() as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("DELETE FROM panel_items WHERE panel_id = ?")
+            .bind(panel.id.trim())
+            .execute(&mut *tx)
+            .await?;
+
+        let bits = panel.bit_ids.iter().flatten().map(|id| ("bit", id.to_string()));
+        let videos = panel.video_ids.iter().flatten().map(|id| ("video", id.to_string()));
+        let texts = panel.text_keys.iter().flatten().map(|key| ("text", key.clone()));
+        let playlists = panel.playlist_ids.iter().flatten().map(|id| ("playlist", id.to_string()));
+        for (kind, item) in bits.chain(videos).chain(texts).chain(playlists) {
+            sqlx::query("INSERT OR IGNORE INTO panel_items (panel_id, kind, item) VALUES (?, ?, ?)")
+                .bind(panel.id.trim())
+                .bind(kind)
+                .bind(item)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn delete_panel(&self, id: &str) -> Result<bool, sqlx::Error> {
+        sqlx::query("DELETE FROM panel_items WHERE panel_id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        let result = sqlx::query("DELETE FROM panels WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Remove dos painéis e das playlists os bits, vídeos e playlists que já não existem
+    async fn prune_panel_items(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM panel_items
+            WHERE (kind = 'bit' AND item NOT IN (SELECT CAST(id AS TEXT) FROM bit_configs))
+               OR (kind = 'video' AND item NOT IN (SELECT CAST(id AS TEXT) FROM video_configs))
+               OR (kind = 'playlist' AND item NOT IN (SELECT CAST(id AS TEXT) FROM playlists))
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query("DELETE FROM playlist_items WHERE video_id NOT IN (SELECT id FROM video_configs)")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // ===== PLAYLISTS =====
+    pub async fn get_playlists(&self) -> Result<Vec<Playlist>, sqlx::Error> {
+        let rows = sqlx::query("SELECT id, name, enabled, priority, valid_from, valid_until, days, start_time, end_time FROM playlists ORDER BY priority DESC, name")
+            .fetch_all(&self.pool)
+            .await?;
+        let items = sqlx::query("SELECT playlist_id, video_id, weight FROM playlist_items ORDER BY playlist_id, position")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut grouped: HashMap<i64, Vec<PlaylistItem>> = HashMap::new();
+        for row in items {
+            grouped.entry(row.get("playlist_id")).or_default().push(PlaylistItem {
+                video_id: row.get("video_id"),
+                weight: row.get::<i64, _>("weight").max(1) as u32,
+            });
+        }
+
+        let date = |value: Option<String>| value.and_then(|v| chrono::NaiveDate::parse_from_str(&v, "%Y-%m-%d").ok());
+        Ok(rows.into_iter().map(|row| {
+            let id: i64 = row.get("id");
+            Playlist {
+                id,
+                name: row.get("name"),
+                enabled: row.get::<i64, _>("enabled") != 0,
+                priority: row.get("priority"),
+                valid_from: date(row.get("valid_from")),
+                valid_until: date(row.get("valid_until")),
+                days: row.get::<String, _>("days").split(',').filter_map(|d| d.trim().parse().ok()).collect(),
+                start_time: row.get("start_time"),
+                end_time: row.get("end_time"),
+                items: grouped.remove(&id).unwrap_or_default(),
+            }
+        }).collect())
+    }
+
+    /// Insere (id = 0) ou atualiza uma playlist e os seus itens
+    pub async fn save_playlist(&self, playlist: &Playlist) -> Result<i64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let days = playlist.days.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(",");
+        let valid_from = playlist.valid_from.map(|d| d.to_string());
+        let valid_until = playlist.valid_until.map(|d| d.to_string());
+        let start_time = playlist.start_time.as_deref().map(str::trim);
+        let end_time = playlist.end_time.as_deref().map(str::trim);
+
+        let id = if playlist.id > 0 {
+            sqlx::query(
+                r#"
+                UPDATE playlists
+                SET name = ?, enabled = ?, priority = ?, valid_from = ?, valid_until = ?, days = ?, start_time = ?, end_time = ?, updated_at = CURRENT_TIMESTAMP
+                WHERE id = ?
+                "#,
+            )
+            .bind(playlist.name.trim())
+            .bind(playlist.enabled as i64)
+            .bind(playlist.priority)
+            .bind(&valid_from)
+            .bind(&valid_until)
+            .bind(&days)
+            .bind(start_time)
+            .bind(end_time)
+            .bind(playlist.id)
+            .execute(&mut *tx)
+            .await?;
+            playlist.id
+        } else {
+            sqlx::query(
+                r#"
+                INSERT INTO playlists (name, enabled, priority, valid_from, valid_until, days, start_time, end_time)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(playlist.name.trim())
+            .bind(playlist.enabled as i64)
+            .bind(playlist.priority)
+            .bind(&valid_from)
+            .bind(&valid_until)
+            .bind(&days)
+            .bind(start_time)
+            .bind(end_time)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_rowid()
+        };
+
+        sqlx::query("DELETE FROM playlist_items WHERE playlist_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        for (position, item) in playlist.items.iter().enumerate() {
+            sqlx::query("INSERT INTO playlist_items (playlist_id, position, video_id, weight) VALUES (?, ?, ?, ?)")
+                .bind(id)
+                .bind(position as i64)
+                .bind(item.video_id)
+                .bind(item.weight as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    pub async fn delete_playlist(&self, id: i64) -> Result<bool, sqlx::Error> {
+        sqlx::query("DELETE FROM playlist_items WHERE playlist_id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        let result = sqlx::query("DELETE FROM playlists WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        self.prune_panel_items().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // ===== BANDA MORTA DO STREAM SSE (MODO DELTA) =====
+    pub async fn get_sse_deadbands(&self) -> Result<HashMap<String, f64>, sqlx::Error> {
+        let rows = sqlx::query("SELECT tag, deadband FROM sse_deadbands")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|row| (row.get("tag"), row.get("deadband"))).collect())
+    }
+
+    pub async fn set_sse_deadband(&self, tag: &str, deadband: f64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO sse_deadbands (tag, deadband) VALUES (?, ?) ON CONFLICT(tag) DO UPDATE SET deadband = excluded.deadband"
+        )
+        .bind(tag)
+        .bind(deadband)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_sse_deadband(&self, tag: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM sse_deadbands WHERE tag = ?")
+            .bind(tag)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // ===== REGISTO DE EXIBIÇÃO (PROOF OF PLAY) =====
+    pub async fn start_video_play(
+        &self,
+        panel: &str,
+        video: &VideoConfig,
+        playlist_id: Option<i64>,
+        source: &str,
+    ) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO video_plays (panel, video_id, video_name, playlist_id, source, started_at) VALUES (?, ?, ?, ?, ?, ?)"
+        )
+        .bind(panel)
+        .bind(video.id)
+        .bind(&video.name)
+        .bind(playlist_id)
+        .bind(source)
+        .bind(proof_of_play::timestamp(chrono::Utc::now()))
+        .execute(&self.pool)
+        .await?;
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Fecha uma exibição em curso; devolve os segundos no ecrã ou None se não existe ou já fechou
+    pub async fn end_video_play(&self, id: i64, status: &str, reason: &str) -> Result<Option<f64>, sqlx::Error> {
+        let row = sqlx::query("SELECT started_at FROM video_plays WHERE id = ? AND status = ?")
+            .bind(id)
+            .bind(PLAY_PLAYING)
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let now = chrono::Utc::now();
+        let started_at: String = row.get("started_at");
+        let seconds = chrono::DateTime::parse_from_rfc3339(&started_at)
+            .map(|start| (now - start.with_timezone(&chrono::Utc)).num_milliseconds().max(0) as f64 / 1000.0)
+            .unwrap_or(0.0);
+        let result = sqlx::query(
+            "UPDATE video_plays SET status = ?, reason = ?, ended_at = ?, seconds = ? WHERE id = ? AND status = ?"
+        )
+        .bind(status)
+        .bind(reason)
+        .bind(proof_of_play::timestamp(now))
+        .bind(seconds)
+        .bind(id)
+        .bind(PLAY_PLAYING)
+        .execute(&self.pool)
+        .await?;
+        Ok((result.rows_affected() > 0).then_some(seconds))
+    }
+
+    /// Exibições iniciadas em [from, to) (limites UTC de proof_of_play::report_range)
+    pub async fn get_video_plays(&self, from: &str, to: &str, panel: Option<&str>) -> Result<Vec<VideoPlay>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM video_plays
+            WHERE started_at >= ? AND started_at < ? AND (? IS NULL OR panel = ?)
+            ORDER BY started_at
+            "#,
+        )
+        .bind(from)
+        .bind(to)
+        .bind(panel)
+        .bind(panel)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| VideoPlay {
+            id: row.get("id"),
+            panel: row.get("panel"),
+            video_id: row.get("video_id"),
+            video_name: row.get("video_name"),
+            playlist_id: row.get("playlist_id"),
+            source: row.get("source"),
+            status: row.get("status"),
+            reason: row.get("reason"),
+            started_at: row.get("started_at"),
+            ended_at: row.get("ended_at"),
+            seconds: row.get("seconds"),
+        }).collect())
+    }
+
+    pub async fn add_ad_gap(&self, panel: &str, started_at: &str, ended_at: &str, seconds: f64) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO ad_gaps (panel, started_at, ended_at, seconds) VALUES (?, ?, ?, ?)")
+            .bind(panel)
+            .bind(started_at)
+            .bind(ended_at)
+            .bind(seconds)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_ad_gaps(&self, from: &str, to: &str, panel: Option<&str>) -> Result<Vec<AdGap>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM ad_gaps
+            WHERE started_at >= ? AND started_at < ? AND (? IS NULL OR panel = ?)
+            ORDER BY started_at
+            "#,
+        )
+        .bind(from)
+        .bind(to)
+        .bind(panel)
+        .bind(panel)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| AdGap {
+            id: row.get("id"),
+            panel: row.get("panel"),
+            started_at: row.get("started_at"),
+            ended_at: row.get("ended_at"),
+            seconds: row.get("seconds"),
+        }).collect())
     }
 
     // ===== SISTEMA DE LOGS =====
@@ -915,6 +2503,7 @@ impl Database {
         Ok(logs)
     }
 
+    #[allow(dead_code)]
     pub async fn get_logs_by_level(&self, level: &str, limit: i32) -> Result<Vec<SystemLog>, sqlx::Error> {
         let rows = sqlx::query("SELECT * FROM system_logs WHERE level = ? ORDER BY id DESC LIMIT ?")
             .bind(level)
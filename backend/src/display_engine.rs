@@ -0,0 +1,818 @@
+// display_engine.rs - ARBITRAGEM DO QUE O PAINEL MOSTRA (NO SERVIDOR)
+// ============================================================================
+// Antes cada browser decidia sozinho que mensagem ou vídeo ganhava; agora o
+// servidor avalia as regras a cada frame PLC (e a cada segundo, para a rotação
+// de vídeos) e publica um único "estado do painel" em /api/events/panel-state.
+// Todos os ecrãs da eclusa mostram exatamente o mesmo.
+//
+// Regras (por ordem):
+//   1. Bit de controle de vídeo (video_control_word_index/bit_index) a 1 e há
+//      vídeos ativos → rotação dos vídeos, cada um durante `duration` segundos.
+//      Se houver uma playlist em vigor (playlist.rs) roda o ciclo dessa playlist;
+//      senão roda todos os vídeos ativos pela ordem de exibição
+//   2. Bit configurado (bit_configs ativos) a 1 de maior prioridade:
+//      action_type "video" → esse vídeo em ciclo; senão a mensagem (template
+//      resolvido com os valores do frame). Mensagens vazias passam ao seguinte.
+//   3. Nenhum bit ativo → texto de repouso (text_configs[idle_text_key]), se houver
+//
+// As regras ficam em cache e são recarregadas (reload) quando os comandos de
+// configuração as alteram.
+//
+// Cada painel (tabela panels) tem o seu estado, PLC e subconjunto de bits,
+// vídeos, textos e playlists: /api/events/panel-state?panel=montante. O painel
+// "default" (sem ?panel=) usa a configuração global: todos os bits, vídeos e
+// playlists, display_plc e idle_text_key.
+//
+// Enquanto o bit de controle está a 0 num painel com vídeos para rodar, a
+// publicidade está suprimida: o período fica registado em ad_gaps (proof_of_play.rs).
+// ============================================================================
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use tokio::sync::{broadcast, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::database::{BitConfig, Database, Panel, TextConfig, VideoConfig};
+use crate::packet_layout::Quality;
+use crate::playlist::{current_playlist, Playlist};
+use crate::proof_of_play;
+use crate::plc_stream::PlcFrame;
+use crate::tcp_server::PlcData;
+
+/// Painel da configuração global (panel.html sem ?panel=)
+pub const DEFAULT_PANEL_ID: &str = "default";
+/// display_configs: PLC que alimenta o painel (vazio = qualquer um)
+pub const DISPLAY_PLC_KEY: &str = "display_plc";
+/// display_configs: chave do text_configs mostrado quando nenhum bit está ativo
+pub const IDLE_TEXT_KEY: &str = "idle_text_key";
+
+const TICK_MS: u64 = 1000;
+
+/// Texto usado no lugar de variáveis com qualidade má (igual ao frontend)
+pub const BAD_QUALITY_PLACEHOLDER: &str = "---";
+
+// ============================================================================
+// ESTADO PUBLICADO
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayMode {
+    Idle,    // Nada a mostrar (ou texto de repouso)
+    Message, // Mensagem de um bit
+    Video,   // Vídeo (rotação ou vídeo de um bit)
+}
+
+/// Mensagem a mostrar, já com o template resolvido
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PanelMessage {
+    pub text: String,
+    pub source: String,         // "bit" ou "idle"
+    pub word_index: Option<i32>,
+    pub bit_index: Option<i32>,
+    pub name: String,
+    pub priority: i32,
+    pub color: String,
+    pub font_size: i32,
+    pub position: String,
+    pub font_family: String,
+    pub font_weight: String,
+    pub text_shadow: bool,
+    pub letter_spacing: i32,
+}
+
+/// Vídeo a mostrar
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PanelVideo {
+    pub id: i64,
+    pub name: String,
+    pub file_path: String,
+    pub duration: i32,
+    pub source: String,     // "playlist" (bit de controle) ou "bit" (action_type video)
+    pub playlist_id: Option<i64>, // Playlist em vigor (None = rotação de todos os vídeos ativos)
+    pub started_at: String, // Início no servidor (ecrãs que ligam a meio saltam para a posição certa)
+}
+
+/// Estado oficial do painel
+#[derive(Debug, Clone, Serialize)]
+pub struct PanelState {
+    pub panel: String,             // Id do painel
+    pub seq: u64,                  // Incrementa a cada mudança
+    pub mode: DisplayMode,
+    pub message: Option<PanelMessage>,
+    pub video: Option<PanelVideo>,
+    pub source_ip: Option<String>, // PLC do último frame avaliado
+    pub updated_at: String,
+    pub server_time: String,       // Hora do envio (para calcular a posição do vídeo)
+}
+
+impl PanelState {
+    fn idle(panel: &str) -> Self {
+        let now = chrono::Utc::now().to_rfc3339();
+        Self {
+            panel: panel.to_string(),
+            seq: 0,
+            mode: DisplayMode::Idle,
+            message: None,
+            video: None,
+            source_ip: None,
+            updated_at: now.clone(),
+            server_time: now,
+        }
+    }
+
+    /// Cópia com server_time = agora
+    pub fn stamped(&self) -> Self {
+        Self { server_time: chrono::Utc::now().to_rfc3339(), ..self.clone() }
+    }
+
+    fn same_output(&self, mode: DisplayMode, message: &Option<PanelMessage>, video: &Option<PanelVideo>) -> bool {
+        self.mode == mode && &self.message == message && &self.video == video
+    }
+}
+
+/// Playlist em vigor de um painel (o que roda quando o bit de controle está a 1)
+#[derive(Debug, Clone, Serialize)]
+pub struct PanelPlaylist {
+    pub panel: String,
+    pub playlist: Option<Playlist>, // None = sem playlist em vigor: todos os vídeos ativos
+    pub videos: Vec<VideoConfig>,   // Um ciclo, já expandido pelos pesos
+    pub server_time: String,
+}
+
+// ============================================================================
+// REGRAS (cache da base de dados)
+// ============================================================================
+
+/// Configuração global lida uma vez por reload e partilhada pelos painéis
+struct DisplayConfig {
+    bits: Vec<BitConfig>,   // Só ativos, por prioridade (maior primeiro)
+    videos: Vec<VideoConfig>, // Todos, pela ordem de exibição
+    texts: Vec<TextConfig>,
+    playlists: Vec<Playlist>, // Por prioridade (maior primeiro)
+    control_word: i32,
+    control_bit: i32,
+    default_plc: Option<String>,
+    default_idle_text: Option<String>,
+}
+
+impl DisplayConfig {
+    async fn load(db: &Database) -> Result<Self, sqlx::Error> {
+        let mut bits: Vec<BitConfig> = db.get_all_bit_configs().await?
+            .into_iter()
+            .filter(|b| b.enabled)
+            .collect();
+        bits.sort_by_key(|b| std::cmp::Reverse(b.priority));
+        let (control_word, control_bit) = db.get_video_control_bit().await?;
+
+        Ok(Self {
+            bits,
+            videos: db.get_all_videos().await?,
+            texts: db.get_all_texts().await?,
+            playlists: db.get_playlists().await?,
+            control_word,
+            control_bit,
+            default_plc: db.get_display_config(DISPLAY_PLC_KEY).await?.filter(|p| !p.is_empty()),
+            default_idle_text: db.get_display_config(IDLE_TEXT_KEY).await?.filter(|k| !k.is_empty()),
+        })
+    }
+
+    /// Regras do painel (None = painel default com a configuração global)
+    fn rules_for(&self, panel: Option<&Panel>) -> DisplayRules {
+        let bit_ids = panel.and_then(|p| p.bit_ids.as_ref());
+        let video_ids = panel.and_then(|p| p.video_ids.as_ref());
+        let text_keys = panel.and_then(|p| p.text_keys.as_ref());
+        let playlist_ids = panel.and_then(|p| p.playlist_ids.as_ref());
+        let idle_key = match panel {
+            Some(p) => p.idle_text_key.as_ref(),
+            None => self.default_idle_text.as_ref(),
+        };
+
+        let videos: Vec<&VideoConfig> = self.videos.iter()
+            .filter(|v| video_ids.is_none_or(|ids| ids.contains(&v.id)))
+            .collect();
+
+        // Ciclo de cada playlist do painel (vídeos apagados ou desativados saem do ciclo)
+        let mut playlists = Vec::new();
+        let mut sequences = HashMap::new();
+        for playlist in &self.playlists {
+            if !playlist.enabled || !playlist_ids.is_none_or(|ids| ids.contains(&playlist.id)) {
+                continue;
+            }
+            let sequence: Vec<VideoConfig> = playlist.sequence().into_iter()
+                .filter_map(|id| self.videos.iter().find(|v| v.id == id && v.enabled))
+                .cloned()
+                .collect();
+            if !sequence.is_empty() {
+                playlists.push(playlist.clone());
+                sequences.insert(playlist.id, sequence);
+            }
+        }
+
+        DisplayRules {
+            bits: self.bits.iter()
+                .filter(|b| bit_ids.is_none_or(|ids| ids.contains(&b.id)))
+                .cloned()
+                .collect(),
+            playlist: videos.iter().filter(|v| v.enabled).map(|v| (*v).clone()).collect(),
+            videos: videos.into_iter().map(|v| (v.id, v.clone())).collect(),
+            playlists,
+            sequences,
+            control_word: self.control_word,
+            control_bit: self.control_bit,
+            idle_text: idle_key.and_then(|key| {
+                self.texts.iter()
+                    .filter(|t| text_keys.is_none_or(|keys| keys.contains(&t.key)))
+                    .find(|t| &t.key == key && t.enabled)
+                    .map(|t| t.text.clone())
+            }),
+            plc: match panel {
+                Some(p) => p.plc.clone().filter(|plc| !plc.is_empty()),
+                None => self.default_plc.clone(),
+            },
+        }
+    }
+}
+
+/// Regras efetivas de um painel
+#[derive(Default)]
+struct DisplayRules {
+    bits: Vec<BitConfig>,            // Só ativos, por prioridade (maior primeiro)
+    playlist: Vec<VideoConfig>,      // Vídeos ativos, pela ordem de exibição
+    videos: HashMap<i64, VideoConfig>, // Todos os do painel (vídeos associados a bits)
+    playlists: Vec<Playlist>,        // Playlists do painel com pelo menos um vídeo ativo
+    sequences: HashMap<i64, Vec<VideoConfig>>, // Ciclo de cada playlist (por id)
+    control_word: i32,
+    control_bit: i32,
+    idle_text: Option<String>,
+    plc: Option<String>,
+}
+
+impl DisplayRules {
+    /// Vídeos a rodar agora: ciclo da playlist em vigor ou todos os ativos
+    fn rotation(&self) -> (Option<&Playlist>, &[VideoConfig]) {
+        match current_playlist(&self.playlists, chrono::Local::now().naive_local()) {
+            Some(playlist) => (Some(playlist), &self.sequences[&playlist.id]),
+            None => (None, &self.playlist),
+        }
+    }
+}
+
+/// Posição na rotação de vídeos
+struct Rotation {
+    playlist_id: Option<i64>,
+    index: usize,
+    started: Instant,
+    started_at: String,
+}
+
+impl Rotation {
+    fn start(playlist_id: Option<i64>, index: usize) -> Self {
+        Self { playlist_id, index, started: Instant::now(), started_at: chrono::Utc::now().to_rfc3339() }
+    }
+}
+
+/// Início de um período com a publicidade suprimida
+struct AdGapStart {
+    started: Instant,
+    started_at: String,
+}
+
+/// Estado de um painel no motor
+struct EngineState {
+    rules: DisplayRules,
+    frame: Option<Arc<PlcData>>,
+    rotation: Option<Rotation>,
+    gap: Option<AdGapStart>,
+    current: PanelState,
+}
+
+impl EngineState {
+    fn new(panel: &str) -> Self {
+        Self { rules: DisplayRules::default(), frame: None, rotation: None, gap: None, current: PanelState::idle(panel) }
+    }
+
+    /// Bit de controle a 0 com vídeos para rodar (sem frame não se sabe: não conta)
+    fn ads_suppressed(&self) -> bool {
+        let rules = &self.rules;
+        self.frame.as_deref().is_some_and(|data| !bit_value(data, rules.control_word, rules.control_bit))
+            && !rules.rotation().1.is_empty()
+    }
+}
+
+// ============================================================================
+// MOTOR
+// ============================================================================
+
+pub struct DisplayEngine {
+    db: Arc<Database>,
+    panels: Mutex<HashMap<String, EngineState>>,
+    tx: broadcast::Sender<PanelState>,
+}
+
+impl DisplayEngine {
+    pub fn new(db: Arc<Database>) -> Self {
+        let (tx, _) = broadcast::channel(64);
+        let mut panels = HashMap::new();
+        panels.insert(DEFAULT_PANEL_ID.to_string(), EngineState::new(DEFAULT_PANEL_ID));
+        Self { db, panels: Mutex::new(panels), tx }
+    }
+
+    /// Mudanças de estado de todos os painéis (campo `panel`)
+    pub fn subscribe(&self) -> broadcast::Receiver<PanelState> {
+        self.tx.subscribe()
+    }
+
+    /// Estado atual do painel (None = painel desconhecido)
+    pub async fn current(&self, panel: &str) -> Option<PanelState> {
+        self.panels.lock().await.get(panel).map(|p| p.current.stamped())
+    }
+
+    /// Publicidade suprimida agora pelo bit de controle (false = painel desconhecido)
+    pub async fn ads_suppressed(&self, panel: &str) -> bool {
+        self.panels.lock().await.get(panel).is_some_and(|p| p.ads_suppressed())
+    }
+
+    /// Playlist que o painel roda agora (None = painel desconhecido)
+    pub async fn current_playlist(&self, panel: &str) -> Option<PanelPlaylist> {
+        let states = self.panels.lock().await;
+        let state = states.get(panel)?;
+        let (playlist, videos) = state.rules.rotation();
+        Some(PanelPlaylist {
+            panel: panel.to_string(),
+            playlist: playlist.cloned(),
+            videos: videos.to_vec(),
+            server_time: chrono::Utc::now().to_rfc3339(),
+        })
+    }
+
+    /// Relê as regras (e a lista de painéis) da base de dados e reavalia
+    pub async fn reload(&self) {
+        let loaded = match DisplayConfig::load(&self.db).await {
+            Ok(config) => self.db.get_panels().await.map(|panels| (config, panels)),
+            Err(e) => Err(e),
+        };
+        let (config, panels) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("⚠️ Painel: erro ao carregar regras de exibição: {:?}", e);
+                return;
+            }
+        };
+
+        let mut states = self.panels.lock().await;
+        states.retain(|id, _| id == DEFAULT_PANEL_ID || panels.iter().any(|p| &p.id == id));
+        let rules = std::iter::once((DEFAULT_PANEL_ID.to_string(), config.rules_for(None)))
+            .chain(panels.iter().map(|p| (p.id.clone(), config.rules_for(Some(p)))));
+        for (id, rules) in rules {
+            let state = states.entry(id.clone()).or_insert_with(|| EngineState::new(&id));
+            if rules.plc != state.rules.plc {
+                state.frame = None; // Frame de outro PLC
+            }
+            state.rules = rules;
+            self.evaluate(state);
+        }
+    }
+
+    async fn on_frame(&self, data: Arc<PlcData>) {
+        let mut states = self.panels.lock().await;
+        for state in states.values_mut() {
+            if state.rules.plc.as_deref().is_some_and(|filter| !data.matches_plc(filter)) {
+                continue;
+            }
+            state.frame = Some(data.clone());
+            self.evaluate(state);
+        }
+    }
+
+    async fn tick(&self) {
+        let mut states = self.panels.lock().await;
+        for state in states.values_mut() {
+            self.evaluate(state);
+        }
+    }
+
+    /// Corre o motor: frames do broadcast PLC + relógio da rotação de vídeos (e dos horários das playlists)
+    pub fn spawn(self: &Arc<Self>, mut rx: broadcast::Receiver<PlcFrame>) {
+        let engine = self.clone();
+        tokio::spawn(async move {
+            engine.reload().await;
+            let mut ticker = tokio::time::interval(Duration::from_millis(TICK_MS));
+            loop {
+                tokio::select! {
+                    frame = rx.recv() => match frame {
+                        Ok(frame) => engine.on_frame(frame.data).await,
+                        Err(RecvError::Lagged(_)) => continue, // O próximo frame traz o estado atual
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = ticker.tick() => engine.tick().await,
+                }
+            }
+        });
+    }
+
+    /// Aplica as regras ao último frame; publica se o resultado mudou
+    fn evaluate(&self, inner: &mut EngineState) {
+        self.track_ad_gap(inner);
+        let (mode, message, video) = decide(inner);
+        if inner.current.same_output(mode, &message, &video) {
+            return;
+        }
+
+        let now = chrono::Utc::now().to_rfc3339();
+        inner.current = PanelState {
+            panel: inner.current.panel.clone(),
+            seq: inner.current.seq + 1,
+            mode,
+            message,
+            video,
+            source_ip: inner.frame.as_ref().map(|f| f.source_ip.clone()),
+            updated_at: now.clone(),
+            server_time: now,
+        };
+        let _ = self.tx.send(inner.current.clone());
+    }
+
+    /// Abre/fecha o período de publicidade suprimida; grava-o em ad_gaps ao fechar
+    fn track_ad_gap(&self, inner: &mut EngineState) {
+        let suppressed = inner.ads_suppressed();
+        if suppressed && inner.gap.is_none() {
+            inner.gap = Some(AdGapStart {
+                started: Instant::now(),
+                started_at: proof_of_play::timestamp(chrono::Utc::now()),
+            });
+        } else if !suppressed {
+            let Some(gap) = inner.gap.take() else { return };
+            let db = self.db.clone();
+            let panel = inner.current.panel.clone();
+            let ended_at = proof_of_play::timestamp(chrono::Utc::now());
+            let seconds = gap.started.elapsed().as_secs_f64();
+            tokio::spawn(async move {
+                if let Err(e) = db.add_ad_gap(&panel, &gap.started_at, &ended_at, seconds).await {
+                    eprintln!("⚠️ Painel {}: erro ao gravar período sem publicidade: {:?}", panel, e);
+                }
+            });
+        }
+    }
+}
+
+fn decide(inner: &mut EngineState) -> (DisplayMode, Option<PanelMessage>, Option<PanelVideo>) {
+    let rules = &inner.rules;
+    let Some(data) = inner.frame.as_deref() else {
+        inner.rotation = None;
+        return idle(rules);
+    };
+
+    // ── 1. Rotação de vídeos pelo bit de controle ──
+    let (playlist, sequence) = rules.rotation();
+    if bit_value(data, rules.control_word, rules.control_bit) && !sequence.is_empty() {
+        let playlist_id = playlist.map(|p| p.id);
+        let rotation = inner.rotation.get_or_insert_with(|| Rotation::start(playlist_id, 0));
+        if rotation.playlist_id != playlist_id || rotation.index >= sequence.len() {
+            *rotation = Rotation::start(playlist_id, 0); // Outra playlist em vigor ou lista encolheu (reload)
+        } else if rotation.started.elapsed().as_secs() >= sequence[rotation.index].duration.max(1) as u64 {
+            *rotation = Rotation::start(playlist_id, (rotation.index + 1) % sequence.len());
+        }
+        let mut video = panel_video(&sequence[rotation.index], "playlist", &rotation.started_at);
+        video.playlist_id = playlist_id;
+        return (DisplayMode::Video, None, Some(video));
+    }
+    inner.rotation = None;
+
+    // ── 2. Bit ativo de maior prioridade ──
+    for bit in &rules.bits {
+        if !bit_value(data, bit.word_index, bit.bit_index) {
+            continue;
+        }
+        if bit.action_type == "video" {
+            if let Some(video) = bit.video_id.and_then(|id| rules.videos.get(&id)) {
+                // Mesmo vídeo já em ciclo: mantém o instante de início
+                let started_at = match &inner.current.video {
+                    Some(current) if current.id == video.id && current.source == "bit" => current.started_at.clone(),
+                    _ => chrono::Utc::now().to_rfc3339(),
+                };
+                return (DisplayMode::Video, None, Some(panel_video(video, "bit", &started_at)));
+            }
+            continue;
+        }
+
+        let text = if bit.use_template && !bit.message_template.is_empty() {
+            render_template(&bit.message_template, data)
+        } else {
+            bit.message.clone()
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        let message = PanelMessage {
+            text,
+            source: "bit".to_string(),
+            word_index: Some(bit.word_index),
+            bit_index: Some(bit.bit_index),
+            name: bit.name.clone(),
+            priority: bit.priority,
+            color: bit.color.clone(),
+            font_size: bit.font_size,
+            position: bit.position.clone(),
+            font_family: bit.font_family.clone(),
+            font_weight: bit.font_weight.clone(),
+            text_shadow: bit.text_shadow,
+            letter_spacing: bit.letter_spacing,
+        };
+        return (DisplayMode::Message, Some(message), None);
+    }
+
+    // ── 3. Repouso ──
+    idle(rules)
+}
+
+fn idle(rules: &DisplayRules) -> (DisplayMode, Option<PanelMessage>, Option<PanelVideo>) {
+    let message = rules.idle_text.as_ref().map(|text| PanelMessage {
+        text: text.clone(),
+        source: "idle".to_string(),
+        word_index: None,
+        bit_index: None,
+        name: String::new(),
+        priority: 0,
+        color: "#ffffff".to_string(),
+        font_size: 48,
+        position: "center".to_string(),
+        font_family: "Arial Black".to_string(),
+        font_weight: "bold".to_string(),
+        text_shadow: true,
+        letter_spacing: 2,
+    });
+    (DisplayMode::Idle, message, None)
+}
+
+fn panel_video(video: &VideoConfig, source: &str, started_at: &str) -> PanelVideo {
+    PanelVideo {
+        id: video.id,
+        name: video.name.clone(),
+        file_path: video.file_path.clone(),
+        duration: video.duration,
+        source: source.to_string(),
+        playlist_id: None,
+        started_at: started_at.to_string(),
+    }
+}
+
+/// Bit `bit_index` de Word[word_index] (ausente = 0)
+fn bit_value(data: &PlcData, word_index: i32, bit_index: i32) -> bool {
+    if !(0..16).contains(&bit_index) {
+        return false;
+    }
+    let word = data.variables.get(&format!("Word[{}]", word_index)).copied().unwrap_or(0.0) as u32;
+    (word >> bit_index) & 1 == 1
+}
+
+// ============================================================================
+// TEMPLATES (mesma sintaxe de frontend/src/utils/templateParser.ts)
+//   {Real[N]} {Real[N]:D}        float de Word[N] (hi) + Word[N+1] (lo)
+//   {Int[N]} {Int[N]/D} {Int[N]*M}   Word[N] com sinal
+//   {Word[N]} {Word[N]/D} {Word[N]*M}
+//   {Nome[N]} {Nome[N]:D} {Nome[N]/D} {Nome[N]*M}   qualquer segmento do layout
+// ============================================================================
+
+/// Substitui as tags do template pelos valores do frame
+pub fn render_template(template: &str, data: &PlcData) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        match tail.find('}').and_then(|end| render_tag(&tail[1..end], data).map(|v| (end, v))) {
+            Some((end, value)) => {
+                out.push_str(&value);
+                rest = &tail[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Modificador de uma tag: `:D` casas decimais, `/D` divisor, `*M` multiplicador
+enum TagModifier<'a> {
+    None,
+    Decimals(usize),
+    Divide(&'a str),
+    Multiply(f64),
+}
+
+/// Resolve uma tag ("Nome[N]" + modificador); None = não é tag, fica como está
+fn render_tag(tag: &str, data: &PlcData) -> Option<String> {
+    let (name, rest) = tag.split_once('[')?;
+    let (index, modifier) = rest.split_once(']')?;
+    let mut chars = name.chars();
+    if !chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return None;
+    }
+    let index: usize = index.parse().ok()?;
+    let number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '.');
+    let modifier = if modifier.is_empty() {
+        TagModifier::None
+    } else if let Some(d) = modifier.strip_prefix(':').filter(|d| !d.is_empty() && d.chars().all(|c| c.is_ascii_digit())) {
+        TagModifier::Decimals(d.parse().ok()?)
+    } else if let Some(d) = modifier.strip_prefix('/').filter(|d| number(d)) {
+        TagModifier::Divide(d)
+    } else if let Some(m) = modifier.strip_prefix('*').filter(|m| number(m)) {
+        TagModifier::Multiply(m.parse().ok()?)
+    } else {
+        return None;
+    };
+
+    let word_key = format!("Word[{}]", index);
+    let word = data.variables.get(&word_key).copied();
+    Some(match (name, &modifier) {
+        // Real[N] legado: float IEEE 754 de duas Words
+        ("Real", TagModifier::None | TagModifier::Decimals(_)) => {
+            let next_key = format!("Word[{}]", index + 1);
+            if is_bad(data, &word_key) || is_bad(data, &next_key) {
+                return Some(BAD_QUALITY_PLACEHOLDER.to_string());
+            }
+            let (Some(hi), Some(lo)) = (word, data.variables.get(&next_key).copied()) else {
+                return Some("{...}".to_string());
+            };
+            let value = f32::from_bits(((hi as u32 & 0xFFFF) << 16) | (lo as u32 & 0xFFFF));
+            let decimals = match modifier { TagModifier::Decimals(d) => d, _ => 2 };
+            if value.is_finite() { format!("{:.*}", decimals, value) } else { "---".to_string() }
+        }
+        ("Int" | "Word", TagModifier::None | TagModifier::Divide(_) | TagModifier::Multiply(_)) => {
+            if is_bad(data, &word_key) {
+                return Some(BAD_QUALITY_PLACEHOLDER.to_string());
+            }
+            let Some(raw) = word else {
+                return Some("{...}".to_string());
+            };
+            let value = if name == "Int" { raw as u32 as u16 as i16 as f64 } else { raw };
+            apply_modifier(value, &modifier)
+        }
+        // Segmento genérico: texto (String, Time, Dtl) ou número
+        _ => {
+            let key = format!("{}[{}]", name, index);
+            if is_bad(data, &key) {
+                return Some(BAD_QUALITY_PLACEHOLDER.to_string());
+            }
+            if let Some(text) = data.texts.get(&key) {
+                return Some(text.clone());
+            }
+            let Some(&value) = data.variables.get(&key) else {
+                return Some("{...}".to_string());
+            };
+            apply_modifier(value, &modifier)
+        }
+    })
+}
+
+fn apply_modifier(value: f64, modifier: &TagModifier) -> String {
+    match modifier {
+        TagModifier::None => value.to_string(),
+        TagModifier::Decimals(d) => format!("{:.*}", d, value),
+        TagModifier::Divide(d) => {
+            let divisor: f64 = d.parse().unwrap_or(0.0);
+            if divisor == 0.0 {
+                return "---".to_string();
+            }
+            // Divisor com ponto decimal: 2 casas; senão 1 casa
+            format!("{:.*}", if d.contains('.') { 2 } else { 1 }, value / divisor)
+        }
+        TagModifier::Multiply(m) => (value * m + 0.5).floor().to_string(), // Math.round
+    }
+}
+
+/// Variável com qualidade que não pode ser mostrada (substituída conta como boa)
+fn is_bad(data: &PlcData, key: &str) -> bool {
+    data.quality.get(key).is_some_and(|q| !q.is_good() && *q != Quality::Substituted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(words: &[(usize, f64)]) -> PlcData {
+        PlcData {
+            timestamp: String::new(),
+            received_at: None,
+            source_ip: "10.0.0.1".to_string(),
+            conn_id: 1,
+            plc_name: "PLC".to_string(),
+            variables: words.iter().map(|(i, v)| (format!("Word[{}]", i), *v)).collect(),
+            texts: HashMap::new(),
+            quality: HashMap::new(),
+        }
+    }
+
+    fn bit(bit_index: i32, priority: i32, action_type: &str, message: &str) -> BitConfig {
+        BitConfig {
+            id: priority as i64,
+            word_index: 1,
+            bit_index,
+            name: message.to_string(),
+            message: message.to_string(),
+            message_off: String::new(),
+            enabled: true,
+            priority,
+            color: "#ffffff".to_string(),
+            font_size: 48,
+            position: "center".to_string(),
+            font_family: "Arial".to_string(),
+            font_weight: "bold".to_string(),
+            text_shadow: false,
+            letter_spacing: 0,
+            use_template: false,
+            message_template: String::new(),
+            action_type: action_type.to_string(),
+            video_id: (action_type == "video").then_some(7),
+        }
+    }
+
+    fn video(id: i64) -> VideoConfig {
+        VideoConfig {
+            id,
+            name: format!("video{}", id),
+            file_path: format!("video{}.mp4", id),
+            duration: 10,
+            enabled: true,
+            priority: 0,
+            description: String::new(),
+            display_order: 0,
+        }
+    }
+
+    fn decide_with(state: &mut EngineState, words: &[(usize, f64)]) -> (DisplayMode, Option<String>, Option<String>) {
+        state.frame = Some(Arc::new(frame(words)));
+        let (mode, message, video) = decide(state);
+        (mode, message.map(|m| m.text), video.map(|v| v.source))
+    }
+
+    #[test]
+    fn control_bit_then_priority_then_idle() {
+        let mut state = EngineState::new(DEFAULT_PANEL_ID);
+        state.rules.bits = vec![bit(0, 20, "text", "ALTA"), bit(1, 10, "text", "BAIXA"), bit(2, 5, "text", " ")];
+        state.rules.playlist = vec![video(1)];
+        state.rules.idle_text = Some("REPOUSO".to_string());
+
+        let (mode, _, source) = decide_with(&mut state, &[(0, 1.0), (1, 3.0)]);
+        assert_eq!((mode, source.as_deref()), (DisplayMode::Video, Some("playlist")));
+        assert_eq!(decide_with(&mut state, &[(0, 0.0), (1, 3.0)]), (DisplayMode::Message, Some("ALTA".to_string()), None));
+        assert_eq!(decide_with(&mut state, &[(1, 2.0)]), (DisplayMode::Message, Some("BAIXA".to_string()), None));
+        // Bit com texto vazio não tapa o repouso
+        assert_eq!(decide_with(&mut state, &[(1, 4.0)]), (DisplayMode::Idle, Some("REPOUSO".to_string()), None));
+
+        // Sem vídeos para rodar, o bit de controle não conta
+        state.rules.playlist.clear();
+        assert_eq!(decide_with(&mut state, &[(0, 1.0), (1, 2.0)]).1.as_deref(), Some("BAIXA"));
+    }
+
+    #[test]
+    fn video_bit_falls_back_when_video_missing() {
+        let mut state = EngineState::new(DEFAULT_PANEL_ID);
+        state.rules.bits = vec![bit(0, 20, "video", "VIDEO"), bit(1, 10, "text", "TEXTO")];
+
+        assert_eq!(decide_with(&mut state, &[(1, 3.0)]), (DisplayMode::Message, Some("TEXTO".to_string()), None));
+
+        state.rules.videos.insert(7, video(7));
+        assert_eq!(decide_with(&mut state, &[(1, 3.0)]), (DisplayMode::Video, None, Some("bit".to_string())));
+    }
+
+    #[test]
+    fn bit_value_bounds() {
+        let data = frame(&[(0, 0x8001 as f64)]);
+        assert!(bit_value(&data, 0, 0));
+        assert!(bit_value(&data, 0, 15));
+        assert!(!bit_value(&data, 0, 1));
+        assert!(!bit_value(&data, 0, 16));
+        assert!(!bit_value(&data, 0, -1));
+        assert!(!bit_value(&data, 5, 0));
+    }
+
+    #[test]
+    fn renders_real_int_word_and_modifiers() {
+        // 1.5f32 = 0x3FC0_0000 → Word[0] = 0x3FC0, Word[1] = 0
+        let data = frame(&[(0, 0x3FC0 as f64), (1, 0.0), (2, 65535.0), (3, 125.0)]);
+
+        assert_eq!(render_template("{Real[0]} {Real[0]:1} {Real[0]:0}", &data), "1.50 1.5 2");
+        assert_eq!(render_template("{Int[2]} {Word[2]}", &data), "-1 65535");
+        assert_eq!(render_template("{Int[3]/10} {Int[3]/2.5} {Word[3]/0}", &data), "12.5 50.00 ---");
+        assert_eq!(render_template("{Int[3]*1.5} {Int[2]*2}", &data), "188 -2");
+        assert_eq!(render_template("{Real[3]} {Int[9]} {texto} {Int[3]/x}", &data), "{...} {...} {texto} {Int[3]/x}");
+    }
+
+    #[test]
+    fn bad_quality_shows_placeholder() {
+        let mut data = frame(&[(0, 0x3FC0 as f64), (1, 0.0), (3, 125.0)]);
+        data.quality.insert("Word[1]".to_string(), Quality::Stale);
+        data.quality.insert("Word[3]".to_string(), Quality::Substituted);
+
+        assert_eq!(render_template("{Real[0]}|{Word[1]}|{Int[3]}", &data), "---|---|125");
+    }
+}
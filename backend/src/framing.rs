@@ -0,0 +1,361 @@
+// framing.rs - PROTOCOLO ENQUADRADO OPCIONAL (CABEÇALHO + SEQUÊNCIA + CRC32)
+// ============================================================================
+// No modo "raw" o stream TSEND_C é cortado a cada packet_size bytes: um único
+// write curto desalinha todos os pacotes seguintes. No modo enquadrado cada
+// pacote vai dentro de um frame:
+//
+//   offset 0   "PLCF"            magic (4 bytes)
+//   offset 4   seq      u32 BE   contador incrementado pelo PLC a cada frame
+//   offset 8   len      u32 BE   tamanho do payload (= packet_size de um layout registado)
+//   offset 12  payload  len bytes
+//   offset 12+len  crc  u32 BE   CRC-32 (IEEE 802.3) de magic..payload
+//
+// Um frame inválido (CRC, tamanho) é descartado e o decoder ressincroniza no
+// próximo magic. Saltos no contador contam como frames perdidos.
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+
+pub const FRAME_MAGIC: [u8; 4] = *b"PLCF";
+pub const FRAME_HEADER_LEN: usize = 12;
+pub const FRAME_CRC_LEN: usize = 4;
+
+/// Bytes de enquadramento por frame (cabeçalho + CRC)
+pub const FRAME_OVERHEAD: usize = FRAME_HEADER_LEN + FRAME_CRC_LEN;
+
+/// Modo de enquadramento das conexões TSEND_C
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FramingMode {
+    Raw,    // Pacotes de packet_size bytes seguidos (comportamento original)
+    Framed, // Apenas frames PLCF
+    Auto,   // Decide por conexão pelos primeiros 4 bytes recebidos
+}
+
+impl FramingMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FramingMode::Raw => "raw",
+            FramingMode::Framed => "framed",
+            FramingMode::Auto => "auto",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "raw" => Some(FramingMode::Raw),
+            "framed" => Some(FramingMode::Framed),
+            "auto" => Some(FramingMode::Auto),
+            _ => None,
+        }
+    }
+}
+
+// ============================================================================
+// CRC-32 (IEEE 802.3, polinómio refletido 0xEDB88320)
+// ============================================================================
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Constrói um frame completo (usado pelo simulador e útil para testes do lado PLC)
+#[allow(dead_code)]
+pub fn encode_frame(seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + FRAME_OVERHEAD);
+    frame.extend_from_slice(&FRAME_MAGIC);
+    frame.extend_from_slice(&seq.to_be_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    let crc = crc32(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+// ============================================================================
+// DECODER COM RESSINCRONIZAÇÃO
+// ============================================================================
+
+/// Estatísticas de perdas de uma conexão enquadrada
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FrameStats {
+    pub frames_ok: u64,
+    pub crc_errors: u64,
+    pub invalid_headers: u64,     // Magic encontrado mas tamanho sem layout registado
+    pub discarded_bytes: u64,     // Lixo descartado até ao próximo magic
+    pub resyncs: u64,             // Perdas de alinhamento (até ao próximo frame válido)
+    pub lost_frames: u64,         // Saltos no contador de sequência
+    pub out_of_order: u64,        // Sequência repetida ou a andar para trás
+    pub sequence_resets: u64,     // Contador reiniciado (recuo grande ou vários seguidos)
+    pub last_seq: Option<u32>,
+}
+
+/// Recuo máximo ainda tratado como frame atrasado; acima disso o contador foi reiniciado
+const REORDER_WINDOW: u32 = 16;
+/// Frames fora de ordem seguidos até aceitar a nova sequência como base
+const MAX_OUT_OF_ORDER_RUN: u32 = 3;
+
+pub struct FrameDecoder {
+    expected_seq: Option<u32>,
+    out_of_order_run: u32,
+    in_sync: bool,
+    stats: FrameStats,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self { expected_seq: None, out_of_order_run: 0, in_sync: true, stats: FrameStats::default() }
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    /// Extrai o próximo payload válido de `buffer` (consumindo os bytes usados
+    /// ou descartados). Devolve None quando faltam bytes para um frame completo.
+    /// `payload_sizes` = tamanhos aceites (layouts registados ou o layout já escolhido).
+    pub fn next_frame(&mut self, buffer: &mut Vec<u8>, payload_sizes: &[usize]) -> Option<Vec<u8>> {
+        loop {
+            // ── Alinhar no magic ──
+            match buffer.windows(FRAME_MAGIC.len()).position(|w| w == FRAME_MAGIC) {
+                Some(0) => {}
+                Some(pos) => self.discard(buffer, pos),
+                None => {
+                    // Manter a cauda: pode ser o início de um magic
+                    let keep = buffer.len().min(FRAME_MAGIC.len() - 1);
+                    let pos = buffer.len() - keep;
+                    if pos > 0 {
+                        self.discard(buffer, pos);
+                    }
+                    return None;
+                }
+            }
+
+            if buffer.len() < FRAME_HEADER_LEN {
+                return None;
+            }
+
+            let seq = u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
+            let len = u32::from_be_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]) as usize;
+            if !payload_sizes.contains(&len) {
+                // Falso magic ou PLC com outro layout: saltar e procurar o seguinte
+                self.stats.invalid_headers += 1;
+                self.discard(buffer, 1);
+                continue;
+            }
+
+            let frame_len = FRAME_HEADER_LEN + len + FRAME_CRC_LEN;
+            if buffer.len() < frame_len {
+                return None;
+            }
+
+            let crc_offset = FRAME_HEADER_LEN + len;
+            let received_crc = u32::from_be_bytes([
+                buffer[crc_offset], buffer[crc_offset + 1], buffer[crc_offset + 2], buffer[crc_offset + 3],
+            ]);
+            if crc32(&buffer[..crc_offset]) != received_crc {
+                self.stats.crc_errors += 1;
+                self.discard(buffer, 1);
+                continue;
+            }
+
+            let payload = buffer[FRAME_HEADER_LEN..crc_offset].to_vec();
+            buffer.drain(..frame_len);
+            self.in_sync = true;
+            self.track_sequence(seq);
+            self.stats.frames_ok += 1;
+            return Some(payload);
+        }
+    }
+
+    fn discard(&mut self, buffer: &mut Vec<u8>, count: usize) {
+        buffer.drain(..count);
+        self.stats.discarded_bytes += count as u64;
+        if self.in_sync {
+            self.in_sync = false;
+            self.stats.resyncs += 1;
+        }
+    }
+
+    fn track_sequence(&mut self, seq: u32) {
+        if let Some(expected) = self.expected_seq {
+            let gap = seq.wrapping_sub(expected);
+            if gap >= 0x8000_0000 {
+                // Atrasado ou repetido: não mexer no esperado, senão os frames
+                // seguintes contariam de novo como perdidos
+                self.out_of_order_run += 1;
+                if expected.wrapping_sub(seq) <= REORDER_WINDOW && self.out_of_order_run < MAX_OUT_OF_ORDER_RUN {
+                    self.stats.out_of_order += 1;
+                    self.stats.last_seq = Some(seq);
+                    return;
+                }
+                // PLC reiniciou o contador: recomeçar a partir daqui
+                self.stats.sequence_resets += 1;
+            } else {
+                self.stats.lost_frames += gap as u64;
+            }
+        }
+        self.out_of_order_run = 0;
+        self.expected_seq = Some(seq.wrapping_add(1));
+        self.stats.last_seq = Some(seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn decodes_after_leading_garbage() {
+        let mut decoder = FrameDecoder::new();
+        let mut buffer = b"xxPLxx".to_vec();
+        buffer.extend(encode_frame(1, &[1, 2, 3, 4]));
+
+        assert_eq!(decoder.next_frame(&mut buffer, &[4]), Some(vec![1, 2, 3, 4]));
+        assert!(buffer.is_empty());
+        assert_eq!(decoder.stats().discarded_bytes, 6);
+        assert_eq!(decoder.stats().resyncs, 1);
+        assert_eq!(decoder.stats().frames_ok, 1);
+    }
+
+    #[test]
+    fn reassembles_short_writes() {
+        let mut decoder = FrameDecoder::new();
+        let mut stream = encode_frame(7, &[9; 8]);
+        stream.extend(encode_frame(8, &[5; 8]));
+
+        let mut buffer = Vec::new();
+        let mut payloads = Vec::new();
+        for chunk in stream.chunks(3) {
+            buffer.extend_from_slice(chunk);
+            while let Some(payload) = decoder.next_frame(&mut buffer, &[8]) {
+                payloads.push(payload);
+            }
+        }
+
+        assert_eq!(payloads, vec![vec![9; 8], vec![5; 8]]);
+        assert_eq!(decoder.stats().discarded_bytes, 0);
+        assert_eq!(decoder.stats().resyncs, 0);
+        assert_eq!(decoder.stats().last_seq, Some(8));
+    }
+
+    #[test]
+    fn keeps_partial_magic_at_tail() {
+        let mut decoder = FrameDecoder::new();
+        let frame = encode_frame(1, &[1, 2]);
+        let mut buffer = b"garbagePLC".to_vec();
+
+        assert_eq!(decoder.next_frame(&mut buffer, &[2]), None);
+        assert_eq!(buffer, b"PLC");
+        buffer.extend_from_slice(&frame[3..]);
+        assert_eq!(decoder.next_frame(&mut buffer, &[2]), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn rejects_bad_crc_and_resyncs() {
+        let mut decoder = FrameDecoder::new();
+        let mut buffer = encode_frame(1, &[1, 2, 3, 4]);
+        let last = buffer.len() - 1;
+        buffer[last] ^= 0xFF;
+        buffer.extend(encode_frame(2, &[5, 6, 7, 8]));
+
+        assert_eq!(decoder.next_frame(&mut buffer, &[4]), Some(vec![5, 6, 7, 8]));
+        assert_eq!(decoder.stats().crc_errors, 1);
+        assert_eq!(decoder.stats().frames_ok, 1);
+        assert_eq!(decoder.stats().resyncs, 1);
+    }
+
+    #[test]
+    fn rejects_unregistered_length() {
+        let mut decoder = FrameDecoder::new();
+        let mut buffer = encode_frame(1, &[0; 6]);
+        buffer.extend(encode_frame(2, &[3; 4]));
+
+        assert_eq!(decoder.next_frame(&mut buffer, &[4]), Some(vec![3; 4]));
+        assert_eq!(decoder.stats().invalid_headers, 1);
+        assert_eq!(decoder.stats().frames_ok, 1);
+
+        // Tamanho enorme no cabeçalho: não pode ficar à espera de 4 GB
+        let mut buffer = FRAME_MAGIC.to_vec();
+        buffer.extend_from_slice(&1u32.to_be_bytes());
+        buffer.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(decoder.next_frame(&mut buffer, &[4]), None);
+        assert!(buffer.len() < FRAME_MAGIC.len());
+    }
+
+    fn feed(decoder: &mut FrameDecoder, seqs: &[u32]) {
+        let mut buffer = Vec::new();
+        for &seq in seqs {
+            buffer.extend(encode_frame(seq, &[0; 2]));
+        }
+        while decoder.next_frame(&mut buffer, &[2]).is_some() {}
+    }
+
+    #[test]
+    fn counts_lost_and_out_of_order() {
+        let mut decoder = FrameDecoder::new();
+        feed(&mut decoder, &[10, 11, 14, 13, 15, 15, 16]);
+
+        assert_eq!(decoder.stats().frames_ok, 7);
+        assert_eq!(decoder.stats().lost_frames, 2);   // 12 e 13 (13 chegou atrasado)
+        assert_eq!(decoder.stats().out_of_order, 2);  // 13 e o 15 repetido
+        assert_eq!(decoder.stats().last_seq, Some(16));
+    }
+
+    #[test]
+    fn sequence_wraps_around() {
+        let mut decoder = FrameDecoder::new();
+        feed(&mut decoder, &[u32::MAX - 1, u32::MAX, 0, 2]);
+
+        assert_eq!(decoder.stats().lost_frames, 1);
+        assert_eq!(decoder.stats().out_of_order, 0);
+
+        feed(&mut decoder, &[u32::MAX]);
+        assert_eq!(decoder.stats().out_of_order, 1);
+    }
+
+    #[test]
+    fn counter_reset_becomes_new_base() {
+        let mut decoder = FrameDecoder::new();
+        feed(&mut decoder, &[5000, 5001, 0, 1, 3]);
+
+        assert_eq!(decoder.stats().sequence_resets, 1);
+        assert_eq!(decoder.stats().out_of_order, 0);
+        assert_eq!(decoder.stats().lost_frames, 1);   // só o 2
+        assert_eq!(decoder.stats().last_seq, Some(3));
+
+        // Recuo pequeno: atrasados até MAX_OUT_OF_ORDER_RUN seguidos, depois nova base
+        let mut decoder = FrameDecoder::new();
+        feed(&mut decoder, &[50, 51, 52, 40, 41, 42, 43, 44]);
+
+        assert_eq!(decoder.stats().out_of_order, 2);  // 40 e 41
+        assert_eq!(decoder.stats().sequence_resets, 1);
+        assert_eq!(decoder.stats().lost_frames, 0);
+    }
+}
@@ -0,0 +1,131 @@
+// ip_filter.rs - REGRAS DE IP / CIDR PARA A ALLOWLIST DE PLCs
+// ============================================================================
+// Aceita um IP simples ("192.168.1.33") ou uma rede CIDR ("192.168.1.0/24",
+// "fd00::/64"). IPs IPv4 mapeados em IPv6 (::ffff:a.b.c.d) são normalizados.
+// ============================================================================
+
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpRule {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRule {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+
+        let network: IpAddr = address.parse()
+            .map_err(|_| format!("Endereço IP inválido: {}", value))?;
+        let network = network.to_canonical();
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| format!("Prefixo CIDR inválido: {} (0..{})", value, max_prefix))?,
+            None => max_prefix,
+        };
+
+        Ok(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn single_address_is_exact_match() {
+        let rule = IpRule::parse(" 192.168.1.33 ").unwrap();
+        assert_eq!(rule, IpRule::parse("192.168.1.33/32").unwrap());
+        assert!(rule.contains(&ip("192.168.1.33")));
+        assert!(!rule.contains(&ip("192.168.1.34")));
+
+        let rule = IpRule::parse("fd00::1").unwrap();
+        assert_eq!(rule, IpRule::parse("fd00::1/128").unwrap());
+        assert!(rule.contains(&ip("fd00::1")));
+        assert!(!rule.contains(&ip("fd00::2")));
+    }
+
+    #[test]
+    fn ipv4_networks() {
+        let rule = IpRule::parse("192.168.1.0/24").unwrap();
+        assert!(rule.contains(&ip("192.168.1.0")));
+        assert!(rule.contains(&ip("192.168.1.255")));
+        assert!(!rule.contains(&ip("192.168.2.1")));
+        assert!(!rule.contains(&ip("fd00::1")));
+
+        let any = IpRule::parse("0.0.0.0/0").unwrap();
+        assert!(any.contains(&ip("10.1.2.3")));
+        assert!(any.contains(&ip("255.255.255.255")));
+        assert!(!any.contains(&ip("::1")));
+    }
+
+    #[test]
+    fn ipv6_networks() {
+        let rule = IpRule::parse("fd00:1234::/32").unwrap();
+        assert!(rule.contains(&ip("fd00:1234:ffff::1")));
+        assert!(!rule.contains(&ip("fd00:1235::1")));
+        assert!(!rule.contains(&ip("10.0.0.1")));
+
+        let any = IpRule::parse("::/0").unwrap();
+        assert!(any.contains(&ip("2001:db8::1")));
+        assert!(!any.contains(&ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_match_ipv4_rules() {
+        let rule = IpRule::parse("10.0.0.0/8").unwrap();
+        assert!(rule.contains(&ip("::ffff:10.20.30.40")));
+        assert!(!rule.contains(&ip("::ffff:11.0.0.1")));
+
+        // A regra também é normalizada
+        let rule = IpRule::parse("::ffff:192.168.1.33").unwrap();
+        assert_eq!(rule, IpRule::parse("192.168.1.33").unwrap());
+        assert!(rule.contains(&ip("192.168.1.33")));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for value in [
+            "",
+            "garbage",
+            "192.168.1",
+            "192.168.1.256",
+            "192.168.1.0/33",
+            "192.168.1.0/",
+            "192.168.1.0/-1",
+            "192.168.1.0/abc",
+            "192.168.1.0/24/8",
+            "fd00::/129",
+            "fd00::/300",
+            "::ffff:10.0.0.0/40",
+        ] {
+            assert!(IpRule::parse(value).is_err(), "{:?} devia ser recusado", value);
+        }
+    }
+}
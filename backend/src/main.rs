@@ -1,15 +1,33 @@
 // PLC Backend Server - EDP Industrial
 // Servidor standalone: REST API + SSE + Video Streaming + PLC TCP
 
+mod capture;
 mod database;
+mod display_engine;
+mod framing;
+mod ip_filter;
+mod media_library;
+mod modbus_client;
+mod polling_client;
+mod s7_client;
+mod packet_layout;
+mod playlist;
+mod proof_of_play;
+mod plc_stream;
 mod tcp_server;
 mod web_server;
+mod ws_server;
 
 use std::sync::Arc;
-use tokio::sync::{Mutex, broadcast};
+use tokio::sync::{Mutex, RwLock, broadcast};
+use capture::FrameCapture;
 use database::Database;
-use tcp_server::TcpServer;
-use tcp_server::PlcData;
+use media_library::MediaLibrary;
+use framing::FramingMode;
+use polling_client::PollingManager;
+use tcp_server::{TcpServer, FRAMING_MODE_KEY};
+use tcp_server::TcpEvent;
+use plc_stream::{PlcFrame, ReplayBuffer};
 
 const WEB_PORT: u16 = 3001;
 const TCP_PORT: u16 = 8502;
@@ -55,8 +73,21 @@ async fn main() {
     // Log de inicialização
     let _ = db.add_system_log("info", "database", "Sistema iniciado", &format!("DB: {}", db_path)).await;
 
+    // Sessões que ficaram abertas (backend terminou sem fechar as conexões)
+    match db.close_interrupted_sessions().await {
+        Ok(0) => {}
+        Ok(n) => println!("🔄 {} sessão(ões) PLC interrompida(s) fechada(s) no histórico", n),
+        Err(e) => eprintln!("⚠️ Erro ao fechar sessões interrompidas: {:?}", e),
+    }
+
     // ── 2. Criar broadcast channel para PLC data ──
-    let (plc_tx, _) = broadcast::channel::<PlcData>(1000);
+    let (plc_tx, _) = broadcast::channel::<PlcFrame>(1000);
+    let replay_frames = std::env::var("SSE_REPLAY_FRAMES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(plc_stream::DEFAULT_REPLAY_FRAMES);
+    let replay = Arc::new(RwLock::new(ReplayBuffer::new(replay_frames)));
+    let (event_tx, _) = broadcast::channel::<TcpEvent>(256);
 
     // ── 3. Iniciar TCP server para PLC ──
     let tcp_port = std::env::var("TCP_PORT")
@@ -66,8 +97,70 @@ async fn main() {
 
     let mut tcp_server = TcpServer::new(tcp_port);
     tcp_server.set_database(Arc::downgrade(&db));
+
+    // Gravação de frames brutos (opt-in: CAPTURE_ENABLED=1 ou via API)
+    let capture_dir = std::env::var("CAPTURE_DIR").unwrap_or_else(|_| format!("{}/captures", db_dir));
+    let capture = Arc::new(FrameCapture::new(&capture_dir));
+    tcp_server.set_capture(capture.clone());
+
+    // Layout do pacote PLC persistido (fallback: UDT_TCP_Data padrão)
+    match db.get_active_packet_layout().await {
+        Ok(Some(layout)) => {
+            if let Err(e) = tcp_server.set_packet_layout(layout).await {
+                eprintln!("⚠️ Layout do pacote inválido, usando padrão: {}", e);
+            }
+        }
+        Ok(None) => println!("📐 Nenhum layout ativo na base de dados, usando padrão"),
+        Err(e) => eprintln!("⚠️ Erro ao carregar layout do pacote, usando padrão: {:?}", e),
+    }
+    // Restantes layouts registados (PLCs com firmwares/UDTs diferentes)
+    match db.get_packet_layouts().await {
+        Ok(layouts) => tcp_server.set_layouts(layouts).await,
+        Err(e) => eprintln!("⚠️ Erro ao carregar layouts registados: {:?}", e),
+    }
+
+    // Inventário de PLCs (nomes amigáveis + aviso de PLCs esperados em falta)
+    if let Some(secs) = std::env::var("PLC_EXPECTED_GRACE_SECS").ok().and_then(|v| v.parse().ok()) {
+        tcp_server.set_expected_grace_secs(secs);
+    }
+    if let Some(ms) = std::env::var("PLC_CLOCK_DRIFT_WARN_MS").ok().and_then(|v| v.parse().ok()) {
+        tcp_server.set_clock_drift_warn_ms(ms);
+    }
+    match db.get_plc_devices().await {
+        Ok(devices) => tcp_server.set_devices(devices).await,
+        Err(e) => eprintln!("⚠️ Erro ao carregar inventário de PLCs: {:?}", e),
+    }
+
+    // PLCs bloqueados persistidos + modo allowlist
+    match db.get_blocked_plcs().await {
+        Ok(blocked) => tcp_server.set_blacklist(blocked.into_iter().map(|b| b.ip).collect()).await,
+        Err(e) => eprintln!("⚠️ Erro ao carregar PLCs bloqueados: {:?}", e),
+    }
+    if let Err(e) = tcp_server.reload_allowlist().await {
+        eprintln!("⚠️ Erro ao carregar allowlist: {}", e);
+    }
+
+    // Enquadramento TSEND_C (raw / framed / auto)
+    if let Ok(Some(mode)) = db.get_display_config(FRAMING_MODE_KEY).await {
+        match FramingMode::parse(&mode) {
+            Some(mode) => tcp_server.set_framing_mode(mode).await,
+            None => eprintln!("⚠️ Modo de enquadramento inválido na base de dados: {}", mode),
+        }
+    }
+
     let tcp_server = Arc::new(tcp_server);
 
+    if std::env::var("CAPTURE_ENABLED").map(|v| v == "1" || v == "true").unwrap_or(false) {
+        let max_mb = std::env::var("CAPTURE_MAX_FILE_MB").ok().and_then(|v| v.parse::<u64>().ok());
+        let max_files = std::env::var("CAPTURE_MAX_FILES").ok().and_then(|v| v.parse().ok());
+        if let Err(e) = capture.start_recording(
+            max_mb.map(|mb| mb * 1024 * 1024).unwrap_or(capture::DEFAULT_MAX_FILE_BYTES),
+            max_files.unwrap_or(capture::DEFAULT_MAX_FILES),
+        ) {
+            eprintln!("⚠️ Erro ao iniciar gravação de frames: {}", e);
+        }
+    }
+
     let tcp_clone = tcp_server.clone();
     tokio::spawn(async move {
         if let Err(e) = tcp_clone.start().await {
@@ -75,22 +168,101 @@ async fn main() {
         }
     });
 
-    // Forward PLC data do TCP server para o broadcast channel (para SSE)
+    // Forward PLC data do TCP server para o broadcast channel (para SSE),
+    // numerando cada frame e guardando-o no buffer de replay (Last-Event-ID)
     let mut rx = tcp_server.subscribe();
     let plc_tx_clone = plc_tx.clone();
+    let replay_clone = replay.clone();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(data) => {
+                    let frame = replay_clone.write().await.push(data);
+                    let _ = plc_tx_clone.send(frame);
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    eprintln!("⚠️ Reencaminhamento PLC atrasado: {} frames perdidos", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    // Forward eventos do TCP server (plc-connected, tcp-stats, ...) para SSE
+    let mut event_rx = tcp_server.subscribe_events();
+    let event_tx_clone = event_tx.clone();
     tokio::spawn(async move {
-        while let Ok(data) = rx.recv().await {
-            let _ = plc_tx_clone.send(data);
+        loop {
+            match event_rx.recv().await {
+                Ok(event) => { let _ = event_tx_clone.send(event); }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
         }
     });
 
     let _ = db.add_system_log("info", "tcp", "Servidor TCP iniciado", &format!("Porta: {}", tcp_port)).await;
 
+    // ── 3b. Clientes em polling configurados (Modbus TCP, S7) ──
+    let pollers = Arc::new(PollingManager::new(tcp_server.clone()));
+    match db.get_modbus_devices().await {
+        Ok(devices) => {
+            for device in devices {
+                modbus_client::apply(&pollers, device).await;
+            }
+        }
+        Err(e) => eprintln!("⚠️ Erro ao carregar equipamentos Modbus: {:?}", e),
+    }
+    match db.get_s7_devices().await {
+        Ok(devices) => {
+            for device in devices {
+                s7_client::apply(&pollers, device).await;
+            }
+        }
+        Err(e) => eprintln!("⚠️ Erro ao carregar equipamentos S7: {:?}", e),
+    }
+
+    // ── 3c. Motor de exibição do painel (estado único para todos os ecrãs) ──
+    let display = Arc::new(display_engine::DisplayEngine::new(db.clone()));
+    display.spawn(plc_tx.subscribe());
+
+    // ── 3d. Biblioteca de vídeos (uploads pela API) ──
+    let media_dir = std::env::var("MEDIA_DIR").unwrap_or_else(|_| format!("{}/media", db_dir));
+    let max_upload_bytes = std::env::var("MEDIA_MAX_UPLOAD_MB")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|mb| mb * 1024 * 1024)
+        .unwrap_or(media_library::DEFAULT_MAX_UPLOAD_BYTES);
+    let extra_roots = std::env::var_os("MEDIA_ROOTS")
+        .map(|roots| std::env::split_paths(&roots).filter(|p| !p.as_os_str().is_empty()).collect())
+        .unwrap_or_default();
+    let media = match MediaLibrary::open(&media_dir, extra_roots, max_upload_bytes) {
+        Ok(media) => Arc::new(media),
+        Err(e) => {
+            eprintln!("Erro ao abrir biblioteca de vídeos: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("🎬 Biblioteca de vídeos: {} (máx. {} MB por ficheiro)", media.dir().display(), max_upload_bytes / 1024 / 1024);
+    for root in &media.roots()[1..] {
+        println!("🎬 Raiz de media extra: {}", root.display());
+    }
+    // Caminhos absolutos antigos → relativos às raízes (só esses são servidos)
+    if let Err(e) = media.migrate_video_paths(&db).await {
+        eprintln!("⚠️ Erro ao migrar caminhos dos vídeos: {:?}", e);
+    }
+
     // ── 4. Criar app state partilhado ──
     let state = Arc::new(web_server::AppState {
         database: db,
         tcp_server: Arc::new(Mutex::new(Some(tcp_server))),
+        pollers,
+        capture,
         plc_broadcast: plc_tx,
+        replay,
+        tcp_events: event_tx,
+        display,
+        media,
     });
 
     // ── 5. Iniciar web server (bloqueia aqui) ──
@@ -0,0 +1,429 @@
+// media_library.rs - BIBLIOTECA DE VÍDEOS GERIDA PELO SERVIDOR
+// ============================================================================
+// Os vídeos enviados por POST /api/videos/upload (multipart) são escritos em
+// streaming num diretório próprio (MEDIA_DIR, padrão <DB_DIR>/media):
+//   - só extensões de vídeo conhecidas (VIDEO_EXTENSIONS)
+//   - tamanho máximo por ficheiro (MEDIA_MAX_UPLOAD_MB)
+//   - escrita num ficheiro temporário (.upload-*.part) e rename no fim, para
+//     nunca ficar um vídeo truncado com nome válido
+//   - nomes seguros e sem colisões ("Campanha Verão.mp4" → "Campanha_Ver_o.mp4",
+//     depois "Campanha_Ver_o-2.mp4")
+// Apagar um vídeo (delete_video / clear_all_videos) remove o ficheiro se estiver
+// na biblioteca e nenhum outro vídeo o usar. Ficheiros das outras raízes
+// (copiados à mão) nunca são apagados.
+//
+// RAÍZES DE MEDIA
+// /api/video só serve ficheiros dentro das raízes: a biblioteca e as pastas
+// extra de MEDIA_ROOTS (separadas por ':'). video_configs.file_path é relativo
+// às raízes ("campanha.mp4", "edp/verao.mp4"), procurado pela ordem
+// biblioteca → MEDIA_ROOTS. Um pedido é recusado se:
+//   - for absoluto, tiver "..", ".", "\" ou componentes escondidos (".x")
+//   - não tiver extensão de vídeo
+//   - depois de resolver symlinks (canonicalize) sair da raiz
+// Caminhos absolutos antigos são convertidos no arranque (migrate_video_paths).
+// ============================================================================
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::database::Database;
+
+/// Extensões aceites (as mesmas que /api/video sabe servir)
+pub const VIDEO_EXTENSIONS: &[&str] = &["mp4", "webm", "mkv", "mov", "avi", "ogg", "ogv"];
+
+pub const DEFAULT_MAX_UPLOAD_BYTES: u64 = 2048 * 1024 * 1024;
+
+const TEMP_PREFIX: &str = ".upload-";
+const TEMP_EXTENSION: &str = "part";
+const MAX_NAME_LEN: usize = 80;
+
+static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Pedido recusado por /api/video
+#[derive(Debug)]
+pub enum MediaPathError {
+    Invalid(String), // Traversal, caminho absoluto, não é vídeo...
+    Escapes(String), // Symlink (ou caminho) que resolve para fora da raiz
+    NotFound,
+}
+
+pub struct MediaLibrary {
+    dir: PathBuf,
+    roots: Vec<PathBuf>, // Canónicas; a biblioteca é a primeira
+    max_upload_bytes: u64,
+}
+
+impl MediaLibrary {
+    /// Cria o diretório (se preciso) e apaga uploads interrompidos.
+    /// `extra_roots`: outras pastas servidas (as inexistentes são ignoradas com aviso)
+    pub fn open(dir: impl Into<PathBuf>, extra_roots: Vec<PathBuf>, max_upload_bytes: u64) -> Result<Self, String> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let dir = dir.canonicalize().map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+        let stale: Vec<PathBuf> = std::fs::read_dir(&dir)
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| is_temp_file(p))
+            .collect();
+        for path in stale {
+            if std::fs::remove_file(&path).is_ok() {
+                println!("🗑️ Upload interrompido removido: {}", path.display());
+            }
+        }
+
+        let mut roots = vec![dir.clone()];
+        for root in extra_roots {
+            match root.canonicalize() {
+                Ok(root) if root.is_dir() => {
+                    if !roots.contains(&root) {
+                        roots.push(root);
+                    }
+                }
+                _ => eprintln!("⚠️ Raiz de media ignorada (não é uma pasta): {}", root.display()),
+            }
+        }
+
+        Ok(Self { dir, roots, max_upload_bytes })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    pub fn max_upload_bytes(&self) -> u64 {
+        self.max_upload_bytes
+    }
+
+    /// Começa a receber um ficheiro; rejeita extensões que não são de vídeo
+    pub async fn begin_upload(&self, original_name: &str) -> Result<Upload, String> {
+        let extension = video_extension(original_name)
+            .ok_or_else(|| format!("Extensão não suportada: {} (aceites: {})", original_name, VIDEO_EXTENSIONS.join(", ")))?;
+        let temp = self.dir.join(format!(
+            "{}{}-{}.{}",
+            TEMP_PREFIX,
+            chrono::Local::now().format("%Y%m%d%H%M%S%f"),
+            UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed),
+            TEMP_EXTENSION
+        ));
+        let file = File::create(&temp).await.map_err(|e| format!("{}: {}", temp.display(), e))?;
+        Ok(Upload {
+            dir: self.dir.clone(),
+            stem: safe_stem(original_name),
+            extension,
+            temp,
+            file,
+            written: 0,
+        })
+    }
+
+    /// Ficheiro real de um caminho relativo pedido em /api/video
+    pub fn resolve(&self, file_path: &str) -> Result<PathBuf, MediaPathError> {
+        let relative = validate_relative(file_path).map_err(MediaPathError::Invalid)?;
+        let mut escaped = None;
+        for root in &self.roots {
+            let Ok(real) = root.join(&relative).canonicalize() else {
+                continue;
+            };
+            if !real.starts_with(root) {
+                escaped = Some(real);
+                continue;
+            }
+            if real.is_file() {
+                return Ok(real);
+            }
+        }
+        match escaped {
+            Some(real) => Err(MediaPathError::Escapes(format!("{} → {}", file_path, real.display()))),
+            None => Err(MediaPathError::NotFound),
+        }
+    }
+
+    /// Caminho a gravar em video_configs.file_path: relativo às raízes.
+    /// Aceita um relativo válido ou um absoluto dentro de uma raiz.
+    pub fn library_path(&self, file_path: &str) -> Result<String, String> {
+        let file_path = file_path.trim();
+        let path = Path::new(file_path);
+        if !path.is_absolute() {
+            validate_relative(file_path)?;
+            return Ok(file_path.to_string());
+        }
+        // Ficheiro existente: symlinks resolvidos; senão compara o caminho tal como está
+        let real = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let relative = self.roots.iter()
+            .find_map(|root| real.strip_prefix(root).ok())
+            .ok_or_else(|| format!("Vídeo fora das raízes de media ({}): {}", self.roots_list(), file_path))?;
+        let relative = relative.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        validate_relative(&relative)?;
+        Ok(relative)
+    }
+
+    /// Converte os file_path absolutos de video_configs em relativos às raízes
+    pub async fn migrate_video_paths(&self, db: &Database) -> Result<(), sqlx::Error> {
+        for video in db.get_all_videos().await? {
+            if !Path::new(&video.file_path).is_absolute() {
+                continue;
+            }
+            match self.library_path(&video.file_path) {
+                Ok(relative) => {
+                    db.update_video_file_path(video.id, &relative).await?;
+                    println!("🎬 Vídeo {} migrado: {} → {}", video.id, video.file_path, relative);
+                }
+                Err(e) => eprintln!("⚠️ Vídeo {} ('{}') não será servido: {} (mova-o para a biblioteca ou acrescente a pasta a MEDIA_ROOTS)", video.id, video.name, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// O caminho é um ficheiro da biblioteca (e não de outra raiz)?
+    fn managed_file(&self, file_path: &str) -> Option<PathBuf> {
+        let relative = validate_relative(file_path).ok()?;
+        let real = self.dir.join(relative).canonicalize().ok()?;
+        (real.starts_with(&self.dir) && real.is_file()).then_some(real)
+    }
+
+    /// Apaga um ficheiro da biblioteca (caminhos fora dela são ignorados)
+    pub async fn remove(&self, file_path: &str) -> bool {
+        let Some(path) = self.managed_file(file_path) else {
+            return false;
+        };
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {
+                println!("🗑️ Vídeo removido da biblioteca: {}", path.display());
+                true
+            }
+            Err(e) => {
+                eprintln!("⚠️ Erro ao remover {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    fn roots_list(&self) -> String {
+        self.roots.iter().map(|r| r.display().to_string()).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Caminho relativo seguro: sem raiz, "..", ".", "\\", componentes escondidos, e com extensão de vídeo
+fn validate_relative(file_path: &str) -> Result<PathBuf, String> {
+    if file_path.is_empty() || file_path.contains(['\\', '\0']) {
+        return Err(format!("Caminho de vídeo inválido: {:?}", file_path));
+    }
+    let path = Path::new(file_path);
+    for component in path.components() {
+        match component {
+            Component::Normal(name) if !name.to_string_lossy().starts_with('.') => {}
+            Component::ParentDir | Component::CurDir => {
+                return Err(format!("Caminho de vídeo com travessia recusado: {}", file_path));
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(format!("Caminho de vídeo absoluto recusado: {}", file_path));
+            }
+            Component::Normal(_) => {
+                return Err(format!("Caminho de vídeo escondido recusado: {}", file_path));
+            }
+        }
+    }
+    if video_extension(file_path).is_none() {
+        return Err(format!("Não é um vídeo ({}): {}", VIDEO_EXTENSIONS.join(", "), file_path));
+    }
+    Ok(path.to_path_buf())
+}
+
+/// Ficheiro a ser recebido (temporário até finish)
+pub struct Upload {
+    dir: PathBuf,
+    stem: String,
+    extension: String,
+    temp: PathBuf,
+    file: File,
+    written: u64,
+}
+
+impl Upload {
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), String> {
+        self.file.write_all(chunk).await.map_err(|e| e.to_string())?;
+        self.written += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Fecha o temporário e dá-lhe um nome livre na biblioteca; devolve o nome (relativo)
+    pub async fn finish(mut self) -> Result<String, String> {
+        if let Err(e) = self.file.flush().await {
+            self.discard().await;
+            return Err(e.to_string());
+        }
+        for n in 1.. {
+            let name = if n == 1 {
+                format!("{}.{}", self.stem, self.extension)
+            } else {
+                format!("{}-{}.{}", self.stem, n, self.extension)
+            };
+            let path = self.dir.join(&name);
+            // Reserva o nome (create_new) e substitui-o pelo temporário
+            match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&path).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    self.discard().await;
+                    return Err(e.to_string());
+                }
+            }
+            return match tokio::fs::rename(&self.temp, &path).await {
+                Ok(()) => Ok(name),
+                Err(e) => {
+                    let _ = tokio::fs::remove_file(&path).await;
+                    self.discard().await;
+                    Err(e.to_string())
+                }
+            };
+        }
+        unreachable!()
+    }
+
+    /// Apaga o temporário (upload recusado ou interrompido)
+    pub async fn discard(self) {
+        drop(self.file);
+        let _ = tokio::fs::remove_file(&self.temp).await;
+    }
+}
+
+/// Extensão de vídeo aceite (minúsculas)
+fn video_extension(name: &str) -> Option<String> {
+    let extension = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+    VIDEO_EXTENSIONS.contains(&extension.as_str()).then_some(extension)
+}
+
+/// Nome base seguro: só ASCII alfanumérico, '-' e '_' (sem caminhos nem pontos)
+fn safe_stem(original_name: &str) -> String {
+    let base = original_name.rsplit(['/', '\\']).next().unwrap_or("");
+    let stem = Path::new(base).file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let mut safe = String::with_capacity(stem.len());
+    for c in stem.chars() {
+        let c = if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' };
+        if !(c == '_' && safe.ends_with('_')) {
+            safe.push(c);
+        }
+    }
+    let safe: String = safe.trim_matches(['_', '-']).chars().take(MAX_NAME_LEN).collect();
+    if safe.is_empty() { "video".to_string() } else { safe }
+}
+
+fn is_temp_file(path: &Path) -> bool {
+    path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with(TEMP_PREFIX))
+        && path.extension().and_then(|x| x.to_str()) == Some(TEMP_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pasta temporária apagada no fim do teste
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "plc-media-{}-{}-{}",
+                name,
+                std::process::id(),
+                UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir.canonicalize().unwrap())
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn validate_relative_rejects_unsafe_paths() {
+        for path in [
+            "",
+            "../video.mp4",
+            "a/../../video.mp4",
+            "./video.mp4",
+            "/etc/video.mp4",
+            "a\\b.mp4",
+            "video\0.mp4",
+            ".hidden.mp4",
+            "a/.git/video.mp4",
+            "video.txt",
+            "video",
+        ] {
+            assert!(validate_relative(path).is_err(), "{:?} devia ser recusado", path);
+        }
+        assert!(validate_relative("video.mp4").is_ok());
+        assert!(validate_relative("promo/2024/Video.MP4").is_ok());
+    }
+
+    #[test]
+    fn resolve_finds_files_in_roots() {
+        let library = TempDir::new("lib");
+        let extra = TempDir::new("extra");
+        std::fs::write(library.0.join("a.mp4"), b"a").unwrap();
+        std::fs::create_dir(extra.0.join("sub")).unwrap();
+        std::fs::write(extra.0.join("sub/b.webm"), b"b").unwrap();
+        let media = MediaLibrary::open(&library.0, vec![extra.0.clone()], 1024).unwrap();
+
+        assert_eq!(media.resolve("a.mp4").unwrap(), library.0.join("a.mp4"));
+        assert_eq!(media.resolve("sub/b.webm").unwrap(), extra.0.join("sub/b.webm"));
+        assert!(matches!(media.resolve("missing.mp4"), Err(MediaPathError::NotFound)));
+        assert!(matches!(media.resolve("../a.mp4"), Err(MediaPathError::Invalid(_))));
+        assert!(matches!(media.resolve(&format!("{}/a.mp4", library.0.display())), Err(MediaPathError::Invalid(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_rejects_symlink_escaping_root() {
+        let library = TempDir::new("lib");
+        let outside = TempDir::new("outside");
+        std::fs::write(outside.0.join("secret.mp4"), b"x").unwrap();
+        std::os::unix::fs::symlink(outside.0.join("secret.mp4"), library.0.join("link.mp4")).unwrap();
+        std::os::unix::fs::symlink(&outside.0, library.0.join("dir")).unwrap();
+        let media = MediaLibrary::open(&library.0, Vec::new(), 1024).unwrap();
+
+        assert!(matches!(media.resolve("link.mp4"), Err(MediaPathError::Escapes(_))));
+        assert!(matches!(media.resolve("dir/secret.mp4"), Err(MediaPathError::Escapes(_))));
+        assert!(media.managed_file("link.mp4").is_none());
+    }
+
+    #[test]
+    fn library_path_relativizes_absolute_paths() {
+        let library = TempDir::new("lib");
+        let extra = TempDir::new("extra");
+        let outside = TempDir::new("outside");
+        std::fs::create_dir(library.0.join("promo")).unwrap();
+        std::fs::write(library.0.join("promo/a.mp4"), b"a").unwrap();
+        std::fs::write(outside.0.join("b.mp4"), b"b").unwrap();
+        let media = MediaLibrary::open(&library.0, vec![extra.0.clone()], 1024).unwrap();
+
+        let inside = library.0.join("promo/a.mp4");
+        assert_eq!(media.library_path(inside.to_str().unwrap()).unwrap(), "promo/a.mp4");
+        // Ainda não existe: compara o caminho tal como está
+        let pending = extra.0.join("c.mkv");
+        assert_eq!(media.library_path(pending.to_str().unwrap()).unwrap(), "c.mkv");
+        assert_eq!(media.library_path(" promo/a.mp4 ").unwrap(), "promo/a.mp4");
+
+        assert!(media.library_path(outside.0.join("b.mp4").to_str().unwrap()).is_err());
+        assert!(media.library_path(library.0.join("notes.txt").to_str().unwrap()).is_err());
+        assert!(media.library_path("../a.mp4").is_err());
+    }
+}
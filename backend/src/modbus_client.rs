@@ -0,0 +1,431 @@
+// modbus_client.rs - CLIENTE MODBUS TCP (POLLING) COMO FONTE DE DADOS PLC
+// ============================================================================
+// Para equipamentos que só falam Modbus TCP (bombas, sensores de nível).
+// Lê periodicamente holding registers (FC03) e input registers (FC04),
+// converte-os num bloco binário e reutiliza o mesmo parser/broadcast/saúde
+// do tcp_server (PlcData, PlcDataPacket, ConnectionHealth, watchdog).
+// ============================================================================
+// MAPEAMENTO:
+//   Cada faixa de registos vira um segmento de PacketLayout. Os registos são
+//   concatenados (2 bytes cada, big-endian como no protocolo) pela ordem das
+//   faixas, e o layout resultante é parseado por parse_plc_packet.
+//   Ex: Holding 0..9 como "Word" + Input 100..103 como 2x "Real"
+//       -> Word[0..9] + Real[0..1]
+// ============================================================================
+
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::packet_layout::{ByteOrder, ClockTimezone, LayoutSegment, PacketLayout, SegmentType};
+use crate::polling_client::{spawn_polling_client, PollingManager};
+use crate::tcp_server::{ConnectionResult, DataSource, TcpServer};
+
+pub const DEFAULT_MODBUS_PORT: u16 = 502;
+
+const RESPONSE_TIMEOUT_SECS: u64 = 5;
+const MAX_REGISTERS_PER_REQUEST: u16 = 125; // Limite do protocolo (FC03/FC04)
+const MIN_POLL_INTERVAL_MS: u64 = 100;
+
+// ============================================================================
+// CONFIGURAÇÃO
+// ============================================================================
+
+/// Tipo de registo Modbus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegisterType {
+    Holding, // FC03
+    Input,   // FC04
+}
+
+impl RegisterType {
+    pub fn function_code(&self) -> u8 {
+        match self {
+            RegisterType::Holding => 0x03,
+            RegisterType::Input => 0x04,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegisterType::Holding => "holding",
+            RegisterType::Input => "input",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "holding" => Some(RegisterType::Holding),
+            "input" => Some(RegisterType::Input),
+            _ => None,
+        }
+    }
+}
+
+/// Faixa de registos lida em cada ciclo e mapeada para variáveis `{name}[i]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModbusRange {
+    pub name: String,
+    pub register_type: RegisterType,
+    pub start_address: u16,
+    pub data_type: SegmentType,
+    pub count: usize,
+    pub byte_order: ByteOrder,
+}
+
+impl ModbusRange {
+    /// Número de registos (16 bits) ocupados pela faixa
+    pub fn register_count(&self) -> usize {
+        self.count * self.data_type.fixed_size().unwrap_or(0) / 2
+    }
+}
+
+/// Equipamento Modbus TCP consultado em polling
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModbusDevice {
+    #[serde(default)]
+    pub id: i64,
+    pub name: String,
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_unit_id")]
+    pub unit_id: u8,
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_ms: u64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub ranges: Vec<ModbusRange>,
+}
+
+fn default_port() -> u16 { DEFAULT_MODBUS_PORT }
+fn default_unit_id() -> u8 { 1 }
+fn default_poll_interval() -> u64 { 500 }
+fn default_enabled() -> bool { true }
+
+impl ModbusDevice {
+    /// Identificador da sessão (usado como "IP" em saúde, cache e blacklist)
+    pub fn session_key(&self) -> String {
+        format!("modbus://{}:{}/{}", self.host, self.port, self.unit_id)
+    }
+
+    /// Layout equivalente aos registos concatenados pela ordem das faixas
+    pub fn packet_layout(&self) -> PacketLayout {
+        let mut offset = 0;
+        let segments = self.ranges.iter().map(|r| {
+            let segment = LayoutSegment {
+                name: r.name.clone(),
+                data_type: r.data_type,
+                count: r.count,
+                offset,
+                byte_order: r.byte_order,
+                max_length: None,
+                valid_min: None,
+                valid_max: None,
+                substitute: None,
+            };
+            offset += r.register_count() * 2;
+            segment
+        }).collect();
+
+        PacketLayout {
+            name: format!("modbus:{}", self.name),
+            packet_size: offset,
+            segments,
+            signature: None,
+            timestamp_segment: None,
+            clock_timezone: ClockTimezone::Utc,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Nome do equipamento não pode ser vazio".to_string());
+        }
+        if self.host.trim().is_empty() {
+            return Err("Host do equipamento não pode ser vazio".to_string());
+        }
+        if self.poll_interval_ms < MIN_POLL_INTERVAL_MS {
+            return Err(format!("Intervalo de polling mínimo: {}ms", MIN_POLL_INTERVAL_MS));
+        }
+        for range in &self.ranges {
+            if !matches!(range.data_type.fixed_size(), Some(size) if size % 2 == 0) {
+                return Err(format!("Tipo {} não alinha com registos de 16 bits", range.data_type.as_str()));
+            }
+            if range.start_address as usize + range.register_count() > u16::MAX as usize + 1 {
+                return Err(format!("Faixa {} ultrapassa o endereço 65535", range.name));
+            }
+        }
+        self.packet_layout().validate()
+    }
+}
+
+// ============================================================================
+// PROTOCOLO - Modbus TCP (MBAP + PDU)
+// ============================================================================
+
+struct ModbusConnection {
+    socket: TcpStream,
+    unit_id: u8,
+    transaction_id: u16,
+}
+
+impl ModbusConnection {
+    /// Lê `quantity` registos a partir de `start` (FC03/FC04), devolvendo os bytes crus
+    async fn read_registers(&mut self, register_type: RegisterType, start: u16, quantity: u16) -> Result<Vec<u8>, String> {
+        self.transaction_id = self.transaction_id.wrapping_add(1);
+        let fc = register_type.function_code();
+
+        // ── Pedido: MBAP (7 bytes) + FC + endereço + quantidade ──
+        let mut request = Vec::with_capacity(12);
+        request.extend_from_slice(&self.transaction_id.to_be_bytes());
+        request.extend_from_slice(&0u16.to_be_bytes()); // Protocolo (0 = Modbus)
+        request.extend_from_slice(&6u16.to_be_bytes()); // Bytes seguintes
+        request.push(self.unit_id);
+        request.push(fc);
+        request.extend_from_slice(&start.to_be_bytes());
+        request.extend_from_slice(&quantity.to_be_bytes());
+
+        self.socket.write_all(&request).await.map_err(|e| e.to_string())?;
+
+        // ── Resposta: MBAP ──
+        let mut header = [0u8; 7];
+        timeout(Duration::from_secs(RESPONSE_TIMEOUT_SECS), self.socket.read_exact(&mut header)).await
+            .map_err(|_| format!("Sem resposta em {}s", RESPONSE_TIMEOUT_SECS))?
+            .map_err(|e| e.to_string())?;
+
+        let transaction_id = u16::from_be_bytes([header[0], header[1]]);
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if transaction_id != self.transaction_id {
+            return Err(format!("Transação inesperada: {} (esperado {})", transaction_id, self.transaction_id));
+        }
+        if !(2..=256).contains(&length) {
+            return Err(format!("Comprimento MBAP inválido: {}", length));
+        }
+
+        let mut pdu = vec![0u8; length - 1];
+        timeout(Duration::from_secs(RESPONSE_TIMEOUT_SECS), self.socket.read_exact(&mut pdu)).await
+            .map_err(|_| format!("Resposta incompleta em {}s", RESPONSE_TIMEOUT_SECS))?
+            .map_err(|e| e.to_string())?;
+
+        // ── Exceção Modbus ──
+        if pdu[0] == fc | 0x80 {
+            let code = pdu.get(1).copied().unwrap_or(0);
+            return Err(format!("Exceção Modbus {:#04x} (FC{:02}, endereço {}, {} registos)", code, fc, start, quantity));
+        }
+        if pdu[0] != fc {
+            return Err(format!("Função inesperada na resposta: {:#04x}", pdu[0]));
+        }
+
+        let byte_count = pdu.get(1).copied().unwrap_or(0) as usize;
+        if byte_count != quantity as usize * 2 || pdu.len() < 2 + byte_count {
+            return Err(format!("Resposta com {} bytes (esperado {})", byte_count, quantity as usize * 2));
+        }
+
+        Ok(pdu[2..2 + byte_count].to_vec())
+    }
+
+    /// Lê todas as faixas do equipamento (em pedidos de até 125 registos)
+    async fn read_all(&mut self, device: &ModbusDevice) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        for range in &device.ranges {
+            let mut address = range.start_address;
+            let mut remaining = range.register_count();
+            while remaining > 0 {
+                let quantity = remaining.min(MAX_REGISTERS_PER_REQUEST as usize) as u16;
+                let bytes = self.read_registers(range.register_type, address, quantity).await?;
+                data.extend_from_slice(&bytes);
+                address = address.wrapping_add(quantity);
+                remaining -= quantity as usize;
+            }
+        }
+        Ok(data)
+    }
+}
+
+// ============================================================================
+// POLLING - uma tarefa por equipamento (com reconexão e backoff)
+// ============================================================================
+
+fn poller_key(id: i64) -> String {
+    format!("modbus:{}", id)
+}
+
+/// (Re)aplica a configuração de um equipamento: pára o polling anterior
+/// e inicia um novo se estiver ativo
+pub async fn apply(manager: &PollingManager, device: ModbusDevice) {
+    let key = poller_key(device.id);
+    manager.stop(&key).await;
+    if !device.enabled {
+        return;
+    }
+
+    let server = manager.server().clone();
+    let session_key = device.session_key();
+    let address = format!("{}:{}", device.host, device.port);
+    let label = format!("Modbus '{}' (unit {}, {}ms)", device.name, device.unit_id, device.poll_interval_ms);
+    let device = Arc::new(device);
+
+    let session_server = server.clone();
+    let handle = spawn_polling_client(
+        server, label, address, session_key.clone(), DataSource::Modbus,
+        move |socket, conn_id| {
+            let server = session_server.clone();
+            let device = device.clone();
+            async move { poll_session(socket, conn_id, &device, &server).await }
+        },
+    );
+    manager.insert(key, session_key, handle).await;
+}
+
+/// Pára o polling de um equipamento
+pub async fn stop(manager: &PollingManager, id: i64) {
+    manager.stop(&poller_key(id)).await;
+}
+
+async fn poll_session(
+    socket: TcpStream,
+    conn_id: u64,
+    device: &ModbusDevice,
+    server: &TcpServer,
+) -> ConnectionResult {
+    let key = device.session_key();
+    let key = key.as_str();
+    let layout = device.packet_layout();
+    server.set_session_layout(key, &layout.name, "modbus").await;
+    let mut connection = ModbusConnection { socket, unit_id: device.unit_id, transaction_id: 0 };
+    let mut interval = tokio::time::interval(Duration::from_millis(device.poll_interval_ms));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut total_bytes = 0u64;
+    let mut packet_count = 0u64;
+
+    loop {
+        interval.tick().await;
+
+        if !server.is_running() {
+            return ConnectionResult::ServerStopped;
+        }
+
+        match connection.read_all(device).await {
+            Ok(data) => {
+                total_bytes += data.len() as u64;
+                packet_count += 1;
+                server.record_received(key, data.len(), total_bytes).await;
+
+                if let Err(e) = server.publish_packet(
+                    key, conn_id, &device.name, &data, &layout, packet_count
+                ).await {
+                    let errors = server.record_parse_error(key, &e).await;
+                    if errors <= 10 || errors.is_multiple_of(100) {
+                        eprintln!("⚠️ Modbus '{}' erro parsing ciclo #{} ({} erros): {}", device.name, packet_count, errors, e);
+                    }
+                }
+            }
+            Err(e) => {
+                server.mark_session_error(key, &e).await;
+                return ConnectionResult::Error(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    #[derive(Clone, Copy)]
+    enum Reply {
+        Registers,     // Holding N = N, input N = N + 1000
+        Exception(u8),
+        WrongTransaction,
+    }
+
+    /// Responde a FC03/FC04 numa porta efémera; devolve os pedidos (fc, início, quantidade)
+    async fn responder(reply: Reply) -> (ModbusConnection, JoinHandle<Vec<(u8, u16, u16)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut requests = Vec::new();
+            let mut request = [0u8; 12];
+            while socket.read_exact(&mut request).await.is_ok() {
+                let fc = request[7];
+                let start = u16::from_be_bytes([request[8], request[9]]);
+                let quantity = u16::from_be_bytes([request[10], request[11]]);
+                requests.push((fc, start, quantity));
+
+                let mut pdu = match reply {
+                    Reply::Exception(code) => vec![fc | 0x80, code],
+                    _ => vec![fc, (quantity * 2) as u8],
+                };
+                if pdu[0] == fc {
+                    for address in start..start + quantity {
+                        let value = if fc == 0x04 { address + 1000 } else { address };
+                        pdu.extend_from_slice(&value.to_be_bytes());
+                    }
+                }
+                let mut tid = u16::from_be_bytes([request[0], request[1]]);
+                if let Reply::WrongTransaction = reply {
+                    tid = tid.wrapping_add(1);
+                }
+                let mut response = tid.to_be_bytes().to_vec();
+                response.extend_from_slice(&[0, 0]);
+                response.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+                response.push(request[6]);
+                response.extend_from_slice(&pdu);
+                socket.write_all(&response).await.unwrap();
+            }
+            requests
+        });
+        let socket = TcpStream::connect(addr).await.unwrap();
+        (ModbusConnection { socket, unit_id: 1, transaction_id: 0 }, task)
+    }
+
+    fn device() -> ModbusDevice {
+        serde_json::from_value(serde_json::json!({
+            "name": "bomba", "host": "127.0.0.1",
+            "ranges": [
+                { "name": "Word", "register_type": "holding", "start_address": 0, "data_type": "Word", "count": 130, "byte_order": "big" },
+                { "name": "Real", "register_type": "input", "start_address": 100, "data_type": "Real", "count": 2, "byte_order": "big" },
+            ],
+        })).unwrap()
+    }
+
+    #[test]
+    fn layout_concatenates_ranges() {
+        let layout = device().packet_layout();
+        assert_eq!(layout.packet_size, 268);
+        assert_eq!(layout.segments.iter().map(|s| s.offset).collect::<Vec<_>>(), vec![0, 260]);
+        assert!(device().validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn splits_reads_above_125_registers() {
+        let (mut connection, task) = responder(Reply::Registers).await;
+        let data = connection.read_all(&device()).await.unwrap();
+        drop(connection);
+
+        assert_eq!(data.len(), 268);
+        assert_eq!(&data[248..252], &[0, 124, 0, 125]); // Fronteira entre os dois pedidos
+        assert_eq!(&data[258..262], &[0, 129, 0x04, 0x4C]); // Word[129], input 100 = 1100
+        assert_eq!(task.await.unwrap(), vec![(0x03, 0, 125), (0x03, 125, 5), (0x04, 100, 4)]);
+    }
+
+    #[tokio::test]
+    async fn reports_exception_and_transaction_mismatch() {
+        let (mut connection, _) = responder(Reply::Exception(0x02)).await;
+        let error = connection.read_registers(RegisterType::Holding, 10, 2).await.unwrap_err();
+        assert!(error.contains("0x02"), "{}", error);
+
+        let (mut connection, _) = responder(Reply::WrongTransaction).await;
+        let error = connection.read_registers(RegisterType::Input, 0, 1).await.unwrap_err();
+        assert!(error.contains("Transação inesperada"), "{}", error);
+    }
+}
@@ -0,0 +1,817 @@
+// packet_layout.rs - LAYOUT CONFIGURÁVEL DO PACOTE PLC (UDT)
+// ============================================================================
+// O layout descreve como o bloco binário enviado pelo PLC (TSEND_C) está
+// organizado: uma lista ordenada de segmentos tipados, cada um com
+// quantidade de elementos, offset em bytes e ordem de bytes.
+// É persistido em SQLite (tabelas packet_layouts / packet_layout_segments)
+// e usado pelo tcp_server para enquadrar e parsear os pacotes.
+// Tipos S7 suportados: Word, Int, Real, DInt, UDInt, LReal, Bool (array
+// empacotado, bit 0 = LSB do primeiro byte), String (S7 STRING[n]: bytes de
+// comprimento máximo e atual + n caracteres), Time (ms, i32) e Dtl (12 bytes).
+// Podem existir vários layouts registados (firmwares diferentes): o servidor
+// escolhe um por conexão pelo perfil do PLC, pelo tamanho do frame ou por uma
+// assinatura (campo de versão) no payload.
+// Um segmento Dtl pode ser marcado como carimbo temporal do PLC
+// (timestamp_segment): passa a ser a hora oficial de cada amostra.
+// O DTL não traz fuso: clock_timezone diz em que fuso está o relógio do PLC
+// ("utc", "local" = fuso do servidor com hora de verão, ou "+01:00").
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+
+/// Nome do layout padrão (UDT_TCP_Data do S7-1500)
+pub const DEFAULT_LAYOUT_NAME: &str = "UDT_TCP_Data";
+
+/// Limite de segurança para o tamanho de um pacote (bytes)
+pub const MAX_PACKET_SIZE: usize = 65536;
+
+/// Comprimento máximo de uma S7 STRING (STRING[254])
+pub const MAX_STRING_LENGTH: usize = 254;
+
+/// Tipo de dado de um segmento
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SegmentType {
+    Word,   // u16
+    Int,    // i16
+    Real,   // f32
+    DInt,   // i32
+    UDInt,  // u32
+    LReal,  // f64
+    Bool,   // 1 bit, empacotado
+    String, // S7 STRING[max_length]
+    Time,   // i32 em ms (T#...)
+    Dtl,    // Data/hora: ano u16, mês, dia, dia da semana, h, m, s (u8), ns u32
+}
+
+impl SegmentType {
+    /// Tamanho de um elemento em bytes (None: Bool empacotado e String de tamanho variável)
+    pub fn fixed_size(&self) -> Option<usize> {
+        match self {
+            SegmentType::Word | SegmentType::Int => Some(2),
+            SegmentType::Real | SegmentType::DInt | SegmentType::UDInt | SegmentType::Time => Some(4),
+            SegmentType::LReal => Some(8),
+            SegmentType::Dtl => Some(12),
+            SegmentType::Bool | SegmentType::String => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SegmentType::Word => "Word",
+            SegmentType::Int => "Int",
+            SegmentType::Real => "Real",
+            SegmentType::DInt => "DInt",
+            SegmentType::UDInt => "UDInt",
+            SegmentType::LReal => "LReal",
+            SegmentType::Bool => "Bool",
+            SegmentType::String => "String",
+            SegmentType::Time => "Time",
+            SegmentType::Dtl => "Dtl",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "Word" => Some(SegmentType::Word),
+            "Int" => Some(SegmentType::Int),
+            "Real" => Some(SegmentType::Real),
+            "DInt" => Some(SegmentType::DInt),
+            "UDInt" => Some(SegmentType::UDInt),
+            "LReal" => Some(SegmentType::LReal),
+            "Bool" => Some(SegmentType::Bool),
+            "String" => Some(SegmentType::String),
+            "Time" => Some(SegmentType::Time),
+            "Dtl" => Some(SegmentType::Dtl),
+            _ => None,
+        }
+    }
+}
+
+/// Ordem dos bytes (S7 usa big-endian)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ByteOrder::Big => "big",
+            ByteOrder::Little => "little",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "big" => Some(ByteOrder::Big),
+            "little" => Some(ByteOrder::Little),
+            _ => None,
+        }
+    }
+
+    pub fn read_u16(&self, b: &[u8]) -> u16 {
+        let bytes = [b[0], b[1]];
+        match self {
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+        }
+    }
+
+    pub fn read_i16(&self, b: &[u8]) -> i16 {
+        self.read_u16(b) as i16
+    }
+
+    pub fn read_f32(&self, b: &[u8]) -> f32 {
+        f32::from_bits(self.read_u32(b))
+    }
+
+    pub fn read_u32(&self, b: &[u8]) -> u32 {
+        let bytes = [b[0], b[1], b[2], b[3]];
+        match self {
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+        }
+    }
+
+    pub fn read_i32(&self, b: &[u8]) -> i32 {
+        self.read_u32(b) as i32
+    }
+
+    pub fn read_f64(&self, b: &[u8]) -> f64 {
+        let bytes = [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]];
+        match self {
+            ByteOrder::Big => f64::from_be_bytes(bytes),
+            ByteOrder::Little => f64::from_le_bytes(bytes),
+        }
+    }
+}
+
+/// Qualidade de uma variável (o painel mostra um marcador em vez de valores maus)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    #[default]
+    Good,
+    NonFinite,   // Real/LReal NaN ou infinito (sensor avariado, divisão por zero no PLC)
+    Stale,       // Sem dados novos do PLC há mais de STALE_DATA_SECS
+    OutOfRange,  // Fora da faixa válida do segmento (ou DTL inválido)
+    Substituted, // Valor mau trocado pelo valor de substituição do segmento
+}
+
+impl Quality {
+    pub fn is_good(&self) -> bool {
+        *self == Quality::Good
+    }
+}
+
+/// Valor decodificado de um elemento: número (templates, alarmes, gráficos)
+/// e/ou texto (String, Time e Dtl formatados)
+#[derive(Debug, Clone)]
+pub struct DecodedValue {
+    pub numeric: Option<f64>,
+    pub text: String,
+    pub quality: Quality,
+}
+
+/// Segmento do pacote: `count` elementos de `data_type` a partir de `offset`.
+/// As variáveis geradas chamam-se `{name}[i]` (ex: "Word[3]").
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayoutSegment {
+    pub name: String,
+    pub data_type: SegmentType,
+    pub count: usize,
+    pub offset: usize,
+    pub byte_order: ByteOrder,
+    #[serde(default)]
+    pub max_length: Option<usize>, // Só String: n de STRING[n] (padrão 254)
+    #[serde(default)]
+    pub valid_min: Option<f64>,    // Faixa válida (fora dela: OutOfRange)
+    #[serde(default)]
+    pub valid_max: Option<f64>,
+    #[serde(default)]
+    pub substitute: Option<f64>,   // Valor usado quando a leitura é má (Substituted)
+}
+
+impl LayoutSegment {
+    /// Bytes entre elementos consecutivos (Bool: 0, empacotado).
+    /// Cada STRING de um array começa num byte par (alinhamento S7).
+    pub fn stride(&self) -> usize {
+        match self.data_type {
+            SegmentType::Bool => 0,
+            SegmentType::String => {
+                let len = 2 + self.max_length.unwrap_or(MAX_STRING_LENGTH);
+                len + len % 2
+            }
+            other => other.fixed_size().unwrap_or(0),
+        }
+    }
+
+    /// Tamanho total do segmento em bytes (None se não cabe em usize)
+    pub fn checked_byte_len(&self) -> Option<usize> {
+        match self.data_type {
+            SegmentType::Bool => Some(self.count.div_ceil(8)),
+            SegmentType::String => {
+                // O último elemento não precisa do byte de alinhamento
+                self.count.saturating_sub(1)
+                    .checked_mul(self.stride())?
+                    .checked_add(2)?
+                    .checked_add(self.max_length.unwrap_or(MAX_STRING_LENGTH))
+            }
+            _ => self.count.checked_mul(self.stride()),
+        }
+    }
+
+    /// Primeiro byte após o segmento (None se não cabe em usize)
+    pub fn checked_end(&self) -> Option<usize> {
+        self.offset.checked_add(self.checked_byte_len()?)
+    }
+
+    /// Primeiro byte após o segmento (satura em vez de transbordar)
+    pub fn end(&self) -> usize {
+        self.checked_end().unwrap_or(usize::MAX)
+    }
+
+    /// Máximo de elementos que cabem num pacote (Bool: 8 por byte)
+    pub fn max_count(&self) -> usize {
+        match self.data_type {
+            SegmentType::Bool => MAX_PACKET_SIZE * 8,
+            _ => MAX_PACKET_SIZE / self.stride().max(1),
+        }
+    }
+
+    /// Decodifica o elemento `index` (o pacote tem de conter o segmento inteiro)
+    /// e classifica a qualidade: NaN/Inf, faixa válida e valor de substituição
+    /// `timezone` = fuso do relógio do PLC (só afeta o valor numérico dos Dtl)
+    pub fn decode(&self, data: &[u8], index: usize, timezone: ClockTimezone) -> DecodedValue {
+        let mut value = self.decode_raw(data, index, timezone);
+        if value.quality.is_good() {
+            if let Some(n) = value.numeric {
+                if self.valid_min.is_some_and(|min| n < min) || self.valid_max.is_some_and(|max| n > max) {
+                    value.quality = Quality::OutOfRange;
+                }
+            }
+        }
+        if !value.quality.is_good() {
+            if let Some(substitute) = self.substitute {
+                value.numeric = Some(substitute);
+                value.text = substitute.to_string();
+                value.quality = Quality::Substituted;
+            }
+        }
+        value
+    }
+
+    fn decode_raw(&self, data: &[u8], index: usize, timezone: ClockTimezone) -> DecodedValue {
+        let order = self.byte_order;
+        let offset = self.offset + index * self.stride();
+        let number = |value: f64, text: String| DecodedValue { numeric: Some(value), text, quality: Quality::Good };
+        let bad = |text: String, quality| DecodedValue { numeric: None, text, quality };
+
+        match self.data_type {
+            SegmentType::Word => {
+                let value = order.read_u16(&data[offset..]);
+                number(value as f64, value.to_string())
+            }
+            SegmentType::Int => {
+                let value = order.read_i16(&data[offset..]);
+                number(value as f64, value.to_string())
+            }
+            SegmentType::DInt => {
+                let value = order.read_i32(&data[offset..]);
+                number(value as f64, value.to_string())
+            }
+            SegmentType::UDInt => {
+                let value = order.read_u32(&data[offset..]);
+                number(value as f64, value.to_string())
+            }
+            // Reais: NaN e Infinito ficam sem valor numérico (qualidade NonFinite)
+            SegmentType::Real => {
+                let value = order.read_f32(&data[offset..]);
+                if value.is_finite() {
+                    number(value as f64, format!("{:.4}", value))
+                } else {
+                    bad(value.to_string(), Quality::NonFinite)
+                }
+            }
+            SegmentType::LReal => {
+                let value = order.read_f64(&data[offset..]);
+                if value.is_finite() {
+                    number(value, format!("{:.6}", value))
+                } else {
+                    bad(value.to_string(), Quality::NonFinite)
+                }
+            }
+            SegmentType::Bool => {
+                let on = data[self.offset + index / 8] & (1 << (index % 8)) != 0;
+                number(if on { 1.0 } else { 0.0 }, on.to_string())
+            }
+            SegmentType::Time => {
+                let ms = order.read_i32(&data[offset..]);
+                number(ms as f64, format_s7_time(ms))
+            }
+            SegmentType::String => {
+                // Byte 0 = comprimento máximo, byte 1 = comprimento atual (limitado ao layout)
+                let max_length = self.max_length.unwrap_or(MAX_STRING_LENGTH);
+                let actual = (data[offset + 1] as usize).min(max_length);
+                let chars = &data[offset + 2..offset + 2 + actual];
+                // S7 STRING é de 1 byte por carácter (Latin-1)
+                let text: String = chars.iter().map(|&c| c as char).collect();
+                DecodedValue { numeric: None, text, quality: Quality::Good }
+            }
+            // Texto = hora de parede do PLC; número = epoch em ms (UTC)
+            SegmentType::Dtl => match decode_dtl(&data[offset..offset + 12]) {
+                Some(time) => match timezone.to_utc(time) {
+                    Some(utc) => number(
+                        utc.timestamp_millis() as f64,
+                        time.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+                    ),
+                    None => bad(time.format("%Y-%m-%d %H:%M:%S%.3f").to_string(), Quality::OutOfRange),
+                },
+                None => bad(String::new(), Quality::OutOfRange),
+            },
+        }
+    }
+}
+
+/// DTL: ano u16 BE, mês, dia, dia da semana, hora, minuto, segundo, nanossegundos u32 BE
+/// (hora de parede do PLC, sem fuso: ver ClockTimezone)
+pub fn decode_dtl(b: &[u8]) -> Option<chrono::NaiveDateTime> {
+    if b.len() < 12 {
+        return None;
+    }
+    let year = u16::from_be_bytes([b[0], b[1]]) as i32;
+    let nanos = u32::from_be_bytes([b[8], b[9], b[10], b[11]]);
+    // O chrono aceita até 2e9 ns (segundo bissexto); no DTL o máximo é 999 999 999
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    chrono::NaiveDate::from_ymd_opt(year, b[2] as u32, b[3] as u32)?
+        .and_hms_nano_opt(b[5] as u32, b[6] as u32, b[7] as u32, nanos)
+}
+
+/// Formato S7 de TIME: T#1D_2H_3M_4S_500MS (T#0MS para zero)
+pub fn format_s7_time(ms: i32) -> String {
+    let sign = if ms < 0 { "-" } else { "" };
+    let mut rest = (ms as i64).abs();
+    let mut parts = Vec::new();
+    for (unit, size) in [("D", 86_400_000), ("H", 3_600_000), ("M", 60_000), ("S", 1000), ("MS", 1)] {
+        let value = rest / size;
+        rest %= size;
+        if value > 0 {
+            parts.push(format!("{}{}", value, unit));
+        }
+    }
+    if parts.is_empty() {
+        parts.push("0MS".to_string());
+    }
+    format!("T#{}{}", sign, parts.join("_"))
+}
+
+/// Campo de versão no payload: u16 big-endian em `offset` igual a `value`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayoutSignature {
+    pub offset: usize,
+    pub value: u16,
+}
+
+/// Fuso do relógio do PLC (os DTL são hora de parede, sem fuso)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ClockTimezone {
+    #[default]
+    Utc,
+    Local,       // Fuso do servidor, com hora de verão (ex: WET/WEST)
+    Offset(i32), // Desvio fixo em minutos (ex: "+01:00" = 60)
+}
+
+/// Desvio máximo aceite (±14:00)
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+impl ClockTimezone {
+    pub fn as_string(&self) -> String {
+        match self {
+            ClockTimezone::Utc => "utc".to_string(),
+            ClockTimezone::Local => "local".to_string(),
+            ClockTimezone::Offset(minutes) => {
+                let sign = if *minutes < 0 { '-' } else { '+' };
+                format!("{}{:02}:{:02}", sign, minutes.abs() / 60, minutes.abs() % 60)
+            }
+        }
+    }
+
+    /// "utc", "local" ou "+HH:MM" / "-HH:MM"
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "utc" | "UTC" => Some(ClockTimezone::Utc),
+            "local" => Some(ClockTimezone::Local),
+            value => {
+                let (sign, rest) = match value.as_bytes().first()? {
+                    b'+' => (1, &value[1..]),
+                    b'-' => (-1, &value[1..]),
+                    _ => return None,
+                };
+                let (hours, minutes) = rest.split_once(':')?;
+                if hours.len() != 2 || minutes.len() != 2 {
+                    return None;
+                }
+                let hours: i32 = hours.parse().ok()?;
+                let minutes: i32 = minutes.parse().ok()?;
+                let total = hours * 60 + minutes;
+                (minutes < 60 && total <= MAX_UTC_OFFSET_MINUTES).then_some(ClockTimezone::Offset(sign * total))
+            }
+        }
+    }
+
+    /// Hora de parede do PLC → instante UTC. Na hora repetida do fim da hora
+    /// de verão usa a primeira; na hora que não existe (início) devolve None.
+    pub fn to_utc(self, time: chrono::NaiveDateTime) -> Option<chrono::DateTime<chrono::Utc>> {
+        use chrono::TimeZone;
+        match self {
+            ClockTimezone::Utc => Some(time.and_utc()),
+            ClockTimezone::Local => chrono::Local.from_local_datetime(&time)
+                .earliest()
+                .map(|t| t.with_timezone(&chrono::Utc)),
+            ClockTimezone::Offset(minutes) => chrono::FixedOffset::east_opt(minutes * 60)?
+                .from_local_datetime(&time)
+                .single()
+                .map(|t| t.with_timezone(&chrono::Utc)),
+        }
+    }
+}
+
+impl TryFrom<String> for ClockTimezone {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        ClockTimezone::parse(&value)
+            .ok_or_else(|| format!("Fuso do relógio inválido: '{}' (utc, local ou +HH:MM)", value))
+    }
+}
+
+impl From<ClockTimezone> for String {
+    fn from(timezone: ClockTimezone) -> Self {
+        timezone.as_string()
+    }
+}
+
+/// Layout completo de um pacote PLC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PacketLayout {
+    pub name: String,
+    pub packet_size: usize,
+    pub segments: Vec<LayoutSegment>,
+    #[serde(default)]
+    pub signature: Option<LayoutSignature>, // Desempate entre layouts com o mesmo tamanho
+    /// Segmento Dtl cujo elemento [0] é o carimbo temporal do PLC.
+    /// Quando definido, é o instante oficial da amostra em vez da hora de receção.
+    #[serde(default)]
+    pub timestamp_segment: Option<String>,
+    /// Fuso em que o relógio do PLC está acertado (todos os Dtl do layout)
+    #[serde(default)]
+    pub clock_timezone: ClockTimezone,
+}
+
+impl PacketLayout {
+    /// Layout UDT_TCP_Data original:
+    ///   Word[0..64]  = 65 Words  (offset 0)
+    ///   Int[0..64]   = 65 Ints   (offset 130)
+    ///   Real[0..256] = 257 Reals (offset 260)
+    ///   TOTAL = 1288 bytes
+    pub fn default_udt() -> Self {
+        let segment = |name: &str, data_type, count, offset| LayoutSegment {
+            name: name.to_string(),
+            data_type,
+            count,
+            offset,
+            byte_order: ByteOrder::Big,
+            max_length: None,
+            valid_min: None,
+            valid_max: None,
+            substitute: None,
+        };
+
+        Self {
+            name: DEFAULT_LAYOUT_NAME.to_string(),
+            packet_size: 1288,
+            segments: vec![
+                segment("Word", SegmentType::Word, 65, 0),
+                segment("Int", SegmentType::Int, 65, 130),
+                segment("Real", SegmentType::Real, 257, 260),
+            ],
+            signature: None,
+            timestamp_segment: None,
+            clock_timezone: ClockTimezone::Utc,
+        }
+    }
+
+    /// Verifica a assinatura (sem assinatura = não identifica o payload)
+    pub fn matches_signature(&self, payload: &[u8]) -> bool {
+        match self.signature {
+            Some(sig) => sig.offset.checked_add(2)
+                .and_then(|end| payload.get(sig.offset..end))
+                .is_some_and(|b| u16::from_be_bytes([b[0], b[1]]) == sig.value),
+            None => false,
+        }
+    }
+
+    /// Carimbo temporal do PLC contido no pacote, convertido de clock_timezone
+    /// para UTC (None sem segmento ou DTL inválido)
+    pub fn plc_timestamp(&self, payload: &[u8]) -> Option<chrono::DateTime<chrono::Utc>> {
+        let name = self.timestamp_segment.as_deref()?;
+        let segment = self.segments.iter().find(|s| s.name == name)?;
+        let time = decode_dtl(payload.get(segment.offset..segment.offset.checked_add(12)?)?)?;
+        self.clock_timezone.to_utc(time)
+    }
+
+    /// Número total de variáveis geradas por pacote
+    pub fn variable_count(&self) -> usize {
+        self.segments.iter().fold(0usize, |total, s| total.saturating_add(s.count))
+    }
+
+    /// Resumo legível (ex: "Word[65] + Int[65] + Real[257]")
+    pub fn summary(&self) -> String {
+        self.segments.iter()
+            .map(|s| format!("{}[{}]", s.name, s.count))
+            .collect::<Vec<_>>()
+            .join(" + ")
+    }
+
+    /// Valida o layout: segmentos dentro do pacote, sem sobreposição e com nomes únicos
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Nome do layout não pode ser vazio".to_string());
+        }
+        if self.packet_size == 0 || self.packet_size > MAX_PACKET_SIZE {
+            return Err(format!(
+                "Tamanho do pacote inválido: {} bytes (1..{})",
+                self.packet_size, MAX_PACKET_SIZE
+            ));
+        }
+        if self.segments.is_empty() {
+            return Err("Layout deve ter pelo menos um segmento".to_string());
+        }
+        if let Some(sig) = self.signature {
+            if sig.offset.checked_add(2).is_none_or(|end| end > self.packet_size) {
+                return Err(format!(
+                    "Assinatura no byte {} fora do pacote ({} bytes)",
+                    sig.offset, self.packet_size
+                ));
+            }
+        }
+
+        let mut names = std::collections::HashSet::new();
+        for segment in &self.segments {
+            if segment.name.trim().is_empty() {
+                return Err("Nome do segmento não pode ser vazio".to_string());
+            }
+            if !names.insert(segment.name.as_str()) {
+                return Err(format!("Segmento duplicado: {}", segment.name));
+            }
+            if segment.count == 0 {
+                return Err(format!("Segmento {} tem quantidade 0", segment.name));
+            }
+            if segment.count > segment.max_count() {
+                return Err(format!(
+                    "Segmento {}: quantidade {} não cabe num pacote (máximo {})",
+                    segment.name, segment.count, segment.max_count()
+                ));
+            }
+            if segment.data_type == SegmentType::String {
+                let max_length = segment.max_length.unwrap_or(MAX_STRING_LENGTH);
+                if max_length == 0 || max_length > MAX_STRING_LENGTH {
+                    return Err(format!(
+                        "String {}: comprimento máximo {} inválido (1..{})",
+                        segment.name, max_length, MAX_STRING_LENGTH
+                    ));
+                }
+            }
+            if [segment.valid_min, segment.valid_max, segment.substitute].iter().flatten().any(|v| !v.is_finite()) {
+                return Err(format!("Segmento {}: faixa válida e substituto têm de ser finitos", segment.name));
+            }
+            if let (Some(min), Some(max)) = (segment.valid_min, segment.valid_max) {
+                if min > max {
+                    return Err(format!("Segmento {}: faixa válida {}..{} invertida", segment.name, min, max));
+                }
+            }
+            match segment.checked_end() {
+                Some(end) if end <= self.packet_size => {}
+                Some(end) => return Err(format!(
+                    "Segmento {} termina no byte {} (pacote tem {} bytes)",
+                    segment.name, end, self.packet_size
+                )),
+                None => return Err(format!(
+                    "Segmento {}: offset {} + tamanho fora dos limites", segment.name, segment.offset
+                )),
+            }
+        }
+
+        if let Some(name) = &self.timestamp_segment {
+            match self.segments.iter().find(|s| &s.name == name) {
+                Some(s) if s.data_type == SegmentType::Dtl => {}
+                Some(_) => return Err(format!("Segmento de carimbo temporal {} tem de ser Dtl", name)),
+                None => return Err(format!("Segmento de carimbo temporal desconhecido: {}", name)),
+            }
+        }
+
+        let mut sorted: Vec<&LayoutSegment> = self.segments.iter().collect();
+        sorted.sort_by_key(|s| s.offset);
+        for pair in sorted.windows(2) {
+            if pair[0].end() > pair[1].offset {
+                return Err(format!(
+                    "Segmentos {} e {} sobrepõem-se (byte {})",
+                    pair[0].name, pair[1].name, pair[1].offset
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(data_type: SegmentType, count: usize, offset: usize) -> LayoutSegment {
+        LayoutSegment {
+            name: "S".to_string(),
+            data_type,
+            count,
+            offset,
+            byte_order: ByteOrder::Big,
+            max_length: None,
+            valid_min: None,
+            valid_max: None,
+            substitute: None,
+        }
+    }
+
+    fn layout(segments: Vec<LayoutSegment>) -> PacketLayout {
+        PacketLayout {
+            name: "teste".to_string(),
+            packet_size: 64,
+            segments,
+            signature: None,
+            timestamp_segment: None,
+            clock_timezone: ClockTimezone::Utc,
+        }
+    }
+
+    #[test]
+    fn validate_rejects_overflowing_sizes() {
+        assert!(layout(vec![segment(SegmentType::Word, usize::MAX / 2 + 1, 0)]).validate().is_err());
+        assert!(layout(vec![segment(SegmentType::Word, 1, usize::MAX)]).validate().is_err());
+        assert!(layout(vec![segment(SegmentType::Bool, usize::MAX, 0)]).validate().is_err());
+        let mut string = segment(SegmentType::String, usize::MAX, 0);
+        string.max_length = Some(10);
+        assert!(layout(vec![string]).validate().is_err());
+
+        let mut signed = layout(vec![segment(SegmentType::Word, 1, 0)]);
+        signed.signature = Some(LayoutSignature { offset: usize::MAX, value: 1 });
+        assert!(signed.validate().is_err());
+        assert!(!signed.matches_signature(&[0u8; 64]));
+    }
+
+    #[test]
+    fn validate_caps_count_to_packet() {
+        assert_eq!(segment(SegmentType::Bool, 1, 0).max_count(), MAX_PACKET_SIZE * 8);
+        assert_eq!(segment(SegmentType::Real, 1, 0).max_count(), MAX_PACKET_SIZE / 4);
+        assert!(layout(vec![segment(SegmentType::Word, MAX_PACKET_SIZE, 0)]).validate().is_err());
+        assert!(layout(vec![segment(SegmentType::Bool, 512, 0)]).validate().is_ok());
+        assert!(layout(vec![segment(SegmentType::Bool, 513, 0)]).validate().is_err());
+    }
+
+    fn decode(segment: &LayoutSegment, data: &[u8], index: usize) -> DecodedValue {
+        let value = segment.decode(data, index, ClockTimezone::Utc);
+        assert_eq!(value.quality, Quality::Good, "{:?}", value);
+        value
+    }
+
+    #[test]
+    fn decodes_both_byte_orders() {
+        let mut dint = segment(SegmentType::DInt, 1, 0);
+        let mut udint = segment(SegmentType::UDInt, 1, 0);
+        let mut lreal = segment(SegmentType::LReal, 1, 0);
+
+        let data = (-2i32).to_be_bytes();
+        assert_eq!(decode(&dint, &data, 0).numeric, Some(-2.0));
+        dint.byte_order = ByteOrder::Little;
+        assert_eq!(decode(&dint, &(-2i32).to_le_bytes(), 0).numeric, Some(-2.0));
+        assert_eq!(decode(&dint, &data, 0).numeric, Some(i32::from_le_bytes(data) as f64));
+
+        assert_eq!(decode(&udint, &0xFEDC_BA98u32.to_be_bytes(), 0).numeric, Some(0xFEDC_BA98u32 as f64));
+        udint.byte_order = ByteOrder::Little;
+        assert_eq!(decode(&udint, &0xFEDC_BA98u32.to_le_bytes(), 0).text, "4275878552");
+
+        assert_eq!(decode(&lreal, &(-1234.5678f64).to_be_bytes(), 0).numeric, Some(-1234.5678));
+        lreal.byte_order = ByteOrder::Little;
+        assert_eq!(decode(&lreal, &(-1234.5678f64).to_le_bytes(), 0).text, "-1234.567800");
+    }
+
+    #[test]
+    fn decodes_bools_lsb_first_across_bytes() {
+        let bools = segment(SegmentType::Bool, 16, 1);
+        let data = [0xFF, 0b1000_0001, 0b0000_0010];
+        let bits: Vec<bool> = (0..16).map(|i| decode(&bools, &data, i).numeric == Some(1.0)).collect();
+
+        let mut expected = [false; 16];
+        expected[0] = true;  // Byte 1, bit 0
+        expected[7] = true;  // Byte 1, bit 7
+        expected[9] = true;  // Byte 2, bit 1
+        assert_eq!(bits, expected);
+    }
+
+    #[test]
+    fn string_length_is_capped_to_declared_maximum() {
+        let mut string = segment(SegmentType::String, 1, 0);
+        string.max_length = Some(4);
+        // Comprimento atual 200 num STRING[4]: só os 4 caracteres do layout
+        let data = [4, 200, b'a', b'b', b'c', b'd', b'X', b'X'];
+        assert_eq!(decode(&string, &data, 0).text, "abcd");
+
+        let data = [4, 2, b'o', b'k', 0, 0];
+        assert_eq!(decode(&string, &data, 0).text, "ok");
+        // Latin-1
+        let data = [4, 1, 0xE7, 0, 0, 0];
+        assert_eq!(decode(&string, &data, 0).text, "ç");
+    }
+
+    fn dtl(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8, nanos: u32) -> [u8; 12] {
+        let mut b = [0u8; 12];
+        b[0..2].copy_from_slice(&year.to_be_bytes());
+        b[2..8].copy_from_slice(&[month, day, 1, hour, minute, second]);
+        b[8..12].copy_from_slice(&nanos.to_be_bytes());
+        b
+    }
+
+    #[test]
+    fn decodes_dtl_and_rejects_invalid_fields() {
+        let time = decode_dtl(&dtl(2024, 3, 31, 1, 2, 3, 500_000_000)).unwrap();
+        assert_eq!(time.format("%Y-%m-%d %H:%M:%S%.3f").to_string(), "2024-03-31 01:02:03.500");
+
+        assert!(decode_dtl(&dtl(2024, 13, 1, 0, 0, 0, 0)).is_none());
+        assert!(decode_dtl(&dtl(2024, 0, 1, 0, 0, 0, 0)).is_none());
+        assert!(decode_dtl(&dtl(2023, 2, 29, 0, 0, 0, 0)).is_none());
+        assert!(decode_dtl(&dtl(2024, 1, 1, 24, 0, 0, 0)).is_none());
+        assert!(decode_dtl(&dtl(2024, 1, 1, 0x25, 0x61, 0, 0)).is_none()); // Lixo/BCD
+        assert!(decode_dtl(&dtl(2024, 1, 1, 23, 59, 59, 1_500_000_000)).is_none());
+        assert!(decode_dtl(&[0x07, 0xE8, 1, 1]).is_none());
+
+        let segment = segment(SegmentType::Dtl, 1, 0);
+        let value = segment.decode(&dtl(2024, 13, 1, 0, 0, 0, 0), 0, ClockTimezone::Utc);
+        assert_eq!(value.quality, Quality::OutOfRange);
+        assert_eq!(value.numeric, None);
+    }
+
+    #[test]
+    fn formats_s7_time() {
+        assert_eq!(format_s7_time(0), "T#0MS");
+        assert_eq!(format_s7_time(93_784_005), "T#1D_2H_3M_4S_5MS");
+        assert_eq!(format_s7_time(-1500), "T#-1S_500MS");
+        assert_eq!(format_s7_time(i32::MIN), "T#-24D_20H_31M_23S_648MS");
+        assert_eq!(format_s7_time(i32::MAX), "T#24D_20H_31M_23S_647MS");
+
+        let time = segment(SegmentType::Time, 1, 0);
+        let value = decode(&time, &(-60_000i32).to_be_bytes(), 0);
+        assert_eq!(value.numeric, Some(-60_000.0));
+        assert_eq!(value.text, "T#-1M");
+    }
+
+    #[test]
+    fn parses_clock_timezones() {
+        assert_eq!(ClockTimezone::parse("utc"), Some(ClockTimezone::Utc));
+        assert_eq!(ClockTimezone::parse("local"), Some(ClockTimezone::Local));
+        assert_eq!(ClockTimezone::parse("+01:00"), Some(ClockTimezone::Offset(60)));
+        assert_eq!(ClockTimezone::parse("-03:30"), Some(ClockTimezone::Offset(-210)));
+        for value in ["", "wet", "+1", "+01:60", "+15:00", "01:00", "+01:00:00"] {
+            assert_eq!(ClockTimezone::parse(value), None, "{:?}", value);
+        }
+        assert_eq!(ClockTimezone::Offset(-210).as_string(), "-03:30");
+
+        let layout: PacketLayout = serde_json::from_value(serde_json::json!({
+            "name": "x", "packet_size": 12, "segments": [], "clock_timezone": "+01:00"
+        })).unwrap();
+        assert_eq!(layout.clock_timezone, ClockTimezone::Offset(60));
+        assert_eq!(serde_json::to_value(&layout).unwrap()["clock_timezone"], "+01:00");
+    }
+
+    #[test]
+    fn plc_timestamp_applies_clock_timezone() {
+        let mut clock = segment(SegmentType::Dtl, 1, 0);
+        clock.name = "Clock".to_string();
+        let mut layout = layout(vec![clock]);
+        layout.timestamp_segment = Some("Clock".to_string());
+        let mut data = [0u8; 64];
+        data[..12].copy_from_slice(&dtl(2024, 7, 1, 13, 0, 0, 0)); // 13:00 WEST = 12:00 UTC
+
+        assert_eq!(layout.plc_timestamp(&data).unwrap().to_rfc3339(), "2024-07-01T13:00:00+00:00");
+        layout.clock_timezone = ClockTimezone::Offset(60);
+        assert_eq!(layout.plc_timestamp(&data).unwrap().to_rfc3339(), "2024-07-01T12:00:00+00:00");
+
+        let value = layout.segments[0].decode(&data, 0, layout.clock_timezone);
+        assert_eq!(value.text, "2024-07-01 13:00:00.000");
+        assert_eq!(value.numeric, Some(1_719_835_200_000.0));
+    }
+}
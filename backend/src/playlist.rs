@@ -0,0 +1,223 @@
+// playlist.rs - PLAYLISTS DE PUBLICIDADE COM HORÁRIO
+// ============================================================================
+// Uma playlist é uma lista ordenada de vídeos com período de validade
+// (valid_from..valid_until, inclusivo), dias da semana (ISO: 1 = segunda ..
+// 7 = domingo; vazio = todos) e janela horária (start_time..end_time, "HH:MM";
+// se end_time < start_time a janela atravessa a meia-noite).
+// Cada item tem um peso: um vídeo com peso 3 passa 3 vezes por ciclo,
+// intercalado com os restantes (round-robin ponderado suave).
+// Quando o bit de controle de vídeo está a 1, o painel mostra a playlist em
+// vigor de maior prioridade entre as que lhe estão atribuídas; sem nenhuma
+// em vigor, roda os vídeos ativos como antes.
+// É persistida em SQLite (tabelas playlists / playlist_items).
+// ============================================================================
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+/// Peso máximo de um item (vezes por ciclo)
+pub const MAX_ITEM_WEIGHT: u32 = 100;
+
+fn default_true() -> bool { true }
+fn default_weight() -> u32 { 1 }
+
+/// Vídeo de uma playlist
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistItem {
+    pub video_id: i64,
+    #[serde(default = "default_weight")]
+    pub weight: u32, // Vezes por ciclo (1..MAX_ITEM_WEIGHT)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playlist {
+    #[serde(default)]
+    pub id: i64,
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub priority: i32,                  // Maior ganha quando várias estão em vigor
+    #[serde(default)]
+    pub valid_from: Option<NaiveDate>,  // "AAAA-MM-DD"
+    #[serde(default)]
+    pub valid_until: Option<NaiveDate>,
+    #[serde(default)]
+    pub days: Vec<u32>,                 // 1 = segunda .. 7 = domingo
+    #[serde(default)]
+    pub start_time: Option<String>,     // "HH:MM"
+    #[serde(default)]
+    pub end_time: Option<String>,
+    #[serde(default)]
+    pub items: Vec<PlaylistItem>,
+}
+
+impl Playlist {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Nome da playlist não pode ser vazio".to_string());
+        }
+        if let (Some(from), Some(until)) = (self.valid_from, self.valid_until) {
+            if from > until {
+                return Err(format!("Validade invertida: {} a {}", from, until));
+            }
+        }
+        if let Some(day) = self.days.iter().find(|d| !(1..=7).contains(*d)) {
+            return Err(format!("Dia da semana inválido: {} (1 = segunda .. 7 = domingo)", day));
+        }
+        match (&self.start_time, &self.end_time) {
+            (Some(start), Some(end)) => {
+                parse_time(start)?;
+                parse_time(end)?;
+                if start == end {
+                    return Err("Janela horária vazia (início = fim)".to_string());
+                }
+            }
+            (None, None) => {}
+            _ => return Err("Janela horária precisa de início e fim".to_string()),
+        }
+        if self.items.is_empty() {
+            return Err("Playlist deve ter pelo menos um vídeo".to_string());
+        }
+        if let Some(item) = self.items.iter().find(|i| i.weight == 0 || i.weight > MAX_ITEM_WEIGHT) {
+            return Err(format!("Peso inválido para o vídeo {}: {} (1..{})", item.video_id, item.weight, MAX_ITEM_WEIGHT));
+        }
+        Ok(())
+    }
+
+    /// Está em vigor no instante `now` (hora local da eclusa)?
+    pub fn is_active(&self, now: NaiveDateTime) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let date = now.date();
+        if self.valid_from.is_some_and(|from| date < from) || self.valid_until.is_some_and(|until| date > until) {
+            return false;
+        }
+        if !self.days.is_empty() && !self.days.contains(&date.weekday().number_from_monday()) {
+            return false;
+        }
+        let window = self.start_time.as_deref().and_then(|s| parse_time(s).ok())
+            .zip(self.end_time.as_deref().and_then(|e| parse_time(e).ok()));
+        match window {
+            Some((start, end)) => {
+                let time = now.time();
+                if start < end {
+                    time >= start && time < end
+                } else {
+                    time >= start || time < end // Atravessa a meia-noite
+                }
+            }
+            None => true,
+        }
+    }
+
+    /// Ordem de exibição de um ciclo: cada vídeo aparece `weight` vezes,
+    /// espalhado pelo ciclo (com pesos iguais mantém a ordem da lista)
+    pub fn sequence(&self) -> Vec<i64> {
+        let total: i64 = self.items.iter().map(|i| i.weight as i64).sum();
+        let mut current = vec![0i64; self.items.len()];
+        let mut sequence = Vec::with_capacity(total as usize);
+        for _ in 0..total {
+            let mut best = 0;
+            for (i, item) in self.items.iter().enumerate() {
+                current[i] += item.weight as i64;
+                if current[i] > current[best] {
+                    best = i;
+                }
+            }
+            current[best] -= total;
+            sequence.push(self.items[best].video_id);
+        }
+        sequence
+    }
+}
+
+/// Playlist em vigor de maior prioridade (empate: menor id)
+pub fn current_playlist(playlists: &[Playlist], now: NaiveDateTime) -> Option<&Playlist> {
+    playlists.iter()
+        .filter(|p| p.is_active(now))
+        .max_by_key(|p| (p.priority, std::cmp::Reverse(p.id)))
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M")
+        .map_err(|_| format!("Hora inválida: {} (HH:MM)", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(items: &[(i64, u32)]) -> Playlist {
+        let items: Vec<_> = items.iter().map(|(id, w)| serde_json::json!({ "video_id": id, "weight": w })).collect();
+        serde_json::from_value(serde_json::json!({ "name": "P", "items": items })).unwrap()
+    }
+
+    /// 2026-03-01 é domingo, 2026-03-02 segunda
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn window_across_midnight() {
+        let mut p = playlist(&[(1, 1)]);
+        p.start_time = Some("22:00".to_string());
+        p.end_time = Some("06:00".to_string());
+
+        assert!(p.is_active(at(2, 22, 0)));
+        assert!(p.is_active(at(2, 23, 59)));
+        assert!(p.is_active(at(2, 5, 59)));
+        assert!(!p.is_active(at(2, 6, 0)));
+        assert!(!p.is_active(at(2, 12, 0)));
+
+        p.start_time = Some("08:00".to_string());
+        p.end_time = Some("18:00".to_string());
+        assert!(p.is_active(at(2, 8, 0)));
+        assert!(!p.is_active(at(2, 18, 0)));
+        assert!(!p.is_active(at(2, 7, 59)));
+    }
+
+    #[test]
+    fn validity_dates_are_inclusive() {
+        let mut p = playlist(&[(1, 1)]);
+        p.valid_from = NaiveDate::from_ymd_opt(2026, 3, 2);
+        p.valid_until = NaiveDate::from_ymd_opt(2026, 3, 2);
+
+        assert!(!p.is_active(at(1, 23, 59)));
+        assert!(p.is_active(at(2, 0, 0)));
+        assert!(p.is_active(at(2, 23, 59)));
+        assert!(!p.is_active(at(3, 0, 0)));
+
+        p.enabled = false;
+        assert!(!p.is_active(at(2, 12, 0)));
+    }
+
+    #[test]
+    fn weekday_filter() {
+        let mut p = playlist(&[(1, 1)]);
+        p.days = vec![1, 7];
+
+        assert!(p.is_active(at(1, 12, 0)));  // Domingo
+        assert!(p.is_active(at(2, 12, 0)));  // Segunda
+        assert!(!p.is_active(at(3, 12, 0))); // Terça
+    }
+
+    #[test]
+    fn weighted_round_robin_order() {
+        assert_eq!(playlist(&[(1, 1), (2, 1), (3, 1)]).sequence(), vec![1, 2, 3]);
+        assert_eq!(playlist(&[(1, 3), (2, 1)]).sequence(), vec![1, 1, 2, 1]);
+        assert_eq!(playlist(&[(1, 5), (2, 1), (3, 1)]).sequence(), vec![1, 1, 2, 1, 3, 1, 1]);
+    }
+
+    #[test]
+    fn highest_priority_then_lowest_id_wins() {
+        let mut playlists: Vec<Playlist> = (1..=3).map(|id| Playlist { id, ..playlist(&[(id, 1)]) }).collect();
+        playlists[1].priority = 5;
+        playlists[2].priority = 5;
+        assert_eq!(current_playlist(&playlists, at(2, 12, 0)).map(|p| p.id), Some(2));
+
+        playlists[1].days = vec![2];
+        assert_eq!(current_playlist(&playlists, at(2, 12, 0)).map(|p| p.id), Some(3));
+    }
+}
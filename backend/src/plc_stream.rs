@@ -0,0 +1,370 @@
+// plc_stream.rs - STREAM SSE DE DADOS PLC EM MODO DELTA
+// ============================================================================
+// Modo "full" (compatível): cada PlcData vai inteiro em {"message": ...}.
+// Modo "delta" (/api/events/plc-data?mode=delta&keyframe=30): por PLC, o
+// primeiro frame e um a cada `keyframe` segundos vão inteiros
+// ({"message": PlcData, "keyframe": true}); entre keyframes só seguem as
+// variáveis que mudaram ({"delta": PlcDelta}). Frames sem mudanças não são
+// enviados. Tags com banda morta (tabela sse_deadbands, ex: "Real[3]" ou o
+// segmento inteiro "Real") só contam como mudadas quando se afastam mais do
+// que a banda do último valor enviado ao cliente.
+//
+// Cada frame publicado recebe um id monotónico ("<arranque>-<n>", id do evento
+// SSE) e fica num buffer circular. Um browser que volta a ligar envia
+// Last-Event-ID e recebe os frames que perdeu (pulsos curtos incluídos), ou um
+// snapshot se o intervalo já saiu do buffer ou é de uma execução anterior.
+// ============================================================================
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+
+use crate::packet_layout::Quality;
+use crate::tcp_server::PlcData;
+
+pub const DEFAULT_KEYFRAME_SECS: u64 = 30;
+pub const MAX_KEYFRAME_SECS: u64 = 3600;
+pub const DEFAULT_REPLAY_FRAMES: usize = 500;  // ~4 min de um PLC a 500 ms
+
+/// Modo do stream SSE de dados PLC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SseMode {
+    Full,  // Frame completo a cada pacote (comportamento original)
+    Delta, // Keyframes periódicos + apenas valores mudados
+}
+
+impl SseMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "full" => Some(SseMode::Full),
+            "delta" => Some(SseMode::Delta),
+            _ => None,
+        }
+    }
+}
+
+// ============================================================================
+// BUFFER DE REPLAY (Last-Event-ID)
+// ============================================================================
+
+/// Frame publicado com o seu id de sequência
+#[derive(Debug, Clone)]
+pub struct PlcFrame {
+    pub id: u64,
+    pub data: Arc<PlcData>,
+}
+
+/// Últimos frames publicados (partilhado pelo reencaminhador e pelos clientes SSE)
+pub struct ReplayBuffer {
+    boot: u64, // Prefixo dos ids: distingue ids de execuções anteriores
+    next_id: u64,
+    capacity: usize,
+    frames: VecDeque<PlcFrame>,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let boot = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        Self { boot, next_id: 1, capacity: capacity.max(1), frames: VecDeque::with_capacity(capacity) }
+    }
+
+    /// Atribui o próximo id ao frame e guarda-o (descartando o mais antigo se cheio)
+    pub fn push(&mut self, data: PlcData) -> PlcFrame {
+        let frame = PlcFrame { id: self.next_id, data: Arc::new(data) };
+        self.next_id += 1;
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame.clone());
+        frame
+    }
+
+    /// Id do último frame publicado (0 = nenhum)
+    pub fn last_id(&self) -> u64 {
+        self.next_id - 1
+    }
+
+    /// Id do evento SSE
+    pub fn event_id(&self, id: u64) -> String {
+        format!("{}-{}", self.boot, id)
+    }
+
+    /// Converte um Last-Event-ID em id de sequência (None = outra execução ou inválido)
+    pub fn parse_event_id(&self, value: &str) -> Option<u64> {
+        let (boot, id) = value.split_once('-')?;
+        if boot.parse::<u64>().ok()? != self.boot {
+            return None;
+        }
+        id.parse().ok()
+    }
+
+    /// Frames publicados depois de `last_id`. None se alguns já saíram do buffer
+    /// (ou o id ainda não existe): o cliente precisa de um snapshot.
+    pub fn since(&self, last_id: u64) -> Option<Vec<PlcFrame>> {
+        if last_id >= self.next_id {
+            return None;
+        }
+        let oldest = self.frames.front().map(|f| f.id).unwrap_or(self.next_id);
+        if last_id + 1 < oldest {
+            return None;
+        }
+        Some(self.frames.iter().filter(|f| f.id > last_id).cloned().collect())
+    }
+}
+
+// ============================================================================
+// CODIFICADOR DELTA
+// ============================================================================
+
+/// Variáveis mudadas desde o último envio ao cliente
+#[derive(Debug, Clone, Serialize)]
+pub struct PlcDelta {
+    pub timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<String>,
+    pub source_ip: String,
+    pub conn_id: u64,
+    pub plc_name: String,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub variables: HashMap<String, f64>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub texts: HashMap<String, String>,
+    /// Qualidades alteradas ("good" = voltou a estar boa)
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub quality: HashMap<String, Quality>,
+    /// Variáveis que deixaram de existir (ex: Real com NaN fica sem valor)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+impl PlcDelta {
+    fn is_empty(&self) -> bool {
+        self.variables.is_empty() && self.texts.is_empty() && self.quality.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Último estado enviado a um cliente para um PLC
+struct Baseline {
+    sent: PlcData,
+    keyframe_at: Instant,
+}
+
+/// Codificador delta de um cliente SSE (um baseline por PLC de origem)
+pub struct DeltaEncoder {
+    keyframe_interval: Duration,
+    deadbands: HashMap<String, f64>,
+    baselines: HashMap<String, Baseline>,
+}
+
+impl DeltaEncoder {
+    pub fn new(keyframe_interval: Duration, deadbands: HashMap<String, f64>) -> Self {
+        Self { keyframe_interval, deadbands, baselines: HashMap::new() }
+    }
+
+    /// Esquece o que foi enviado: o próximo frame de cada PLC segue como keyframe
+    pub fn reset(&mut self) {
+        self.baselines.clear();
+    }
+
+    /// Payload SSE para o frame (None = nada mudou, não enviar)
+    pub fn encode(&mut self, data: &PlcData) -> Option<serde_json::Value> {
+        let keyframe_due = match self.baselines.get(&data.source_ip) {
+            Some(b) => b.sent.conn_id != data.conn_id || b.keyframe_at.elapsed() >= self.keyframe_interval,
+            None => true,
+        };
+        if keyframe_due {
+            let payload = serde_json::json!({ "message": data, "keyframe": true });
+            self.baselines.insert(data.source_ip.clone(), Baseline { sent: data.clone(), keyframe_at: Instant::now() });
+            return Some(payload);
+        }
+
+        let baseline = self.baselines.get_mut(&data.source_ip)?;
+        let sent = &mut baseline.sent;
+        let mut delta = PlcDelta {
+            timestamp: data.timestamp.clone(),
+            received_at: data.received_at.clone(),
+            source_ip: data.source_ip.clone(),
+            conn_id: data.conn_id,
+            plc_name: data.plc_name.clone(),
+            variables: HashMap::new(),
+            texts: HashMap::new(),
+            quality: HashMap::new(),
+            removed: Vec::new(),
+        };
+
+        // ── Qualidades (uma mudança de qualidade também reenvia o valor) ──
+        for name in data.quality.keys().chain(sent.quality.keys()) {
+            let new = data.quality.get(name).copied().unwrap_or_default();
+            let old = sent.quality.get(name).copied().unwrap_or_default();
+            if new != old {
+                delta.quality.insert(name.clone(), new);
+            }
+        }
+
+        // ── Valores numéricos (com banda morta) ──
+        for (name, &value) in &data.variables {
+            let changed = match sent.variables.get(name) {
+                Some(&old) => delta.quality.contains_key(name) || exceeds_deadband(old, value, deadband_for(&self.deadbands, name)),
+                None => true,
+            };
+            if changed {
+                delta.variables.insert(name.clone(), value);
+                sent.variables.insert(name.clone(), value);
+            }
+        }
+
+        // ── Textos (String, Time, Dtl) ──
+        for (name, text) in &data.texts {
+            if sent.texts.get(name) != Some(text) || delta.quality.contains_key(name) {
+                delta.texts.insert(name.clone(), text.clone());
+                sent.texts.insert(name.clone(), text.clone());
+            }
+        }
+
+        // ── Variáveis que desapareceram ──
+        sent.variables.retain(|name, _| {
+            let keep = data.variables.contains_key(name);
+            if !keep {
+                delta.removed.push(name.clone());
+            }
+            keep
+        });
+        sent.texts.retain(|name, _| {
+            let keep = data.texts.contains_key(name);
+            if !keep && !delta.removed.contains(name) {
+                delta.removed.push(name.clone());
+            }
+            keep
+        });
+
+        sent.quality = data.quality.clone();
+        sent.timestamp = data.timestamp.clone();
+        sent.received_at = data.received_at.clone();
+        sent.plc_name = data.plc_name.clone();
+
+        if delta.is_empty() {
+            return None;
+        }
+        Some(serde_json::json!({ "delta": delta }))
+    }
+}
+
+/// Banda morta da variável: tag exata ("Real[3]") ou segmento ("Real")
+fn deadband_for(deadbands: &HashMap<String, f64>, name: &str) -> f64 {
+    deadbands.get(name)
+        .or_else(|| name.split('[').next().and_then(|segment| deadbands.get(segment)))
+        .copied()
+        .unwrap_or(0.0)
+}
+
+fn exceeds_deadband(old: f64, new: f64, deadband: f64) -> bool {
+    if deadband > 0.0 {
+        (new - old).abs() > deadband
+    } else {
+        new != old
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(conn_id: u64, variables: &[(&str, f64)]) -> PlcData {
+        PlcData {
+            timestamp: String::new(),
+            received_at: None,
+            source_ip: "10.0.0.1".to_string(),
+            conn_id,
+            plc_name: "PLC".to_string(),
+            variables: variables.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            texts: HashMap::new(),
+            quality: HashMap::new(),
+        }
+    }
+
+    fn ids(frames: Option<Vec<PlcFrame>>) -> Option<Vec<u64>> {
+        frames.map(|f| f.iter().map(|f| f.id).collect())
+    }
+
+    fn is_keyframe(payload: &Option<serde_json::Value>) -> bool {
+        matches!(payload, Some(p) if p["keyframe"] == true)
+    }
+
+    #[test]
+    fn keyframe_on_interval_and_conn_id_change() {
+        let mut encoder = DeltaEncoder::new(Duration::from_secs(3600), HashMap::new());
+        assert!(is_keyframe(&encoder.encode(&data(1, &[("Word[0]", 1.0)]))));
+        assert_eq!(encoder.encode(&data(1, &[("Word[0]", 1.0)])), None);
+
+        let delta = encoder.encode(&data(1, &[("Word[0]", 2.0)])).unwrap();
+        assert_eq!(delta["delta"]["variables"], serde_json::json!({ "Word[0]": 2.0 }));
+
+        // PLC voltou a ligar (nova conexão): estado completo outra vez
+        assert!(is_keyframe(&encoder.encode(&data(2, &[("Word[0]", 2.0)]))));
+
+        let mut encoder = DeltaEncoder::new(Duration::ZERO, HashMap::new());
+        assert!(is_keyframe(&encoder.encode(&data(1, &[("Word[0]", 1.0)]))));
+        assert!(is_keyframe(&encoder.encode(&data(1, &[("Word[0]", 1.0)]))));
+    }
+
+    #[test]
+    fn deadband_by_tag_then_segment() {
+        let deadbands = HashMap::from([("Real[3]".to_string(), 0.5), ("Real".to_string(), 2.0)]);
+        let mut encoder = DeltaEncoder::new(Duration::from_secs(3600), deadbands);
+        encoder.encode(&data(1, &[("Real[3]", 10.0), ("Real[4]", 10.0), ("Int[0]", 1.0)]));
+
+        assert_eq!(encoder.encode(&data(1, &[("Real[3]", 10.4), ("Real[4]", 11.5), ("Int[0]", 1.0)])), None);
+
+        // Comparado com o último valor enviado (10), não com o último recebido
+        let delta = encoder.encode(&data(1, &[("Real[3]", 10.6), ("Real[4]", 11.9), ("Int[0]", 1.0)])).unwrap();
+        assert_eq!(delta["delta"]["variables"], serde_json::json!({ "Real[3]": 10.6 }));
+
+        let delta = encoder.encode(&data(1, &[("Real[3]", 10.6), ("Real[4]", 12.1), ("Int[0]", 2.0)])).unwrap();
+        assert_eq!(delta["delta"]["variables"], serde_json::json!({ "Real[4]": 12.1, "Int[0]": 2.0 }));
+    }
+
+    #[test]
+    fn reset_forces_keyframe() {
+        let mut encoder = DeltaEncoder::new(Duration::from_secs(3600), HashMap::new());
+        encoder.encode(&data(1, &[("Word[0]", 1.0)]));
+        encoder.reset();
+        assert!(is_keyframe(&encoder.encode(&data(1, &[("Word[0]", 1.0)]))));
+        assert_eq!(encoder.encode(&data(1, &[("Word[0]", 1.0)])), None);
+    }
+
+    #[test]
+    fn replay_since_empty_buffer() {
+        let buffer = ReplayBuffer::new(3);
+        assert_eq!(buffer.last_id(), 0);
+        assert_eq!(ids(buffer.since(0)), Some(vec![]));
+        assert_eq!(ids(buffer.since(1)), None);
+    }
+
+    #[test]
+    fn replay_since_boundaries_and_eviction() {
+        let mut buffer = ReplayBuffer::new(3);
+        for _ in 0..5 {
+            buffer.push(data(1, &[]));
+        }
+        // Ficaram 3, 4 e 5
+        assert_eq!(buffer.last_id(), 5);
+        assert_eq!(ids(buffer.since(2)), Some(vec![3, 4, 5]));
+        assert_eq!(ids(buffer.since(4)), Some(vec![5]));
+        assert_eq!(ids(buffer.since(5)), Some(vec![]));
+        assert_eq!(ids(buffer.since(1)), None); // O 2 já saiu do buffer
+        assert_eq!(ids(buffer.since(6)), None); // Ainda não existe
+    }
+
+    #[test]
+    fn parses_event_id_of_this_boot_only() {
+        let buffer = ReplayBuffer::new(3);
+        let id = buffer.event_id(42);
+
+        assert_eq!(buffer.parse_event_id(&id), Some(42));
+        assert_eq!(buffer.parse_event_id(&format!("{}-42", buffer.boot + 1)), None);
+        assert_eq!(buffer.parse_event_id("42"), None);
+        assert_eq!(buffer.parse_event_id(&format!("{}-x", buffer.boot)), None);
+        assert_eq!(buffer.parse_event_id("abc-42"), None);
+    }
+}
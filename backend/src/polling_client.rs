@@ -0,0 +1,127 @@
+// polling_client.rs - INFRAESTRUTURA PARTILHADA DOS CLIENTES EM POLLING
+// ============================================================================
+// Modbus TCP, S7 ISO-on-TCP, ...: o servidor liga-se ao equipamento e lê
+// periodicamente. Este módulo trata da reconexão com backoff, do registo da
+// sessão no TcpServer (saúde, watchdog, desconexão forçada) e da gestão das
+// tarefas ativas para que uma alteração de configuração as possa reiniciar.
+// ============================================================================
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+use crate::tcp_server::{ConnectionResult, DataSource, TcpServer};
+
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+const MAX_BACKOFF_SECS: u64 = 30;
+
+/// Tarefas de polling ativas, indexadas por chave de configuração (ex: "modbus:3")
+pub struct PollingManager {
+    server: Arc<TcpServer>,
+    pollers: Mutex<HashMap<String, (JoinHandle<()>, String)>>,
+}
+
+impl PollingManager {
+    pub fn new(server: Arc<TcpServer>) -> Self {
+        Self { server, pollers: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn server(&self) -> &Arc<TcpServer> {
+        &self.server
+    }
+
+    /// Regista uma tarefa de polling (pára a anterior com a mesma chave)
+    pub async fn insert(&self, key: String, session_key: String, handle: JoinHandle<()>) {
+        self.stop(&key).await;
+        self.pollers.lock().await.insert(key, (handle, session_key));
+    }
+
+    /// Pára a tarefa de polling e termina a sessão associada
+    pub async fn stop(&self, key: &str) {
+        if let Some((handle, session_key)) = self.pollers.lock().await.remove(key) {
+            handle.abort();
+            self.server.end_session(&session_key).await;
+            println!("🛑 Polling '{}' parado", key);
+        }
+    }
+}
+
+/// Cliente com reconexão: liga-se a `address`, abre uma sessão com `session_key`
+/// e executa `session` até esta terminar; depois reconecta com backoff exponencial.
+/// A sessão corre numa tarefa própria para o watchdog/desconexão a poderem abortar.
+pub fn spawn_polling_client<F, Fut>(
+    server: Arc<TcpServer>,
+    label: String,
+    address: String,
+    session_key: String,
+    source: DataSource,
+    session: F,
+) -> JoinHandle<()>
+where
+    F: Fn(TcpStream, u64) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ConnectionResult> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut backoff = Duration::from_secs(2);
+        let mut retry_count = 0u32;
+
+        println!("🔄 {}: iniciando polling em {}", label, address);
+
+        loop {
+            if !server.is_running() {
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+
+            if server.is_blacklisted(&session_key).await {
+                sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+
+            match timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS), TcpStream::connect(&address)).await {
+                Ok(Ok(socket)) => {
+                    retry_count = 0;
+                    backoff = Duration::from_secs(2);
+                    let _ = socket.set_nodelay(true);
+
+                    let conn_id = server.open_session(&session_key, &address, source).await;
+
+                    let session_server = server.clone();
+                    let key = session_key.clone();
+                    let future = session(socket, conn_id);
+                    let task = tokio::spawn(async move {
+                        let result = future.await;
+                        session_server.close_session(&key, conn_id, &result).await;
+                    });
+                    server.register_session_handle(&session_key, task.abort_handle()).await;
+                    let _ = task.await;
+
+                    println!("🔄 {}: reconectando...", label);
+                }
+                Ok(Err(e)) => {
+                    retry_count += 1;
+                    if retry_count.is_multiple_of(5) {
+                        eprintln!("❌ {}: falha ao conectar {} (tentativa {}): {}",
+                            label, address, retry_count, e);
+                    }
+                }
+                Err(_) => {
+                    retry_count += 1;
+                    if retry_count.is_multiple_of(5) {
+                        eprintln!("❌ {}: timeout ao conectar {} (tentativa {})",
+                            label, address, retry_count);
+                    }
+                }
+            }
+
+            // Backoff exponencial até 30 segundos
+            sleep(backoff).await;
+            backoff = std::cmp::min(backoff * 2, Duration::from_secs(MAX_BACKOFF_SECS));
+        }
+    })
+}
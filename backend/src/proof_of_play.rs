@@ -0,0 +1,266 @@
+// proof_of_play.rs - REGISTO DE EXIBIÇÃO DE VÍDEOS E RELATÓRIOS
+// ============================================================================
+// Cada ecrã reporta o início e o fim (concluído ou interrompido) de cada vídeo
+// por /api/invoke (report_play_start / report_play_end); o servidor guarda as
+// exibições em video_plays com o seu próprio relógio, para que ecrãs com a
+// hora errada não falsifiquem os tempos.
+//
+// O display_engine regista à parte (ad_gaps) os períodos em que o bit de
+// controle de vídeo esteve a 0 num painel com vídeos para rodar: publicidade
+// suprimida pela eclusa, não falha do ecrã.
+//
+// Relatórios por dia (hora local), painel e vídeo: get_play_report e os CSV
+// /api/reports/plays.csv e /api/reports/ad-gaps.csv (?from=&to=&panel=).
+// ============================================================================
+
+use std::collections::BTreeMap;
+use chrono::{DateTime, Local, NaiveDate, SecondsFormat, TimeZone, Utc};
+use serde::Serialize;
+
+/// Estados de uma exibição (video_plays.status)
+pub const PLAY_PLAYING: &str = "playing";
+pub const PLAY_FINISHED: &str = "finished";
+pub const PLAY_ABORTED: &str = "aborted";
+
+/// Motivo gravado quando a exibição é interrompida com o bit de controle a 0
+pub const REASON_CONTROL_BIT: &str = "control_bit";
+
+/// Período máximo de um relatório (dias)
+pub const MAX_REPORT_DAYS: i64 = 366;
+
+/// Uma exibição de um vídeo num painel
+#[derive(Debug, Clone, Serialize)]
+pub struct VideoPlay {
+    pub id: i64,
+    pub panel: String,
+    pub video_id: i64,
+    pub video_name: String,       // Nome na altura (o vídeo pode ser apagado depois)
+    pub playlist_id: Option<i64>,
+    pub source: String,           // "playlist" ou "bit"
+    pub status: String,           // playing | finished | aborted
+    pub reason: String,           // Motivo da interrupção (vazio se concluído)
+    pub started_at: String,       // RFC 3339 UTC (relógio do servidor)
+    pub ended_at: Option<String>,
+    pub seconds: Option<f64>,     // Tempo no ecrã
+}
+
+/// Período com a publicidade suprimida pelo bit de controle de vídeo
+#[derive(Debug, Clone, Serialize)]
+pub struct AdGap {
+    pub id: i64,
+    pub panel: String,
+    pub started_at: String,
+    pub ended_at: String,
+    pub seconds: f64,
+}
+
+/// Linha do relatório de exibições (dia x painel x vídeo)
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayReportRow {
+    pub day: String,              // AAAA-MM-DD (hora local)
+    pub panel: String,
+    pub video_id: i64,
+    pub video_name: String,
+    pub plays: i64,               // Exibições iniciadas
+    pub finished: i64,
+    pub aborted: i64,
+    pub seconds: f64,             // Tempo total no ecrã
+}
+
+/// Linha do relatório de supressões (dia x painel)
+#[derive(Debug, Clone, Serialize)]
+pub struct AdGapReportRow {
+    pub day: String,
+    pub panel: String,
+    pub gaps: i64,
+    pub seconds: f64,
+}
+
+/// Instante atual no formato gravado na base de dados (comparável como texto)
+pub fn timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Período [from, to] em dias locais → limites UTC [início, fim) para as queries
+pub fn report_range(from: &str, to: &str) -> Result<(String, String), String> {
+    let parse = |value: &str| NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+        .map_err(|_| format!("Data inválida: '{}' (AAAA-MM-DD)", value));
+    let from = parse(from)?;
+    let to = parse(to)?;
+    if from > to {
+        return Err(format!("Período invertido: {} a {}", from, to));
+    }
+    if (to - from).num_days() >= MAX_REPORT_DAYS {
+        return Err(format!("Período demasiado longo (máximo {} dias)", MAX_REPORT_DAYS));
+    }
+    let midnight = |date: NaiveDate| Local.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .map(|local| timestamp(local.with_timezone(&Utc)))
+        .ok_or_else(|| format!("Data inválida: {}", date));
+    Ok((midnight(from)?, midnight(to.succ_opt().unwrap_or(to))?))
+}
+
+/// Dia local de um instante gravado
+fn local_day(at: &str) -> String {
+    DateTime::parse_from_rfc3339(at)
+        .map(|t| t.with_timezone(&Local).format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|_| at.chars().take(10).collect())
+}
+
+/// Agrupa as exibições por dia de início, painel e vídeo
+pub fn play_report(plays: &[VideoPlay]) -> Vec<PlayReportRow> {
+    let mut rows: BTreeMap<(String, String, i64), PlayReportRow> = BTreeMap::new();
+    for play in plays {
+        let day = local_day(&play.started_at);
+        let row = rows.entry((day.clone(), play.panel.clone(), play.video_id)).or_insert_with(|| PlayReportRow {
+            day,
+            panel: play.panel.clone(),
+            video_id: play.video_id,
+            video_name: play.video_name.clone(),
+            plays: 0,
+            finished: 0,
+            aborted: 0,
+            seconds: 0.0,
+        });
+        row.plays += 1;
+        match play.status.as_str() {
+            PLAY_FINISHED => row.finished += 1,
+            PLAY_ABORTED => row.aborted += 1,
+            _ => {}
+        }
+        row.seconds += play.seconds.unwrap_or(0.0);
+    }
+    rows.into_values().collect()
+}
+
+/// Agrupa as supressões por dia de início e painel
+pub fn gap_report(gaps: &[AdGap]) -> Vec<AdGapReportRow> {
+    let mut rows: BTreeMap<(String, String), AdGapReportRow> = BTreeMap::new();
+    for gap in gaps {
+        let day = local_day(&gap.started_at);
+        let row = rows.entry((day.clone(), gap.panel.clone())).or_insert_with(|| AdGapReportRow {
+            day,
+            panel: gap.panel.clone(),
+            gaps: 0,
+            seconds: 0.0,
+        });
+        row.gaps += 1;
+        row.seconds += gap.seconds;
+    }
+    rows.into_values().collect()
+}
+
+// ============================================================================
+// CSV (separador ';', como o Excel em português espera; decimais com ponto)
+// ============================================================================
+
+fn csv_field(value: &str) -> String {
+    if value.contains([';', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn play_report_csv(rows: &[PlayReportRow]) -> String {
+    let mut csv = String::from("day;panel;video_id;video_name;plays;finished;aborted;seconds\n");
+    for r in rows {
+        csv.push_str(&format!(
+            "{};{};{};{};{};{};{};{:.1}\n",
+            r.day, csv_field(&r.panel), r.video_id, csv_field(&r.video_name), r.plays, r.finished, r.aborted, r.seconds
+        ));
+    }
+    csv
+}
+
+pub fn gap_report_csv(rows: &[AdGapReportRow]) -> String {
+    let mut csv = String::from("day;panel;gaps;seconds\n");
+    for r in rows {
+        csv.push_str(&format!("{};{};{};{:.1}\n", r.day, csv_field(&r.panel), r.gaps, r.seconds));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(at: &str) -> DateTime<Local> {
+        DateTime::parse_from_rfc3339(at).unwrap().with_timezone(&Local)
+    }
+
+    /// Meio-dia local do dia `day` de março de 2026, como gravado na base de dados
+    fn noon(day: u32) -> String {
+        timestamp(Local.with_ymd_and_hms(2026, 3, day, 12, 0, 0).unwrap().with_timezone(&Utc))
+    }
+
+    fn play(panel: &str, video_id: i64, day: u32, status: &str, seconds: Option<f64>) -> VideoPlay {
+        VideoPlay {
+            id: 0,
+            panel: panel.to_string(),
+            video_id,
+            video_name: format!("video{}", video_id),
+            playlist_id: None,
+            source: "playlist".to_string(),
+            status: status.to_string(),
+            reason: String::new(),
+            started_at: noon(day),
+            ended_at: None,
+            seconds,
+        }
+    }
+
+    #[test]
+    fn report_range_is_local_days_in_utc() {
+        let (start, end) = report_range("2026-03-01", " 2026-03-31 ").unwrap();
+        assert!(start.ends_with('Z') && end.ends_with('Z'));
+        assert_eq!(local(&start).naive_local(), NaiveDate::from_ymd_opt(2026, 3, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+        assert_eq!(local(&end).naive_local(), NaiveDate::from_ymd_opt(2026, 4, 1).unwrap().and_hms_opt(0, 0, 0).unwrap());
+
+        let (start, end) = report_range("2026-03-02", "2026-03-02").unwrap();
+        assert_eq!(local(&end).date_naive(), NaiveDate::from_ymd_opt(2026, 3, 3).unwrap());
+        assert!(start < end);
+    }
+
+    #[test]
+    fn report_range_limits() {
+        assert!(report_range("2024-01-01", "2024-12-31").is_ok()); // 366 dias (ano bissexto)
+        assert!(report_range("2024-01-01", "2025-01-01").is_err());
+        assert!(report_range("2026-03-02", "2026-03-01").is_err());
+        assert!(report_range("2026-02-30", "2026-03-01").is_err());
+        assert!(report_range("ontem", "2026-03-01").is_err());
+    }
+
+    #[test]
+    fn plays_grouped_by_day_panel_and_video() {
+        let rows = play_report(&[
+            play("a", 1, 2, PLAY_FINISHED, Some(10.0)),
+            play("a", 1, 2, PLAY_ABORTED, Some(2.5)),
+            play("a", 1, 2, PLAY_PLAYING, None),
+            play("b", 1, 2, PLAY_FINISHED, Some(10.0)),
+            play("a", 2, 2, PLAY_FINISHED, Some(5.0)),
+            play("a", 1, 3, PLAY_FINISHED, Some(10.0)),
+        ]);
+
+        let summary: Vec<_> = rows.iter()
+            .map(|r| (r.day.as_str(), r.panel.as_str(), r.video_id, r.plays, r.finished, r.aborted, r.seconds))
+            .collect();
+        assert_eq!(summary, vec![
+            ("2026-03-02", "a", 1, 3, 1, 1, 12.5),
+            ("2026-03-02", "a", 2, 1, 1, 0, 5.0),
+            ("2026-03-02", "b", 1, 1, 1, 0, 10.0),
+            ("2026-03-03", "a", 1, 1, 1, 0, 10.0),
+        ]);
+    }
+
+    #[test]
+    fn csv_quotes_separator_and_quotes() {
+        assert_eq!(csv_field("Entrada"), "Entrada");
+        assert_eq!(csv_field("Eclusa; Norte"), "\"Eclusa; Norte\"");
+        assert_eq!(csv_field("Ecrã \"A\""), "\"Ecrã \"\"A\"\"\"");
+        assert_eq!(csv_field("linha\n2"), "\"linha\n2\"");
+
+        let csv = gap_report_csv(&[AdGapReportRow { day: "2026-03-02".to_string(), panel: "a;b".to_string(), gaps: 2, seconds: 90.25 }]);
+        assert_eq!(csv, "day;panel;gaps;seconds\n2026-03-02;\"a;b\";2;90.2\n");
+    }
+}
@@ -0,0 +1,458 @@
+// s7_client.rs - CLIENTE S7 ISO-on-TCP (RFC1006) PARA POLLING DE DATA BLOCKS
+// ============================================================================
+// Alternativa ao TSEND_C quando não se pode alterar o programa do PLC:
+// o servidor liga-se à porta 102 e lê periodicamente um intervalo de bytes
+// de um DB. Os bytes lidos passam pelo mesmo parse_plc_packet (layout ativo)
+// e pelo mesmo broadcast/saúde/watchdog do tcp_server.
+// ============================================================================
+// PROTOCOLO (só leitura):
+//   TPKT (4 bytes) + COTP (CR/CC na ligação, DT nos dados) + S7comm
+//   1. COTP Connection Request  (TSAP remoto = 0x01 | rack*32+slot)
+//   2. S7 Setup Communication   (negoceia o tamanho do PDU)
+//   3. S7 Read Var (área DB)    em blocos de (PDU - 18) bytes
+//
+// NOTA S7-1200/1500: o DB tem de ter "Optimized block access" desligado e o
+// PLC tem de permitir "PUT/GET communication from remote partner".
+// Para testes sem hardware: `cargo run --bin s7_stub` (src/bin/s7_stub.rs).
+// ============================================================================
+
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::packet_layout::MAX_PACKET_SIZE;
+use crate::polling_client::{spawn_polling_client, PollingManager};
+use crate::tcp_server::{ConnectionResult, DataSource, TcpServer};
+
+pub const DEFAULT_S7_PORT: u16 = 102;
+
+const RESPONSE_TIMEOUT_SECS: u64 = 5;
+const MIN_POLL_INTERVAL_MS: u64 = 100;
+const REQUESTED_PDU_SIZE: u16 = 960;
+const READ_OVERHEAD: usize = 18; // Cabeçalho S7 (12) + parâmetros (2) + item de dados (4)
+
+// Códigos do protocolo
+const TPKT_VERSION: u8 = 0x03;
+const COTP_CR: u8 = 0xE0;
+const COTP_CC: u8 = 0xD0;
+const COTP_DT: u8 = 0xF0;
+const S7_PROTOCOL_ID: u8 = 0x32;
+const S7_JOB: u8 = 0x01;
+const S7_ACK_DATA: u8 = 0x03;
+const S7_FUNC_SETUP: u8 = 0xF0;
+const S7_FUNC_READ: u8 = 0x04;
+const S7_AREA_DB: u8 = 0x84;
+const S7_TRANSPORT_BYTE: u8 = 0x02;
+const S7_RETURN_OK: u8 = 0xFF;
+
+// ============================================================================
+// CONFIGURAÇÃO
+// ============================================================================
+
+/// PLC S7 consultado em polling (lê DB{db_number}.DBB{start_byte} .. +length)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S7Device {
+    #[serde(default)]
+    pub id: i64,
+    pub name: String,
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub rack: u8,
+    #[serde(default = "default_slot")]
+    pub slot: u8,
+    pub db_number: u16,
+    #[serde(default)]
+    pub start_byte: u32,
+    /// Bytes a ler; 0 = tamanho do layout ativo
+    #[serde(default)]
+    pub length: usize,
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_ms: u64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_port() -> u16 { DEFAULT_S7_PORT }
+fn default_slot() -> u8 { 1 }
+fn default_poll_interval() -> u64 { 500 }
+fn default_enabled() -> bool { true }
+
+impl S7Device {
+    /// Identificador da sessão (usado como "IP" em saúde, cache e blacklist)
+    pub fn session_key(&self) -> String {
+        format!("s7://{}:{}/DB{}", self.host, self.port, self.db_number)
+    }
+
+    /// TSAP remoto: 0x01 (PG/OP) + rack/slot da CPU
+    fn remote_tsap(&self) -> [u8; 2] {
+        [0x01, (self.rack << 5) | self.slot]
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Nome do equipamento não pode ser vazio".to_string());
+        }
+        if self.host.trim().is_empty() {
+            return Err("Host do equipamento não pode ser vazio".to_string());
+        }
+        if self.rack > 7 {
+            return Err(format!("Rack inválido: {} (0..7)", self.rack));
+        }
+        if self.slot > 31 {
+            return Err(format!("Slot inválido: {} (0..31)", self.slot));
+        }
+        if self.db_number == 0 {
+            return Err("Número do DB deve ser maior que 0".to_string());
+        }
+        if self.length > MAX_PACKET_SIZE {
+            return Err(format!("Comprimento inválido: {} bytes (máx {})", self.length, MAX_PACKET_SIZE));
+        }
+        check_address(self.start_byte, self.length)?;
+        if self.poll_interval_ms < MIN_POLL_INTERVAL_MS {
+            return Err(format!("Intervalo de polling mínimo: {}ms", MIN_POLL_INTERVAL_MS));
+        }
+        Ok(())
+    }
+}
+
+/// Endereço S7 em bits com 24 bits: o último byte de DBB{start}..+length tem de caber
+fn check_address(start_byte: u32, length: usize) -> Result<(), String> {
+    if (start_byte as usize + length.max(1) - 1) * 8 > 0xFF_FFFF {
+        return Err(format!("Endereço DBB{} fora do alcance", start_byte));
+    }
+    Ok(())
+}
+
+// ============================================================================
+// PROTOCOLO - TPKT / COTP / S7comm
+// ============================================================================
+
+struct S7Connection {
+    socket: TcpStream,
+    pdu_size: u16,
+    pdu_ref: u16,
+}
+
+impl S7Connection {
+    /// Estabelece a ligação ISO (COTP) e negoceia o PDU (Setup Communication)
+    async fn connect(socket: TcpStream, device: &S7Device) -> Result<Self, String> {
+        let mut connection = Self { socket, pdu_size: REQUESTED_PDU_SIZE, pdu_ref: 0 };
+
+        // ── COTP Connection Request ──
+        let tsap = device.remote_tsap();
+        let request = [
+            17, COTP_CR,
+            0x00, 0x00,             // Referência destino
+            0x00, 0x01,             // Referência origem
+            0x00,                   // Classe 0
+            0xC0, 0x01, 0x0A,       // TPDU 1024 bytes
+            0xC1, 0x02, 0x01, 0x00, // TSAP local
+            0xC2, 0x02, tsap[0], tsap[1],
+        ];
+        connection.send_tpkt(&request).await?;
+        let reply = connection.recv_tpkt().await?;
+        if reply.len() < 2 || reply[1] & 0xF0 != COTP_CC {
+            return Err(format!(
+                "Ligação ISO recusada (rack {}, slot {})", device.rack, device.slot
+            ));
+        }
+
+        // ── Setup Communication ──
+        let mut params = vec![S7_FUNC_SETUP, 0x00, 0x00, 0x01, 0x00, 0x01];
+        params.extend_from_slice(&REQUESTED_PDU_SIZE.to_be_bytes());
+        let (params, _) = connection.job(&params).await?;
+        if params.len() < 8 || params[0] != S7_FUNC_SETUP {
+            return Err("Resposta inválida ao Setup Communication".to_string());
+        }
+        connection.pdu_size = u16::from_be_bytes([params[6], params[7]]);
+        if (connection.pdu_size as usize) <= READ_OVERHEAD {
+            return Err(format!("PDU negociado demasiado pequeno: {}", connection.pdu_size));
+        }
+
+        Ok(connection)
+    }
+
+    async fn send_tpkt(&mut self, payload: &[u8]) -> Result<(), String> {
+        let mut frame = Vec::with_capacity(4 + payload.len());
+        frame.extend_from_slice(&[TPKT_VERSION, 0x00]);
+        frame.extend_from_slice(&((4 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.socket.write_all(&frame).await.map_err(|e| e.to_string())
+    }
+
+    /// Lê um TPKT completo e devolve o conteúdo (COTP + S7)
+    async fn recv_tpkt(&mut self) -> Result<Vec<u8>, String> {
+        let mut header = [0u8; 4];
+        timeout(Duration::from_secs(RESPONSE_TIMEOUT_SECS), self.socket.read_exact(&mut header)).await
+            .map_err(|_| format!("Sem resposta em {}s", RESPONSE_TIMEOUT_SECS))?
+            .map_err(|e| e.to_string())?;
+
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        if header[0] != TPKT_VERSION || length < 7 {
+            return Err(format!("TPKT inválido (versão {}, {} bytes)", header[0], length));
+        }
+
+        let mut payload = vec![0u8; length - 4];
+        timeout(Duration::from_secs(RESPONSE_TIMEOUT_SECS), self.socket.read_exact(&mut payload)).await
+            .map_err(|_| format!("Resposta incompleta em {}s", RESPONSE_TIMEOUT_SECS))?
+            .map_err(|e| e.to_string())?;
+        Ok(payload)
+    }
+
+    /// Envia um pedido S7 (Job) e devolve (parâmetros, dados) da resposta
+    async fn job(&mut self, params: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+        self.pdu_ref = self.pdu_ref.wrapping_add(1);
+
+        let mut payload = vec![0x02, COTP_DT, 0x80];
+        payload.extend_from_slice(&[S7_PROTOCOL_ID, S7_JOB, 0x00, 0x00]);
+        payload.extend_from_slice(&self.pdu_ref.to_be_bytes());
+        payload.extend_from_slice(&(params.len() as u16).to_be_bytes());
+        payload.extend_from_slice(&0u16.to_be_bytes());
+        payload.extend_from_slice(params);
+        self.send_tpkt(&payload).await?;
+
+        let reply = self.recv_tpkt().await?;
+        if reply.len() < 3 || reply[1] != COTP_DT {
+            return Err("Resposta sem COTP DT".to_string());
+        }
+
+        let s7 = &reply[3..];
+        if s7.len() < 12 || s7[0] != S7_PROTOCOL_ID || s7[1] != S7_ACK_DATA {
+            return Err("Cabeçalho S7 inválido na resposta".to_string());
+        }
+
+        let pdu_ref = u16::from_be_bytes([s7[4], s7[5]]);
+        if pdu_ref != self.pdu_ref {
+            return Err(format!("PDU inesperado: {} (esperado {})", pdu_ref, self.pdu_ref));
+        }
+        if s7[10] != 0 || s7[11] != 0 {
+            return Err(format!("Erro S7: classe {:#04x}, código {:#04x}", s7[10], s7[11]));
+        }
+
+        let param_len = u16::from_be_bytes([s7[6], s7[7]]) as usize;
+        let data_len = u16::from_be_bytes([s7[8], s7[9]]) as usize;
+        if s7.len() < 12 + param_len + data_len {
+            return Err("Resposta S7 truncada".to_string());
+        }
+
+        Ok((
+            s7[12..12 + param_len].to_vec(),
+            s7[12 + param_len..12 + param_len + data_len].to_vec(),
+        ))
+    }
+
+    /// Lê `length` bytes (≤ PDU - 18) de DB{db}.DBB{start}
+    async fn read_chunk(&mut self, db: u16, start: u32, length: u16) -> Result<Vec<u8>, String> {
+        let address = start * 8; // Endereço em bits
+        let mut params = vec![
+            S7_FUNC_READ, 0x01,  // Read Var, 1 item
+            0x12, 0x0A, 0x10,    // Especificação de variável (S7ANY)
+            S7_TRANSPORT_BYTE,
+        ];
+        params.extend_from_slice(&length.to_be_bytes());
+        params.extend_from_slice(&db.to_be_bytes());
+        params.push(S7_AREA_DB);
+        params.extend_from_slice(&address.to_be_bytes()[1..]);
+
+        let (params, data) = self.job(&params).await?;
+        if params.first() != Some(&S7_FUNC_READ) || data.len() < 4 {
+            return Err("Resposta inválida ao Read Var".to_string());
+        }
+        if data[0] != S7_RETURN_OK {
+            return Err(format!(
+                "Leitura DB{}.DBB{} ({} bytes) recusada: código {:#04x}",
+                db, start, length, data[0]
+            ));
+        }
+
+        // Tamanho em bits para BYTE/WORD/DWORD (0x04), em bytes para os restantes
+        let size = u16::from_be_bytes([data[2], data[3]]) as usize;
+        let size = if data[1] == 0x04 { size / 8 } else { size };
+        if size != length as usize || data.len() < 4 + size {
+            return Err(format!("Leitura devolveu {} bytes (esperado {})", size, length));
+        }
+
+        Ok(data[4..4 + size].to_vec())
+    }
+
+    /// Lê o intervalo completo em blocos que cabem no PDU negociado
+    async fn read_db(&mut self, db: u16, start: u32, length: usize) -> Result<Vec<u8>, String> {
+        let max_chunk = self.pdu_size as usize - READ_OVERHEAD;
+        let mut data = Vec::with_capacity(length);
+        while data.len() < length {
+            let chunk = (length - data.len()).min(max_chunk) as u16;
+            let bytes = self.read_chunk(db, start + data.len() as u32, chunk).await?;
+            data.extend_from_slice(&bytes);
+        }
+        Ok(data)
+    }
+}
+
+// ============================================================================
+// POLLING - uma tarefa por PLC (com reconexão e backoff)
+// ============================================================================
+
+fn poller_key(id: i64) -> String {
+    format!("s7:{}", id)
+}
+
+/// (Re)aplica a configuração de um PLC S7: pára o polling anterior
+/// e inicia um novo se estiver ativo
+pub async fn apply(manager: &PollingManager, device: S7Device) {
+    let key = poller_key(device.id);
+    manager.stop(&key).await;
+    if !device.enabled {
+        return;
+    }
+
+    let server = manager.server().clone();
+    let session_key = device.session_key();
+    let address = format!("{}:{}", device.host, device.port);
+    let label = format!("S7 '{}' (DB{}, {}ms)", device.name, device.db_number, device.poll_interval_ms);
+    let device = Arc::new(device);
+
+    let session_server = server.clone();
+    let handle = spawn_polling_client(
+        server, label, address, session_key.clone(), DataSource::S7,
+        move |socket, conn_id| {
+            let server = session_server.clone();
+            let device = device.clone();
+            async move { poll_session(socket, conn_id, &device, &server).await }
+        },
+    );
+    manager.insert(key, session_key, handle).await;
+}
+
+/// Pára o polling de um PLC S7
+pub async fn stop(manager: &PollingManager, id: i64) {
+    manager.stop(&poller_key(id)).await;
+}
+
+async fn poll_session(
+    socket: TcpStream,
+    conn_id: u64,
+    device: &S7Device,
+    server: &TcpServer,
+) -> ConnectionResult {
+    let key = device.session_key();
+    let key = key.as_str();
+
+    let mut connection = match S7Connection::connect(socket, device).await {
+        Ok(connection) => connection,
+        Err(e) => {
+            server.mark_session_error(key, &e).await;
+            return ConnectionResult::Error(e);
+        }
+    };
+    println!("🔗 S7 '{}': ligação ISO estabelecida (PDU {} bytes)", device.name, connection.pdu_size);
+
+    let mut interval = tokio::time::interval(Duration::from_millis(device.poll_interval_ms));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut total_bytes = 0u64;
+    let mut packet_count = 0u64;
+    let mut layout_name: Option<String> = None;
+
+    loop {
+        interval.tick().await;
+
+        if !server.is_running() {
+            return ConnectionResult::ServerStopped;
+        }
+
+        // Layout do perfil do PLC ou o layout por omissão (lido a cada ciclo para acompanhar alterações)
+        let (layout, detection) = match server.profile_layout(key).await {
+            Some(layout) => (layout, "profile"),
+            None => (server.get_packet_layout().await, "default"),
+        };
+        if layout_name.as_deref() != Some(layout.name.as_str()) {
+            server.set_session_layout(key, &layout.name, detection).await;
+            layout_name = Some(layout.name.clone());
+        }
+        let length = if device.length == 0 { layout.packet_size } else { device.length };
+        // Com length = 0 o tamanho vem do layout: só aqui se sabe se o fim cabe no endereço
+        if let Err(e) = check_address(device.start_byte, length) {
+            server.mark_session_error(key, &e).await;
+            return ConnectionResult::Error(e);
+        }
+
+        match connection.read_db(device.db_number, device.start_byte, length).await {
+            Ok(data) => {
+                total_bytes += data.len() as u64;
+                packet_count += 1;
+                server.record_received(key, data.len(), total_bytes).await;
+
+                if let Err(e) = server.publish_packet(
+                    key, conn_id, &device.name, &data, &layout, packet_count
+                ).await {
+                    let errors = server.record_parse_error(key, &e).await;
+                    if errors <= 10 || errors.is_multiple_of(100) {
+                        eprintln!("⚠️ S7 '{}' erro parsing ciclo #{} ({} erros): {}", device.name, packet_count, errors, e);
+                    }
+                }
+            }
+            Err(e) => {
+                server.mark_session_error(key, &e).await;
+                return ConnectionResult::Error(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "bin/s7_stub.rs"]
+#[allow(dead_code)]
+mod s7_stub;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn address_must_fit_24_bits() {
+        assert!(check_address(0, 1288).is_ok());
+        assert!(check_address(0x1F_FFFF, 0).is_ok());
+        assert!(check_address(0x1F_FFFF, 1).is_ok());
+        assert!(check_address(0x1F_FFFF, 2).is_err());
+        assert!(check_address(0x1F_FF00, 1288).is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_db_in_chunks_from_stub() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = s7_stub::StubConfig { db_number: 1, db_size: 1288, pdu_size: 240, started: std::time::Instant::now() };
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = s7_stub::handle_client(socket, config).await;
+        });
+
+        let device: S7Device = serde_json::from_value(serde_json::json!({
+            "name": "stub", "host": "127.0.0.1", "db_number": 1,
+        })).unwrap();
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let mut connection = S7Connection::connect(socket, &device).await.unwrap();
+        assert_eq!(connection.pdu_size, 240);
+
+        // 1288 bytes em blocos de 222: Int[i] = -i atravessa as fronteiras dos blocos
+        let data = connection.read_db(1, 0, 1288).await.unwrap();
+        assert_eq!(data.len(), 1288);
+        for i in 0..65 {
+            assert_eq!(i16::from_be_bytes([data[130 + i * 2], data[131 + i * 2]]), -(i as i16));
+        }
+
+        let data = connection.read_db(1, 132, 4).await.unwrap();
+        assert_eq!(data, [0xFF, 0xFF, 0xFF, 0xFE]);
+
+        assert!(connection.read_db(2, 0, 4).await.is_err());
+        assert!(connection.read_db(1, 1280, 16).await.is_err());
+        // A ligação continua utilizável depois de uma leitura recusada
+        assert_eq!(connection.read_db(1, 130, 2).await.unwrap(), [0, 0]);
+    }
+}
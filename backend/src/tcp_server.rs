@@ -3,14 +3,17 @@
 // OTIMIZADO PARA: TSEND_C @ 2Hz, 1288 bytes/pacote, conexão direta via cabo
 // ============================================================================
 // FUNCIONALIDADES:
-//   - Recepção e parsing binário (Word[65] + Int[65] + Real[257])
+//   - Recepção e parsing binário guiado por layout configurável (packet_layout)
+//   - Vários layouts registados: escolha por conexão (perfil, tamanho, assinatura)
 //   - Monitoramento de saúde por conexão (ConnectionHealth)
 //   - Watchdog automático para conexões mortas
 //   - Cache de últimos dados para consulta rápida
 //   - Gestão de conexões (desconectar/bloquear/reconectar)
 //   - Tratamento de reconexões e conexões duplicadas
 //   - Logging para banco de dados SQLite
-//   - Emissão de eventos via broadcast channel (plc-connected, tcp-stats, etc.)
+//   - Histórico de sessões (plc_sessions) para relatórios de disponibilidade
+//   - Protocolo enquadrado opcional (magic + sequência + CRC32, ver framing.rs)
+//   - Emissão de eventos tipados (TcpEvent) via broadcast channel (plc-connected, tcp-stats, etc.)
 //   - Modo somente recepção (TSEND_C não espera ACK)
 // ============================================================================
 
@@ -23,19 +26,16 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{broadcast, RwLock};
 use tokio::time::{sleep, timeout};
 use serde::{Deserialize, Serialize};
-use crate::database::Database;
+use crate::capture::FrameCapture;
+use crate::database::{Database, PlcDevice};
+use crate::framing::{FrameDecoder, FrameStats, FramingMode, FRAME_MAGIC, FRAME_OVERHEAD};
+use crate::ip_filter::IpRule;
+use crate::packet_layout::{PacketLayout, Quality, SegmentType};
 
 // ============================================================================
-// CONSTANTES - ESTRUTURA PLC UDT_TCP_Data
+// CONSTANTES
 // ============================================================================
-
-const WORD_COUNT: usize = 65;        // Word[0..64]
-const INT_COUNT: usize = 65;         // Int[0..64]
-const REAL_COUNT: usize = 257;       // Real[0..256]
-const WORD_OFFSET: usize = 0;
-const INT_OFFSET: usize = WORD_COUNT * 2;                // 130
-const REAL_OFFSET: usize = INT_OFFSET + INT_COUNT * 2;   // 260
-const EXPECTED_PACKET_SIZE: usize = REAL_OFFSET + REAL_COUNT * 4; // 1288
+// A estrutura do pacote (UDT) já não é fixa: ver packet_layout.rs
 
 // Timeouts (otimizados para rede industrial com latência variável)
 const READ_TIMEOUT_SECS: u64 = 15;
@@ -43,24 +43,84 @@ const INACTIVITY_TIMEOUT_SECS: u64 = 180;    // 3 minutos sem dados = morto
 const FRAGMENT_WARN_SECS: u64 = 30;
 const FRAGMENT_CLEAR_SECS: u64 = 90;
 const WATCHDOG_INTERVAL_MS: u64 = 2000;      // Verificar a cada 2s
-const MAX_ACCUMULATOR_SIZE: usize = EXPECTED_PACKET_SIZE * 3; // ~3.8KB
+const MAX_ACCUMULATOR_PACKETS: usize = 3;    // Accumulator guarda até 3 pacotes
+pub const STALE_DATA_SECS: u64 = 10;         // Sem dados novos = variáveis com qualidade Stale
+pub const DEFAULT_EXPECTED_GRACE_SECS: u64 = 60; // Tolerância para PLCs esperados ligarem
+pub const DEFAULT_CLOCK_DRIFT_WARN_MS: u64 = 2000; // Desvio do relógio do PLC que gera aviso
 
 // ============================================================================
 // ESTRUTURAS DE DADOS
 // ============================================================================
 
+/// Chave em display_configs que ativa o modo allowlist ("true"/"false")
+pub const ALLOWLIST_ENABLED_KEY: &str = "plc_allowlist_enabled";
+
+/// Chave em display_configs com o modo de enquadramento TSEND_C ("raw", "framed", "auto")
+pub const FRAMING_MODE_KEY: &str = "plc_framing_mode";
+
+/// Origem dos dados de uma sessão
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataSource {
+    Tsend,  // PLC envia frames via TSEND_C (servidor escuta)
+    Modbus, // Polling Modbus TCP (servidor é cliente)
+    S7,     // Polling S7 ISO-on-TCP de um DB (servidor é cliente)
+}
+
+impl DataSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataSource::Tsend => "tsend",
+            DataSource::Modbus => "modbus",
+            DataSource::S7 => "s7",
+        }
+    }
+}
+
 /// Estado de saúde de uma conexão (uso interno, contém Instant)
 #[derive(Debug, Clone)]
 pub struct ConnectionHealth {
     pub ip: String,
     pub conn_id: u64,
+    pub source: DataSource,
     pub connected_at: Instant,
     pub last_data_received: Instant,
     pub total_bytes: u64,
     pub packet_count: u64,
+    pub parse_errors: u64,            // Pacotes recebidos que o layout não conseguiu decodificar
     pub is_alive: bool,
     pub last_error: Option<String>,
     removal_in_progress: bool,
+    session_id: Option<i64>,          // Linha em plc_sessions (histórico)
+    pub frame_stats: Option<FrameStats>, // Só em conexões com protocolo enquadrado
+    pub layout_name: Option<String>,  // Layout escolhido para esta conexão
+    pub layout_detection: Option<String>, // "profile", "signature", "size", "default", "modbus"
+    pub clock: Option<ClockStats>,    // Só com layouts que trazem o carimbo temporal do PLC
+}
+
+/// Desvio entre o relógio do PLC e o do servidor, por conexão.
+/// offset = hora de receção - carimbo do PLC (inclui a latência da rede)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClockStats {
+    pub samples: u64,
+    pub last_offset_ms: i64,
+    pub drift_ms: f64,       // Média móvel do offset (positivo = PLC atrasado)
+    pub jitter_ms: f64,      // Variação média entre offsets consecutivos (RFC 3550)
+    pub drift_warning: bool, // Desvio acima do limite
+}
+
+impl ClockStats {
+    fn update(&mut self, offset_ms: i64) {
+        if self.samples == 0 {
+            self.drift_ms = offset_ms as f64;
+        } else {
+            let delta = (offset_ms - self.last_offset_ms).abs() as f64;
+            self.jitter_ms += (delta - self.jitter_ms) / 16.0;
+            self.drift_ms += (offset_ms as f64 - self.drift_ms) / 8.0;
+        }
+        self.last_offset_ms = offset_ms;
+        self.samples += 1;
+    }
 }
 
 /// Versão serializável de ConnectionHealth (para retornar ao frontend)
@@ -68,12 +128,32 @@ pub struct ConnectionHealth {
 pub struct ConnectionHealthInfo {
     pub ip: String,
     pub conn_id: u64,
+    pub source: DataSource,
     pub connected_secs: u64,
     pub seconds_since_last_data: u64,
     pub total_bytes: u64,
     pub packet_count: u64,
+    pub parse_errors: u64,
     pub is_alive: bool,
     pub last_error: Option<String>,
+    pub frame_stats: Option<FrameStats>,
+    pub layout_name: Option<String>,
+    pub layout_detection: Option<String>,
+    pub clock: Option<ClockStats>,
+}
+
+/// Entrada do inventário: PLC registado e/ou visto nesta execução
+#[derive(Debug, Clone, Serialize)]
+pub struct PlcInventoryItem {
+    pub device_id: Option<i64>,
+    pub name: String,
+    pub location: String,
+    pub ip: String,
+    pub layout_name: Option<String>,
+    pub expected_online: bool,
+    pub status: String,               // "connected", "offline", "missing", "blocked", "unregistered"
+    pub conn_id: Option<u64>,
+    pub missing_secs: Option<u64>,    // Tempo sem ligação (PLCs esperados)
 }
 
 impl ConnectionHealth {
@@ -81,21 +161,62 @@ impl ConnectionHealth {
         ConnectionHealthInfo {
             ip: self.ip.clone(),
             conn_id: self.conn_id,
+            source: self.source,
             connected_secs: self.connected_at.elapsed().as_secs(),
             seconds_since_last_data: self.last_data_received.elapsed().as_secs(),
             total_bytes: self.total_bytes,
             packet_count: self.packet_count,
+            parse_errors: self.parse_errors,
             is_alive: self.is_alive,
             last_error: self.last_error.clone(),
+            frame_stats: self.frame_stats.clone(),
+            layout_name: self.layout_name.clone(),
+            layout_detection: self.layout_detection.clone(),
+            clock: self.clock.clone(),
         }
     }
 }
 
 /// Dados PLC parseados - enviado via broadcast channel para lib.rs
+/// Cada frame identifica o PLC de origem (IP, ID da conexão e nome amigável)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlcData {
-    pub timestamp: String,
+    pub timestamp: String, // Carimbo do PLC (layout com timestamp_segment) ou hora de receção
+    /// Hora de receção no servidor - só presente quando `timestamp` vem do PLC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<String>,
+    pub source_ip: String,
+    pub conn_id: u64,
+    pub plc_name: String,
     pub variables: HashMap<String, f64>,
+    /// Valores de texto (String, Time e Dtl formatados) - não cabem em `variables`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub texts: HashMap<String, String>,
+    /// Qualidade das variáveis que não estão boas (ausente = Good)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub quality: HashMap<String, Quality>,
+}
+
+impl PlcData {
+    /// Marca todas as variáveis como Stale (o PLC deixou de enviar dados)
+    fn mark_stale(&mut self) {
+        let names = self.variables.keys().chain(self.texts.keys())
+            .filter(|name| !name.starts_with('_'));
+        let stale: Vec<String> = names.cloned().collect();
+        for name in stale {
+            self.quality.insert(name, Quality::Stale);
+        }
+        for quality in self.quality.values_mut() {
+            *quality = Quality::Stale;
+        }
+    }
+
+    /// Verifica se o frame pertence ao PLC indicado (IP, nome ou ID da conexão)
+    pub fn matches_plc(&self, filter: &str) -> bool {
+        self.source_ip == filter
+            || self.plc_name == filter
+            || self.conn_id.to_string() == filter
+    }
 }
 
 /// Variável PLC individual (enriquecida com tipo e unidade)
@@ -105,6 +226,8 @@ pub struct PlcVariable {
     pub value: String,
     pub data_type: String,
     pub unit: Option<String>,
+    #[serde(default)]
+    pub quality: Quality,
 }
 
 /// Pacote de dados PLC cacheado para consulta via API
@@ -114,6 +237,10 @@ pub struct PlcDataPacket {
     pub timestamp: u64,
     pub size: usize,
     pub variables: Vec<PlcVariable>,
+    #[serde(skip)]
+    frame: Option<PlcData>,  // Frame tal como foi publicado (snapshot dos clientes SSE)
+    #[serde(skip)]
+    stale_sent: bool,        // Já foi reenviado com qualidade Stale
 }
 
 /// Estatísticas de conexão (retornável ao frontend)
@@ -126,8 +253,112 @@ pub struct ConnectionStats {
     pub plc_status: String,
 }
 
+/// Eventos do servidor TCP - publicados no broadcast channel de eventos
+/// e expostos ao browser como eventos SSE nomeados (ver `TcpEvent::name`)
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum TcpEvent {
+    ServerStarted {
+        port: u16,
+        expected_packet_size: usize,
+    },
+    ServerStopped {},
+    PlcConnected {
+        id: u64,
+        ip: String,
+        address: String,
+    },
+    PlcDisconnected {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        ip: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    PlcForceDisconnected {
+        ip: String,
+        blocked: bool,
+    },
+    PlcMissing {
+        device_id: i64,
+        name: String,
+        ip: String,
+        missing_secs: u64,
+    },
+    PlcClockDrift {
+        ip: String,
+        id: u64,
+        drift_ms: i64,
+        jitter_ms: i64,
+        threshold_ms: u64,
+    },
+    Stats(ConnectionStats),
+    ConnectionSlow {
+        ip: String,
+        id: u64,
+        seconds_since_data: u64,
+    },
+    ConnectionDead {
+        ip: String,
+        id: u64,
+        seconds_since_data: u64,
+        total_bytes: u64,
+        packet_count: u64,
+    },
+    ConnectionTimeout {
+        ip: String,
+        id: u64,
+        reason: String,
+    },
+    ConnectionError {
+        ip: String,
+        id: u64,
+        error: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    DataStats {
+        ip: String,
+        id: u64,
+        bytes_per_second: u64,
+        packets: u64,
+        total_bytes: u64,
+        transfer_rate: String,
+        packets_per_second: u64,
+        avg_packet_size: u64,
+        connection_uptime: u64,
+    },
+}
+
+impl TcpEvent {
+    /// Nome do evento (compatível com os eventos Tauri originais)
+    pub fn name(&self) -> &'static str {
+        match self {
+            TcpEvent::ServerStarted { .. } => "tcp-server-started",
+            TcpEvent::ServerStopped {} => "tcp-server-stopped",
+            TcpEvent::PlcConnected { .. } => "plc-connected",
+            TcpEvent::PlcDisconnected { .. } => "plc-disconnected",
+            TcpEvent::PlcForceDisconnected { .. } => "plc-force-disconnected",
+            TcpEvent::PlcMissing { .. } => "plc-missing",
+            TcpEvent::PlcClockDrift { .. } => "plc-clock-drift",
+            TcpEvent::Stats(_) => "tcp-stats",
+            TcpEvent::ConnectionSlow { .. } => "tcp-connection-slow",
+            TcpEvent::ConnectionDead { .. } => "tcp-connection-dead",
+            TcpEvent::ConnectionTimeout { .. } => "tcp-connection-timeout",
+            TcpEvent::ConnectionError { .. } => "tcp-connection-error",
+            TcpEvent::DataStats { .. } => "plc-data-stats",
+        }
+    }
+}
+
+/// Enquadramento de uma conexão TSEND_C (Detecting = modo auto à espera dos primeiros bytes)
+enum StreamMode {
+    Detecting,
+    Raw,
+    Framed(FrameDecoder),
+}
+
 /// Resultado interno de cada conexão
-enum ConnectionResult {
+pub(crate) enum ConnectionResult {
     Normal(u64),
     Timeout(String),
     Error(String),
@@ -142,42 +373,73 @@ enum ConnectionResult {
 pub struct TcpServer {
     port: u16,
     tx: broadcast::Sender<PlcData>,
+    event_tx: broadcast::Sender<TcpEvent>,
     is_running: Arc<AtomicBool>,
     active_connections: Arc<AtomicU64>,
     total_connection_count: Arc<AtomicU64>,
+    next_conn_id: Arc<AtomicU64>,
     last_data_time: Arc<AtomicU64>,
     database: Option<Weak<Database>>,
+    capture: Option<Arc<FrameCapture>>,
     // Gestão de conexões
     connected_clients: Arc<RwLock<Vec<String>>>,
     connection_handles: Arc<RwLock<HashMap<String, tokio::task::AbortHandle>>>,
     unique_plcs: Arc<RwLock<HashSet<String>>>,
     blacklisted_ips: Arc<RwLock<HashSet<String>>>,
+    allowlist_enabled: Arc<AtomicBool>,
+    allowlist: Arc<RwLock<Vec<IpRule>>>,
+    rejected_logged_at: Arc<RwLock<HashMap<String, Instant>>>,
     ip_to_id: Arc<RwLock<HashMap<String, u64>>>,
+    plc_names: Arc<RwLock<HashMap<String, String>>>,
+    // Inventário (plc_devices) e PLCs esperados em falta: id -> (desde, já avisado)
+    devices: Arc<RwLock<Vec<PlcDevice>>>,
+    missing_since: Arc<RwLock<HashMap<i64, (Instant, bool)>>>,
+    expected_grace_secs: u64,
+    clock_drift_warn_ms: u64,
     bytes_received: Arc<RwLock<HashMap<String, u64>>>,
     // Cache de dados & saúde
     latest_data: Arc<RwLock<HashMap<String, PlcDataPacket>>>,
     connection_health: Arc<RwLock<HashMap<String, ConnectionHealth>>>,
+    // Layout do pacote PLC por omissão + layouts registados (alteráveis em runtime via API)
+    packet_layout: Arc<RwLock<Arc<PacketLayout>>>,
+    layouts: Arc<RwLock<Vec<Arc<PacketLayout>>>>,
+    framing_mode: Arc<RwLock<FramingMode>>,
 }
 
 impl TcpServer {
     pub fn new(port: u16) -> Self {
         let (tx, _) = broadcast::channel(1000);
+        let (event_tx, _) = broadcast::channel(256);
         Self {
             port,
             tx,
+            event_tx,
             is_running: Arc::new(AtomicBool::new(false)),
             active_connections: Arc::new(AtomicU64::new(0)),
             total_connection_count: Arc::new(AtomicU64::new(0)),
+            next_conn_id: Arc::new(AtomicU64::new(1)),
             last_data_time: Arc::new(AtomicU64::new(0)),
             database: None,
+            capture: None,
             connected_clients: Arc::new(RwLock::new(Vec::new())),
             connection_handles: Arc::new(RwLock::new(HashMap::new())),
             unique_plcs: Arc::new(RwLock::new(HashSet::new())),
             blacklisted_ips: Arc::new(RwLock::new(HashSet::new())),
+            allowlist_enabled: Arc::new(AtomicBool::new(false)),
+            allowlist: Arc::new(RwLock::new(Vec::new())),
+            rejected_logged_at: Arc::new(RwLock::new(HashMap::new())),
             ip_to_id: Arc::new(RwLock::new(HashMap::new())),
+            plc_names: Arc::new(RwLock::new(HashMap::new())),
+            devices: Arc::new(RwLock::new(Vec::new())),
+            missing_since: Arc::new(RwLock::new(HashMap::new())),
+            expected_grace_secs: DEFAULT_EXPECTED_GRACE_SECS,
+            clock_drift_warn_ms: DEFAULT_CLOCK_DRIFT_WARN_MS,
             bytes_received: Arc::new(RwLock::new(HashMap::new())),
             latest_data: Arc::new(RwLock::new(HashMap::new())),
             connection_health: Arc::new(RwLock::new(HashMap::new())),
+            packet_layout: Arc::new(RwLock::new(Arc::new(PacketLayout::default_udt()))),
+            layouts: Arc::new(RwLock::new(vec![Arc::new(PacketLayout::default_udt())])),
+            framing_mode: Arc::new(RwLock::new(FramingMode::Auto)),
         }
     }
 
@@ -185,14 +447,310 @@ impl TcpServer {
         self.database = Some(database);
     }
 
+    pub fn set_capture(&mut self, capture: Arc<FrameCapture>) {
+        self.capture = Some(capture);
+    }
+
     pub fn subscribe(&self) -> broadcast::Receiver<PlcData> {
         self.tx.subscribe()
     }
 
-    // ====== Emissão de eventos (log) ======
-    fn emit_event(&self, event: &str, _data: serde_json::Value) {
-        // Eventos são informativos - PLC data vai pelo broadcast channel
-        let _ = event; // silenciar warnings
+    pub fn subscribe_events(&self) -> broadcast::Receiver<TcpEvent> {
+        self.event_tx.subscribe()
+    }
+
+    // ====== Layout do pacote ======
+    pub async fn get_packet_layout(&self) -> Arc<PacketLayout> {
+        self.packet_layout.read().await.clone()
+    }
+
+    /// Substitui o layout ativo (por omissão). Conexões abertas que o usam
+    /// passam a usá-lo no próximo read.
+    pub async fn set_packet_layout(&self, layout: PacketLayout) -> Result<(), String> {
+        layout.validate()?;
+        println!("📐 Layout do pacote: {} ({} bytes: {})",
+            layout.name, layout.packet_size, layout.summary());
+        let layout = Arc::new(layout);
+        self.register_layout(layout.clone()).await;
+        *self.packet_layout.write().await = layout;
+        Ok(())
+    }
+
+    /// Substitui os layouts registados (inválidos são ignorados com aviso)
+    pub async fn set_layouts(&self, layouts: Vec<PacketLayout>) {
+        let mut valid = Vec::with_capacity(layouts.len());
+        for layout in layouts {
+            match layout.validate() {
+                Ok(()) => valid.push(Arc::new(layout)),
+                Err(e) => eprintln!("⚠️ Layout {} ignorado: {}", layout.name, e),
+            }
+        }
+        let default = self.get_packet_layout().await;
+        if !valid.iter().any(|l| l.name == default.name) {
+            valid.push(default);
+        }
+        println!("📐 {} layout(s) registado(s): {}", valid.len(),
+            valid.iter().map(|l| format!("{} ({}B)", l.name, l.packet_size)).collect::<Vec<_>>().join(", "));
+        *self.layouts.write().await = valid;
+    }
+
+    /// Regista ou atualiza um layout (sem mudar o layout por omissão)
+    pub async fn register_layout(&self, layout: Arc<PacketLayout>) {
+        let mut layouts = self.layouts.write().await;
+        layouts.retain(|l| l.name != layout.name);
+        layouts.push(layout);
+    }
+
+    pub async fn unregister_layout(&self, name: &str) {
+        self.layouts.write().await.retain(|l| l.name != name);
+    }
+
+    pub async fn get_layouts(&self) -> Vec<Arc<PacketLayout>> {
+        self.layouts.read().await.clone()
+    }
+
+    async fn layout_by_name(&self, name: &str) -> Option<Arc<PacketLayout>> {
+        self.layouts.read().await.iter().find(|l| l.name == name).cloned()
+    }
+
+    /// Layout do perfil do PLC registado no inventário (plc_devices.layout_name)
+    pub(crate) async fn profile_layout(&self, ip: &str) -> Option<Arc<PacketLayout>> {
+        let name = self.devices.read().await.iter()
+            .find(|d| d.expected_ip == ip)
+            .and_then(|d| d.layout_name.clone())?;
+        self.layout_by_name(&name).await
+    }
+
+    /// Regista na saúde da conexão o layout escolhido e como foi escolhido
+    pub(crate) async fn set_session_layout(&self, ip: &str, layout_name: &str, detection: &str) {
+        let mut health = self.connection_health.write().await;
+        if let Some(h) = health.get_mut(ip) {
+            h.layout_name = Some(layout_name.to_string());
+            h.layout_detection = Some(detection.to_string());
+        }
+    }
+
+    // ====== Enquadramento (aplica-se às conexões novas) ======
+    pub async fn get_framing_mode(&self) -> FramingMode {
+        *self.framing_mode.read().await
+    }
+
+    pub async fn set_framing_mode(&self, mode: FramingMode) {
+        println!("🧩 Enquadramento TSEND_C: {}", mode.as_str());
+        *self.framing_mode.write().await = mode;
+    }
+
+    // ====== Inventário de PLCs (nomes amigáveis + PLCs esperados) ======
+    pub fn set_expected_grace_secs(&mut self, secs: u64) {
+        self.expected_grace_secs = secs;
+    }
+
+    pub fn set_clock_drift_warn_ms(&mut self, ms: u64) {
+        self.clock_drift_warn_ms = ms;
+    }
+
+    /// Substitui o inventário (arranque e após alterações via API)
+    pub async fn set_devices(&self, devices: Vec<PlcDevice>) {
+        *self.plc_names.write().await = devices.iter()
+            .map(|d| (d.expected_ip.clone(), d.name.clone()))
+            .collect();
+        self.missing_since.write().await
+            .retain(|id, _| devices.iter().any(|d| d.id == *id && d.expected_online));
+        *self.devices.write().await = devices;
+    }
+
+    /// Nome amigável do PLC (fallback: "PLC <ip>")
+    pub async fn get_plc_name(&self, ip: &str) -> String {
+        self.plc_names.read().await.get(ip)
+            .cloned()
+            .unwrap_or_else(|| format!("PLC {}", ip))
+    }
+
+    /// Avisa quando um PLC esperado não liga dentro do período de tolerância.
+    /// O período conta desde o arranque do servidor ou desde a última desconexão.
+    async fn check_expected_plcs(&self) {
+        let devices = self.devices.read().await.clone();
+        let connected: HashSet<String> = self.connection_health.read().await.keys().cloned().collect();
+
+        for device in devices.iter().filter(|d| d.expected_online) {
+            if connected.contains(&device.expected_ip) {
+                let previous = self.missing_since.write().await.remove(&device.id);
+                if let Some((since, true)) = previous {
+                    println!("✅ PLC esperado '{}' ({}) voltou após {}s",
+                        device.name, device.expected_ip, since.elapsed().as_secs());
+                    self.log_to_db("info", "plc",
+                        &format!("PLC esperado '{}' voltou a ligar", device.name),
+                        &format!("IP: {} | Ausente: {}s", device.expected_ip, since.elapsed().as_secs())
+                    ).await;
+                }
+                continue;
+            }
+
+            let missing_secs = {
+                let mut missing = self.missing_since.write().await;
+                let entry = missing.entry(device.id).or_insert((Instant::now(), false));
+                let secs = entry.0.elapsed().as_secs();
+                if entry.1 || secs < self.expected_grace_secs {
+                    continue;
+                }
+                entry.1 = true;
+                secs
+            };
+
+            println!("🚨 PLC ESPERADO EM FALTA: '{}' ({}) sem ligação há {}s",
+                device.name, device.expected_ip, missing_secs);
+            self.log_to_db("warning", "plc",
+                &format!("PLC esperado '{}' não está ligado", device.name),
+                &format!("IP: {} | Local: {} | Sem ligação há {}s (tolerância {}s)",
+                    device.expected_ip, device.location, missing_secs, self.expected_grace_secs)
+            ).await;
+            self.emit_event(TcpEvent::PlcMissing {
+                device_id: device.id,
+                name: device.name.clone(),
+                ip: device.expected_ip.clone(),
+                missing_secs,
+            });
+        }
+    }
+
+    /// Inventário: PLCs registados + IPs vistos nesta execução que não estão registados
+    pub async fn get_inventory(&self) -> Vec<PlcInventoryItem> {
+        let devices = self.devices.read().await.clone();
+        let health = self.connection_health.read().await;
+        let blacklisted = self.blacklisted_ips.read().await;
+        let missing = self.missing_since.read().await;
+        let unique = self.unique_plcs.read().await;
+
+        let status_of = |ip: &str| {
+            if blacklisted.contains(ip) {
+                "blocked"
+            } else if health.contains_key(ip) {
+                "connected"
+            } else {
+                "offline"
+            }
+        };
+
+        let mut items: Vec<PlcInventoryItem> = devices.iter().map(|d| {
+            let mut status = status_of(&d.expected_ip);
+            let missing_entry = missing.get(&d.id).filter(|_| d.expected_online && status == "offline");
+            if missing_entry.is_some_and(|(_, warned)| *warned) {
+                status = "missing";
+            }
+            PlcInventoryItem {
+                device_id: Some(d.id),
+                name: d.name.clone(),
+                location: d.location.clone(),
+                ip: d.expected_ip.clone(),
+                layout_name: d.layout_name.clone(),
+                expected_online: d.expected_online,
+                status: status.to_string(),
+                conn_id: health.get(&d.expected_ip).map(|h| h.conn_id),
+                missing_secs: missing_entry.map(|(since, _)| since.elapsed().as_secs()),
+            }
+        }).collect();
+
+        let mut unregistered: Vec<&String> = unique.iter()
+            .chain(health.keys())
+            .filter(|ip| !devices.iter().any(|d| &d.expected_ip == *ip))
+            .collect();
+        unregistered.sort();
+        unregistered.dedup();
+        items.extend(unregistered.into_iter().map(|ip| PlcInventoryItem {
+            device_id: None,
+            name: format!("PLC {}", ip),
+            location: String::new(),
+            ip: ip.clone(),
+            layout_name: None,
+            expected_online: false,
+            status: if status_of(ip) == "connected" { "unregistered" } else { status_of(ip) }.to_string(),
+            conn_id: health.get(ip).map(|h| h.conn_id),
+            missing_secs: None,
+        }));
+
+        items
+    }
+
+    // ====== Emissão de eventos (broadcast channel de eventos) ======
+    fn emit_event(&self, event: TcpEvent) {
+        // Sem subscritores o envio falha - eventos são descartáveis
+        let _ = self.event_tx.send(event);
+    }
+
+    async fn emit_stats(&self) {
+        let stats = self.get_connection_stats().await;
+        self.emit_event(TcpEvent::Stats(stats));
+    }
+
+    // ====== Blacklist / allowlist ======
+
+    /// Restaura a blacklist persistida (arranque)
+    pub async fn set_blacklist(&self, ips: HashSet<String>) {
+        if !ips.is_empty() {
+            println!("🚫 {} PLC(s) bloqueado(s) restaurado(s)", ips.len());
+        }
+        *self.blacklisted_ips.write().await = ips;
+    }
+
+    /// Ativa/desativa o modo allowlist e substitui as regras
+    pub async fn set_allowlist(&self, enabled: bool, rules: Vec<IpRule>) {
+        let count = rules.len();
+        *self.allowlist.write().await = rules;
+        self.allowlist_enabled.store(enabled, Ordering::SeqCst);
+        if enabled {
+            println!("🛡️ Modo allowlist ATIVO ({} regra(s))", count);
+            if count == 0 {
+                println!("⚠️ Allowlist vazia: todas as conexões de PLC serão recusadas");
+            }
+        }
+    }
+
+    /// Recarrega allowlist e modo a partir da base de dados.
+    /// Entradas inválidas são ignoradas com aviso (não bloqueiam o arranque).
+    pub async fn reload_allowlist(&self) -> Result<(), String> {
+        let db = self.database.as_ref().and_then(|w| w.upgrade())
+            .ok_or("Base de dados indisponível")?;
+
+        let enabled = db.get_display_config(ALLOWLIST_ENABLED_KEY).await
+            .map_err(|e| e.to_string())?
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
+        let mut rules = Vec::new();
+        for entry in db.get_allowlist().await.map_err(|e| e.to_string())? {
+            match IpRule::parse(&entry.entry) {
+                Ok(rule) => rules.push(rule),
+                Err(e) => eprintln!("⚠️ Allowlist: entrada #{} ignorada: {}", entry.id, e),
+            }
+        }
+
+        self.set_allowlist(enabled, rules).await;
+        Ok(())
+    }
+
+    /// Verifica se o IP pode ligar-se (sempre true com o modo allowlist desligado)
+    async fn is_allowed(&self, ip: &std::net::IpAddr) -> bool {
+        if !self.allowlist_enabled.load(Ordering::SeqCst) {
+            return true;
+        }
+        self.allowlist.read().await.iter().any(|rule| rule.contains(ip))
+    }
+
+    /// Regista a tentativa recusada em system_logs (no máximo 1x por minuto por IP)
+    async fn log_rejected(&self, ip: &str, address: &str) {
+        {
+            let mut logged = self.rejected_logged_at.write().await;
+            if let Some(at) = logged.get(ip) {
+                if at.elapsed() < Duration::from_secs(60) {
+                    return;
+                }
+            }
+            logged.insert(ip.to_string(), Instant::now());
+        }
+        self.log_to_db("warning", "security",
+            &format!("Conexão recusada: {} fora da allowlist", ip),
+            &format!("Endereço: {}", address)
+        ).await;
     }
 
     // ====== Logging para banco de dados ======
@@ -228,31 +786,32 @@ impl TcpServer {
 
         println!("═══════════════════════════════════════════════════════════");
         println!("🚀 SERVIDOR TCP INICIADO NA PORTA {}", self.port);
+        let layout = self.get_packet_layout().await;
+
         println!("═══════════════════════════════════════════════════════════");
         println!("⚡ Otimizado para PLC Siemens S7-1500 (TSEND_C @ 2Hz)");
         println!("📡 Modo: SOMENTE RECEPÇÃO (sem ACK)");
-        println!("📦 Pacote esperado: {} bytes (Word[{}] + Int[{}] + Real[{}])",
-            EXPECTED_PACKET_SIZE, WORD_COUNT, INT_COUNT, REAL_COUNT);
+        println!("📦 Pacote esperado: {} bytes ({}) - layout {}",
+            layout.packet_size, layout.summary(), layout.name);
+        println!("🧩 Enquadramento: {}", self.get_framing_mode().await.as_str());
         println!("⏱️  Timeout leitura: {}s | Inatividade: {}s",
             READ_TIMEOUT_SECS, INACTIVITY_TIMEOUT_SECS);
         println!("═══════════════════════════════════════════════════════════");
 
-        self.emit_event("tcp-server-started", serde_json::json!({
-            "port": self.port,
-            "expected_packet_size": EXPECTED_PACKET_SIZE
-        }));
+        self.emit_event(TcpEvent::ServerStarted {
+            port: self.port,
+            expected_packet_size: layout.packet_size,
+        });
 
         self.log_to_db("info", "tcp",
             "Servidor TCP iniciado",
-            &format!("Porta: {} | Pacote: {} bytes", self.port, EXPECTED_PACKET_SIZE)
+            &format!("Porta: {} | Pacote: {} bytes | Layout: {}", self.port, layout.packet_size, layout.name)
         ).await;
 
         // Iniciar watchdog em background
         let watchdog_self = self.clone();
         tokio::spawn(async move { watchdog_self.run_watchdog().await; });
 
-        let mut next_id = 1u64;
-
         // ── Accept loop ──
         while self.is_running.load(Ordering::SeqCst) {
             let accept_result = timeout(
@@ -265,79 +824,32 @@ impl TcpServer {
                     let ip = addr.ip().to_string();
 
                     // ── Blacklist check ──
-                    if self.blacklisted_ips.read().await.contains(&ip) {
+                    if self.is_blacklisted(&ip).await {
                         println!("🚫 CONEXÃO RECUSADA: {} (bloqueado)", ip);
                         drop(socket);
                         continue;
                     }
 
+                    // ── Allowlist check ──
+                    if !self.is_allowed(&addr.ip()).await {
+                        println!("🛡️ CONEXÃO RECUSADA: {} (fora da allowlist)", ip);
+                        drop(socket);
+                        self.log_rejected(&ip, &addr.to_string()).await;
+                        continue;
+                    }
+
                     // ── Conexão duplicada: matar anterior ──
                     if self.connection_handles.read().await.contains_key(&ip) {
                         println!("⚠️ CONEXÃO DUPLICADA: {} - Matando antiga!", ip);
                         if let Some(old_handle) = self.connection_handles.write().await.remove(&ip) {
                             old_handle.abort();
-                            self.connection_health.write().await.remove(&ip);
+                            let health = self.connection_health.write().await.remove(&ip);
+                            self.end_session_record(health, "replaced").await;
                             sleep(Duration::from_millis(100)).await;
                         }
                     }
 
-                    // ── Atribuir ID (manter mesmo para reconexões) ──
-                    let conn_id = {
-                        let mut id_map = self.ip_to_id.write().await;
-                        if let Some(&existing_id) = id_map.get(&ip) {
-                            println!("🔄 RECONEXÃO: {} (ID #{})", ip, existing_id);
-                            existing_id
-                        } else {
-                            let new_id = next_id;
-                            next_id += 1;
-                            id_map.insert(ip.clone(), new_id);
-                            println!("🆕 NOVA CONEXÃO: {} (ID #{})", ip, new_id);
-                            new_id
-                        }
-                    };
-
-                    // ── Registrar saúde ──
-                    let now = Instant::now();
-                    self.connection_health.write().await.insert(ip.clone(), ConnectionHealth {
-                        ip: ip.clone(),
-                        conn_id,
-                        connected_at: now,
-                        last_data_received: now,
-                        total_bytes: 0,
-                        packet_count: 0,
-                        is_alive: true,
-                        last_error: None,
-                        removal_in_progress: false,
-                    });
-
-                    // ── Registrar cliente ──
-                    self.connected_clients.write().await.push(ip.clone());
-                    self.unique_plcs.write().await.insert(ip.clone());
-
-                    let current_active = self.active_connections.fetch_add(1, Ordering::SeqCst) + 1;
-                    self.total_connection_count.fetch_add(1, Ordering::SeqCst);
-                    let total_unique = self.unique_plcs.read().await.len() as u64;
-
-                    println!("✅ PLC CONECTADO: {} (ID: {}) | Ativos: {}", ip, conn_id, current_active);
-
-                    // Eventos Tauri
-                    self.emit_event("plc-connected", serde_json::json!({
-                        "id": conn_id,
-                        "ip": ip,
-                        "address": addr.to_string()
-                    }));
-
-                    self.emit_event("tcp-stats", serde_json::json!({
-                        "active_connections": current_active,
-                        "total_connections": total_unique,
-                        "server_status": "Rodando",
-                        "plc_status": "Conectado"
-                    }));
-
-                    self.log_to_db("info", "plc",
-                        &format!("PLC conectado: {} (ID #{})", ip, conn_id),
-                        &format!("Endereço: {} | Ativos: {}", addr, current_active)
-                    ).await;
+                    let conn_id = self.open_session(&ip, &addr.to_string(), DataSource::Tsend).await;
 
                     // ── Spawn handler ──
                     let server = self.clone();
@@ -347,73 +859,11 @@ impl TcpServer {
                         let result = handle_client_connection(
                             socket, conn_id, ip_clone.clone(), &server
                         ).await;
-
-                        // ── Cleanup após desconexão ──
-                        let should_cleanup = {
-                            let mut health = server.connection_health.write().await;
-                            if let Some(h) = health.get_mut(&ip_clone) {
-                                if !h.removal_in_progress {
-                                    h.removal_in_progress = true;
-                                    true
-                                } else { false }
-                            } else { false }
-                        };
-
-                        if should_cleanup {
-                            match &result {
-                                ConnectionResult::Normal(bytes) => {
-                                    println!("📊 PLC {} desconectou normalmente. Total: {} bytes", ip_clone, bytes);
-                                }
-                                ConnectionResult::Timeout(reason) => {
-                                    println!("⏰ PLC {} timeout: {}", ip_clone, reason);
-                                    server.emit_event("tcp-connection-timeout", serde_json::json!({
-                                        "ip": ip_clone, "id": conn_id, "reason": reason
-                                    }));
-                                }
-                                ConnectionResult::Error(error) => {
-                                    println!("❌ PLC {} erro: {}", ip_clone, error);
-                                    server.log_to_db("error", "tcp",
-                                        &format!("Erro na conexão PLC {}", ip_clone), error
-                                    ).await;
-                                    server.emit_event("tcp-connection-error", serde_json::json!({
-                                        "ip": ip_clone, "id": conn_id, "error": error
-                                    }));
-                                }
-                                ConnectionResult::ServerStopped => {
-                                    println!("🛑 PLC {} - servidor parou", ip_clone);
-                                }
-                            }
-
-                            // Remover dos registros
-                            server.connected_clients.write().await.retain(|x| x != &ip_clone);
-                            server.connection_handles.write().await.remove(&ip_clone);
-                            server.connection_health.write().await.remove(&ip_clone);
-
-                            let remaining = server.active_connections.fetch_sub(1, Ordering::SeqCst).saturating_sub(1);
-                            let total_unique = server.unique_plcs.read().await.len() as u64;
-
-                            println!("❌ PLC DESCONECTADO: {} | Ativos: {}", ip_clone, remaining);
-
-                            server.emit_event("plc-disconnected", serde_json::json!({
-                                "id": conn_id, "ip": ip_clone
-                            }));
-
-                            server.emit_event("tcp-stats", serde_json::json!({
-                                "active_connections": remaining,
-                                "total_connections": total_unique,
-                                "server_status": "Rodando",
-                                "plc_status": if remaining > 0 { "Conectado" } else { "Desconectado" }
-                            }));
-
-                            server.log_to_db("info", "plc",
-                                &format!("PLC desconectado: {}", ip_clone),
-                                &format!("ID: {} | Ativos restantes: {}", conn_id, remaining)
-                            ).await;
-                        }
+                        server.close_session(&ip_clone, conn_id, &result).await;
                     });
 
                     // Registrar handle para poder abortar depois
-                    self.connection_handles.write().await.insert(ip, connection_handle.abort_handle());
+                    self.register_session_handle(&ip, connection_handle.abort_handle()).await;
                 }
                 Ok(Err(e)) => {
                     eprintln!("❌ Erro ao aceitar conexão: {}", e);
@@ -430,6 +880,350 @@ impl TcpServer {
         Ok(())
     }
 
+    // ====================================================================
+    // SESSÕES - Registo/limpeza partilhados por todas as fontes de dados
+    // (TSEND_C recebido, Modbus TCP em polling, ...)
+    // ====================================================================
+
+    /// Regista uma nova sessão (saúde, clientes, eventos) e devolve o ID da conexão.
+    /// O ID mantém-se para reconexões do mesmo IP.
+    pub(crate) async fn open_session(&self, ip: &str, address: &str, source: DataSource) -> u64 {
+        // ── Atribuir ID (manter mesmo para reconexões) ──
+        let conn_id = {
+            let mut id_map = self.ip_to_id.write().await;
+            if let Some(&existing_id) = id_map.get(ip) {
+                println!("🔄 RECONEXÃO: {} (ID #{})", ip, existing_id);
+                existing_id
+            } else {
+                let new_id = self.next_conn_id.fetch_add(1, Ordering::SeqCst);
+                id_map.insert(ip.to_string(), new_id);
+                println!("🆕 NOVA CONEXÃO: {} (ID #{})", ip, new_id);
+                new_id
+            }
+        };
+
+        // ── Abrir sessão no histórico ──
+        let session_id = match self.database.as_ref().and_then(|w| w.upgrade()) {
+            Some(db) => {
+                let plc_name = self.get_plc_name(ip).await;
+                db.open_plc_session(ip, &plc_name, conn_id, source.as_str()).await
+                    .map_err(|e| eprintln!("⚠️ Erro ao registar sessão de {}: {:?}", ip, e))
+                    .ok()
+            }
+            None => None,
+        };
+
+        // ── Registrar saúde ──
+        let now = Instant::now();
+        self.connection_health.write().await.insert(ip.to_string(), ConnectionHealth {
+            ip: ip.to_string(),
+            conn_id,
+            source,
+            connected_at: now,
+            last_data_received: now,
+            total_bytes: 0,
+            packet_count: 0,
+            parse_errors: 0,
+            is_alive: true,
+            last_error: None,
+            removal_in_progress: false,
+            session_id,
+            frame_stats: None,
+            layout_name: None,
+            layout_detection: None,
+            clock: None,
+        });
+
+        // ── Registrar cliente ──
+        self.connected_clients.write().await.push(ip.to_string());
+        self.unique_plcs.write().await.insert(ip.to_string());
+
+        let current_active = self.active_connections.fetch_add(1, Ordering::SeqCst) + 1;
+        self.total_connection_count.fetch_add(1, Ordering::SeqCst);
+
+        println!("✅ PLC CONECTADO: {} (ID: {}) | Ativos: {}", ip, conn_id, current_active);
+
+        // Eventos
+        self.emit_event(TcpEvent::PlcConnected {
+            id: conn_id,
+            ip: ip.to_string(),
+            address: address.to_string(),
+        });
+        self.emit_stats().await;
+
+        self.log_to_db("info", "plc",
+            &format!("PLC conectado: {} (ID #{})", ip, conn_id),
+            &format!("Endereço: {} | Origem: {} | Ativos: {}", address, source.as_str(), current_active)
+        ).await;
+
+        conn_id
+    }
+
+    /// Cleanup após desconexão (ignorado se o watchdog já removeu a sessão)
+    pub(crate) async fn close_session(&self, ip: &str, conn_id: u64, result: &ConnectionResult) {
+        let should_cleanup = {
+            let mut health = self.connection_health.write().await;
+            if let Some(h) = health.get_mut(ip) {
+                if !h.removal_in_progress {
+                    h.removal_in_progress = true;
+                    true
+                } else { false }
+            } else { false }
+        };
+
+        if !should_cleanup {
+            return;
+        }
+
+        let end_reason = match result {
+            ConnectionResult::Normal(_) => "normal",
+            ConnectionResult::Timeout(_) => "timeout",
+            ConnectionResult::Error(_) => "error",
+            ConnectionResult::ServerStopped => "server_stopped",
+        };
+
+        match result {
+            ConnectionResult::Normal(bytes) => {
+                println!("📊 PLC {} desconectou normalmente. Total: {} bytes", ip, bytes);
+            }
+            ConnectionResult::Timeout(reason) => {
+                println!("⏰ PLC {} timeout: {}", ip, reason);
+                self.emit_event(TcpEvent::ConnectionTimeout {
+                    ip: ip.to_string(), id: conn_id, reason: reason.clone(),
+                });
+            }
+            ConnectionResult::Error(error) => {
+                println!("❌ PLC {} erro: {}", ip, error);
+                self.log_to_db("error", "tcp",
+                    &format!("Erro na conexão PLC {}", ip), error
+                ).await;
+                self.emit_event(TcpEvent::ConnectionError {
+                    ip: ip.to_string(), id: conn_id, error: error.clone(),
+                });
+            }
+            ConnectionResult::ServerStopped => {
+                println!("🛑 PLC {} - servidor parou", ip);
+            }
+        }
+
+        // Remover dos registros
+        self.connected_clients.write().await.retain(|x| x != ip);
+        self.connection_handles.write().await.remove(ip);
+        let health = self.connection_health.write().await.remove(ip);
+        self.end_session_record(health, end_reason).await;
+
+        let remaining = self.active_connections.fetch_sub(1, Ordering::SeqCst).saturating_sub(1);
+
+        println!("❌ PLC DESCONECTADO: {} | Ativos: {}", ip, remaining);
+
+        self.emit_event(TcpEvent::PlcDisconnected {
+            id: Some(conn_id), ip: ip.to_string(), reason: None,
+        });
+        self.emit_stats().await;
+
+        self.log_to_db("info", "plc",
+            &format!("PLC desconectado: {}", ip),
+            &format!("ID: {} | Ativos restantes: {}", conn_id, remaining)
+        ).await;
+    }
+
+    /// Regista bytes recebidos numa sessão (timestamp global, contadores e saúde)
+    pub(crate) async fn record_received(&self, ip: &str, n: usize, total_bytes: u64) {
+        // Atualizar timestamp global
+        let now_ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        self.last_data_time.store(now_ts, Ordering::SeqCst);
+
+        // Atualizar bytes recebidos
+        {
+            let mut bytes = self.bytes_received.write().await;
+            *bytes.entry(ip.to_string()).or_insert(0) += n as u64;
+        }
+
+        // Atualizar saúde da conexão
+        {
+            let mut health = self.connection_health.write().await;
+            if let Some(h) = health.get_mut(ip) {
+                h.last_data_received = Instant::now();
+                h.total_bytes = total_bytes;
+                h.is_alive = true;
+            }
+        }
+    }
+
+    /// Atualiza as estatísticas de enquadramento (perdas, CRC, ressincronizações)
+    async fn update_frame_stats(&self, ip: &str, stats: FrameStats) {
+        let mut health = self.connection_health.write().await;
+        if let Some(h) = health.get_mut(ip) {
+            h.frame_stats = Some(stats);
+        }
+    }
+
+    /// Atualiza a estimativa de desvio do relógio do PLC e avisa quando passa o limite
+    /// (o aviso só é retirado abaixo de 80% do limite, para não oscilar)
+    async fn update_clock(&self, ip: &str, conn_id: u64, offset_ms: i64) {
+        let threshold = self.clock_drift_warn_ms as f64;
+        let clock = {
+            let mut health = self.connection_health.write().await;
+            let Some(h) = health.get_mut(ip) else { return };
+            let clock = h.clock.get_or_insert_with(ClockStats::default);
+            clock.update(offset_ms);
+            let drift = clock.drift_ms.abs();
+            let warn = if clock.drift_warning { drift > threshold * 0.8 } else { drift > threshold };
+            if warn == clock.drift_warning {
+                return;
+            }
+            clock.drift_warning = warn;
+            clock.clone()
+        };
+
+        if !clock.drift_warning {
+            println!("✅ Relógio do PLC {} normalizado (desvio {:.0}ms)", ip, clock.drift_ms);
+            return;
+        }
+
+        println!("⏱️ RELÓGIO DO PLC {} DESVIADO: {:.0}ms (jitter {:.0}ms, limite {}ms)",
+            ip, clock.drift_ms, clock.jitter_ms, self.clock_drift_warn_ms);
+        self.log_to_db("warning", "plc",
+            &format!("Relógio do PLC {} desviado {:.0}ms", ip, clock.drift_ms),
+            &format!("ID: {} | Jitter: {:.0}ms | Limite: {}ms | Amostras: {}",
+                conn_id, clock.jitter_ms, self.clock_drift_warn_ms, clock.samples)
+        ).await;
+        self.emit_event(TcpEvent::PlcClockDrift {
+            ip: ip.to_string(),
+            id: conn_id,
+            drift_ms: clock.drift_ms.round() as i64,
+            jitter_ms: clock.jitter_ms.round() as i64,
+            threshold_ms: self.clock_drift_warn_ms,
+        });
+    }
+
+    /// Marca a sessão como não saudável (erro de I/O ou timeouts)
+    pub(crate) async fn mark_session_error(&self, ip: &str, error: &str) {
+        let mut health = self.connection_health.write().await;
+        if let Some(h) = health.get_mut(ip) {
+            h.is_alive = false;
+            h.last_error = Some(error.to_string());
+        }
+    }
+
+    /// Conta um pacote que não foi possível decodificar; devolve o total da conexão
+    pub(crate) async fn record_parse_error(&self, ip: &str, error: &str) -> u64 {
+        let mut health = self.connection_health.write().await;
+        match health.get_mut(ip) {
+            Some(h) => {
+                h.parse_errors += 1;
+                h.last_error = Some(error.to_string());
+                h.parse_errors
+            }
+            None => 1,
+        }
+    }
+
+    /// Parseia um pacote completo, envia-o pelo broadcast e atualiza cache e saúde
+    pub(crate) async fn publish_packet(
+        &self,
+        ip: &str,
+        conn_id: u64,
+        plc_name: &str,
+        packet_data: &[u8],
+        layout: &PacketLayout,
+        packet_count: u64,
+    ) -> Result<(), String> {
+        // Atualizar contador de pacotes no health
+        {
+            let mut health = self.connection_health.write().await;
+            if let Some(h) = health.get_mut(ip) {
+                h.packet_count = packet_count;
+            }
+        }
+
+        // Gravar o frame bruto antes do parse (captura também frames inválidos)
+        if let Some(capture) = &self.capture {
+            capture.record(ip, packet_data);
+        }
+
+        // Parsear dados binários PLC
+        let (plc_data, plc_variables) = parse_plc_packet(packet_data, layout, ip, conn_id, plc_name)?;
+
+        // Desvio do relógio do PLC (layouts com carimbo temporal)
+        if let Some(plc_time) = layout.plc_timestamp(packet_data) {
+            let offset = chrono::Utc::now() - plc_time;
+            self.update_clock(ip, conn_id, offset.num_milliseconds()).await;
+        }
+
+        // Cachear no latest_data para API queries e snapshot SSE (antes do envio:
+        // um snapshot que já vê o id do frame no buffer de replay tem de o ver aqui)
+        let packet = PlcDataPacket {
+            ip: ip.to_string(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            size: packet_data.len(),
+            variables: plc_variables,
+            frame: Some(plc_data.clone()),
+            stale_sent: false,
+        };
+        self.latest_data.write().await.insert(ip.to_string(), packet);
+
+        // Enviar via broadcast channel (main.rs reencaminha para SSE)
+        let _ = self.tx.send(plc_data);
+
+        Ok(())
+    }
+
+    /// Reinjeta um frame gravado: parse com o layout do perfil do PLC ou detetado
+    /// pelo tamanho + broadcast (não mexe na saúde nem no cache latest_data; conn_id 0 = reprodução)
+    pub(crate) async fn replay_frame(&self, ip: &str, packet_data: &[u8]) -> Result<(), String> {
+        let default_layout = self.get_packet_layout().await;
+        let layout = match self.profile_layout(ip).await {
+            Some(layout) => layout,
+            None => detect_framed_layout(&self.get_layouts().await, packet_data, &default_layout)
+                .map(|(layout, _)| layout)
+                .unwrap_or(default_layout),
+        };
+        let plc_name = self.get_plc_name(ip).await;
+        let (plc_data, _) = parse_plc_packet(packet_data, &layout, ip, 0, &plc_name)?;
+        let _ = self.tx.send(plc_data);
+        Ok(())
+    }
+
+    /// Termina uma sessão à força: aborta a tarefa e limpa os registos
+    pub(crate) async fn end_session(&self, ip: &str) -> bool {
+        if let Some(handle) = self.connection_handles.write().await.remove(ip) {
+            handle.abort();
+            let health = self.connection_health.write().await.remove(ip);
+            self.end_session_record(health, "forced").await;
+            self.connected_clients.write().await.retain(|x| x != ip);
+            self.active_connections.fetch_sub(1, Ordering::SeqCst);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Fecha a linha da sessão em plc_sessions com o motivo e os contadores finais
+    async fn end_session_record(&self, health: Option<ConnectionHealth>, reason: &str) {
+        let Some(h) = health else { return };
+        let Some(session_id) = h.session_id else { return };
+        if let Some(db) = self.database.as_ref().and_then(|w| w.upgrade()) {
+            if let Err(e) = db.close_plc_session(session_id, reason, h.total_bytes, h.packet_count).await {
+                eprintln!("⚠️ Erro ao fechar sessão de {}: {:?}", h.ip, e);
+            }
+        }
+    }
+
+    /// Verifica se um IP/sessão está bloqueado
+    pub(crate) async fn is_blacklisted(&self, ip: &str) -> bool {
+        self.blacklisted_ips.read().await.contains(ip)
+    }
+
+    /// Regista o handle da tarefa de uma sessão (para watchdog/desconexão forçada)
+    pub(crate) async fn register_session_handle(&self, ip: &str, handle: tokio::task::AbortHandle) {
+        self.connection_handles.write().await.insert(ip.to_string(), handle);
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.is_running.load(Ordering::SeqCst)
+    }
+
     // ====================================================================
     // WATCHDOG - Monitora conexões mortas e limpa recursos
     // ====================================================================
@@ -459,17 +1253,22 @@ impl TcpServer {
                     .collect::<Vec<_>>()
             };
 
+            // ── PLCs esperados que não ligaram (a cada ~10s) ──
+            if iteration.is_multiple_of(5) {
+                self.check_expected_plcs().await;
+            }
+
             // ── Emitir warnings para conexões lentas (a cada ~30s) ──
-            if iteration % 15 == 0 {
+            if iteration.is_multiple_of(15) {
                 let health = self.connection_health.read().await;
                 for (ip, h) in health.iter() {
                     if h.removal_in_progress { continue; }
                     let secs = h.last_data_received.elapsed().as_secs();
                     if secs > INACTIVITY_TIMEOUT_SECS / 2 && secs <= INACTIVITY_TIMEOUT_SECS {
                         println!("⚠️ WATCHDOG: {} LENTA! Sem dados há {}s", ip, secs);
-                        self.emit_event("tcp-connection-slow", serde_json::json!({
-                            "ip": ip, "id": h.conn_id, "seconds_since_data": secs
-                        }));
+                        self.emit_event(TcpEvent::ConnectionSlow {
+                            ip: ip.clone(), id: h.conn_id, seconds_since_data: secs,
+                        });
                     }
                 }
             }
@@ -482,13 +1281,13 @@ impl TcpServer {
                         if !h.removal_in_progress {
                             h.removal_in_progress = true;
 
-                            self.emit_event("tcp-connection-dead", serde_json::json!({
-                                "ip": ip,
-                                "id": h.conn_id,
-                                "seconds_since_data": h.last_data_received.elapsed().as_secs(),
-                                "total_bytes": h.total_bytes,
-                                "packet_count": h.packet_count
-                            }));
+                            self.emit_event(TcpEvent::ConnectionDead {
+                                ip: ip.clone(),
+                                id: h.conn_id,
+                                seconds_since_data: h.last_data_received.elapsed().as_secs(),
+                                total_bytes: h.total_bytes,
+                                packet_count: h.packet_count,
+                            });
 
                             true
                         } else { false }
@@ -511,7 +1310,8 @@ impl TcpServer {
                         }
                     }
 
-                    self.connection_health.write().await.remove(&ip);
+                    let health = self.connection_health.write().await.remove(&ip);
+                    self.end_session_record(health, "watchdog").await;
                     self.connected_clients.write().await.retain(|x| x != &ip);
                     self.active_connections.fetch_sub(1, Ordering::SeqCst);
 
@@ -521,14 +1321,15 @@ impl TcpServer {
                     ).await;
 
                     // Emitir desconexão
-                    self.emit_event("plc-disconnected", serde_json::json!({
-                        "ip": ip, "reason": "watchdog_timeout"
-                    }));
+                    self.emit_event(TcpEvent::PlcDisconnected {
+                        id: None, ip: ip.clone(), reason: Some("watchdog_timeout".to_string()),
+                    });
+                    self.emit_stats().await;
                 }
             }
 
             // ── Estatísticas periódicas (~1 minuto) ──
-            if iteration % 30 == 0 {
+            if iteration.is_multiple_of(30) {
                 let active = self.active_connections.load(Ordering::SeqCst);
                 let cache_size = self.latest_data.read().await.len();
                 let health_count = self.connection_health.read().await.len();
@@ -536,10 +1337,40 @@ impl TcpServer {
 
                 println!("📊 WATCHDOG: Ativos={} Cache={} Health={} PLCs_Únicos={}",
                     active, cache_size, health_count, unique_count);
+
+                // Atualizar sessões abertas no histórico (fim aproximado se o backend cair)
+                if let Some(db) = self.database.as_ref().and_then(|w| w.upgrade()) {
+                    let open: Vec<(i64, u64, u64)> = self.connection_health.read().await.values()
+                        .filter_map(|h| h.session_id.map(|id| (id, h.total_bytes, h.packet_count)))
+                        .collect();
+                    for (id, bytes, packets) in open {
+                        let _ = db.touch_plc_session(id, bytes, packets).await;
+                    }
+                }
+            }
+
+            // ── Dados parados: reenviar o último frame com qualidade Stale (uma vez) ──
+            let stale_frames = {
+                let now_ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+                let mut data = self.latest_data.write().await;
+                data.values_mut()
+                    .filter(|p| !p.stale_sent && now_ts.saturating_sub(p.timestamp) >= STALE_DATA_SECS)
+                    .filter_map(|p| {
+                        p.stale_sent = true;
+                        let frame = p.frame.as_mut()?;
+                        frame.mark_stale();
+                        Some(frame.clone())
+                    })
+                    .collect::<Vec<_>>()
+            };
+            for plc_data in stale_frames {
+                println!("🕸️ WATCHDOG: Dados de {} parados há {}s - variáveis marcadas Stale",
+                    plc_data.source_ip, STALE_DATA_SECS);
+                let _ = self.tx.send(plc_data);
             }
 
             // ── Limpar cache latest_data > 5min (~150 iterações) ──
-            if iteration % 150 == 0 {
+            if iteration.is_multiple_of(150) {
                 let now_ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
                 let mut data = self.latest_data.write().await;
                 let before = data.len();
@@ -551,7 +1382,7 @@ impl TcpServer {
             }
 
             // ── Resetar bytes_received a cada 24h (~43200 iterações) ──
-            if iteration % 43200 == 0 {
+            if iteration.is_multiple_of(43200) {
                 self.bytes_received.write().await.clear();
                 println!("🗑️ WATCHDOG: Reset diário de contadores de bytes");
             }
@@ -563,6 +1394,7 @@ impl TcpServer {
     // ====================================================================
     // PARAR SERVIDOR
     // ====================================================================
+    #[allow(dead_code)]
     pub async fn stop(&self) -> Result<String, String> {
         if !self.is_running.load(Ordering::SeqCst) {
             return Err("Servidor não está rodando".to_string());
@@ -579,18 +1411,16 @@ impl TcpServer {
         }
 
         // Limpar estado
-        self.connection_health.write().await.clear();
+        let sessions: Vec<ConnectionHealth> = self.connection_health.write().await.drain().map(|(_, h)| h).collect();
+        for h in sessions {
+            self.end_session_record(Some(h), "server_stopped").await;
+        }
         self.active_connections.store(0, Ordering::SeqCst);
         self.connected_clients.write().await.clear();
 
         // Eventos
-        self.emit_event("tcp-server-stopped", serde_json::json!({}));
-        self.emit_event("tcp-stats", serde_json::json!({
-            "active_connections": 0,
-            "total_connections": self.unique_plcs.read().await.len(),
-            "server_status": "Parado",
-            "plc_status": "Desconectado"
-        }));
+        self.emit_event(TcpEvent::ServerStopped {});
+        self.emit_stats().await;
 
         self.log_to_db("info", "tcp", "Servidor TCP parado", "").await;
 
@@ -604,26 +1434,19 @@ impl TcpServer {
     pub async fn disconnect_client(&self, client_ip: &str) -> Result<String, String> {
         println!("🔌 DESCONECTANDO: {}", client_ip);
 
-        // Adicionar à blacklist para impedir reconexão
+        // Adicionar à blacklist para impedir reconexão (persistida para sobreviver a reinícios)
         self.blacklisted_ips.write().await.insert(client_ip.to_string());
+        if let Some(db) = self.database.as_ref().and_then(|w| w.upgrade()) {
+            if let Err(e) = db.block_plc(client_ip, "Desconectado pelo operador").await {
+                eprintln!("⚠️ Erro ao persistir bloqueio de {}: {:?}", client_ip, e);
+            }
+        }
 
-        if let Some(handle) = self.connection_handles.write().await.remove(client_ip) {
-            handle.abort();
-            self.connection_health.write().await.remove(client_ip);
-            self.connected_clients.write().await.retain(|ip| ip != client_ip);
-
-            let remaining = self.active_connections.fetch_sub(1, Ordering::SeqCst).saturating_sub(1);
-            let total_unique = self.unique_plcs.read().await.len() as u64;
-
-            self.emit_event("plc-force-disconnected", serde_json::json!({
-                "ip": client_ip, "blocked": true
-            }));
-            self.emit_event("tcp-stats", serde_json::json!({
-                "active_connections": remaining,
-                "total_connections": total_unique,
-                "server_status": "Rodando",
-                "plc_status": if remaining > 0 { "Conectado" } else { "Desconectado" }
-            }));
+        if self.end_session(client_ip).await {
+            self.emit_event(TcpEvent::PlcForceDisconnected {
+                ip: client_ip.to_string(), blocked: true,
+            });
+            self.emit_stats().await;
 
             self.log_to_db("warning", "plc",
                 &format!("PLC {} desconectado e bloqueado", client_ip), ""
@@ -640,6 +1463,11 @@ impl TcpServer {
     // ====================================================================
     pub async fn allow_reconnect(&self, client_ip: &str) -> Result<String, String> {
         if self.blacklisted_ips.write().await.remove(client_ip) {
+            if let Some(db) = self.database.as_ref().and_then(|w| w.upgrade()) {
+                if let Err(e) = db.unblock_plc(client_ip).await {
+                    eprintln!("⚠️ Erro ao remover bloqueio de {}: {:?}", client_ip, e);
+                }
+            }
             println!("✅ {} desbloqueado para reconexão", client_ip);
             self.log_to_db("info", "plc", &format!("PLC {} desbloqueado", client_ip), "").await;
             Ok(format!("PLC {} pode reconectar", client_ip))
@@ -678,6 +1506,7 @@ impl TcpServer {
         self.connected_clients.read().await.clone()
     }
 
+    #[allow(dead_code)]
     pub async fn get_all_known_plcs(&self) -> Vec<(String, String)> {
         let connected = self.connected_clients.read().await;
         let blacklisted = self.blacklisted_ips.read().await;
@@ -696,9 +1525,24 @@ impl TcpServer {
     }
 
     pub async fn get_plc_data(&self, ip: &str) -> Option<PlcDataPacket> {
-        self.latest_data.read().await.get(ip).cloned()
+        let mut packet = self.latest_data.read().await.get(ip).cloned()?;
+        let now_ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        if now_ts.saturating_sub(packet.timestamp) >= STALE_DATA_SECS {
+            for variable in &mut packet.variables {
+                variable.quality = Quality::Stale;
+            }
+        }
+        Some(packet)
     }
 
+    /// Último frame de cada PLC (snapshot dos clientes SSE; dados parados já vêm como Stale)
+    pub async fn get_latest_frames(&self) -> Vec<PlcData> {
+        self.latest_data.read().await.values()
+            .filter_map(|p| p.frame.clone())
+            .collect()
+    }
+
+    #[allow(dead_code)]
     pub async fn get_all_plc_data(&self) -> HashMap<String, PlcDataPacket> {
         self.latest_data.read().await.clone()
     }
@@ -709,6 +1553,7 @@ impl TcpServer {
             .collect()
     }
 
+    #[allow(dead_code)]
     pub async fn get_bytes_received(&self) -> HashMap<String, u64> {
         self.bytes_received.read().await.clone()
     }
@@ -716,6 +1561,7 @@ impl TcpServer {
     // ====================================================================
     // CONEXÃO ATIVA AO PLC (modo cliente com retry)
     // ====================================================================
+    #[allow(dead_code)]
     pub async fn connect_to_plc(
         &self,
         plc_ip: &str,
@@ -764,7 +1610,7 @@ impl TcpServer {
                     }
                     Ok(Err(e)) => {
                         retry_count += 1;
-                        if retry_count % 5 == 0 {
+                        if retry_count.is_multiple_of(5) {
                             eprintln!("❌ Falha ao conectar PLC {} (tentativa {}): {}",
                                 plc_address, retry_count, e);
                             server.log_to_db("error", "plc",
@@ -775,7 +1621,7 @@ impl TcpServer {
                     }
                     Err(_) => {
                         retry_count += 1;
-                        if retry_count % 5 == 0 {
+                        if retry_count.is_multiple_of(5) {
                             eprintln!("❌ Timeout ao conectar PLC {} (tentativa {})",
                                 plc_address, retry_count);
                         }
@@ -788,7 +1634,7 @@ impl TcpServer {
                     backoff = std::cmp::min(backoff * 2, Duration::from_secs(30));
                 }
 
-                if retry_count > 0 && retry_count % 10 == 0 {
+                if retry_count > 0 && retry_count.is_multiple_of(10) {
                     println!("💪 Tentativa #{} de reconexão com PLC - mantendo persistência",
                         retry_count);
                 }
@@ -824,6 +1670,25 @@ async fn handle_client_connection(
     let mut consecutive_timeouts = 0u32;
     let start_time = Instant::now();
 
+    // Enquadramento decidido no início da conexão (auto = pelos primeiros 4 bytes)
+    let mut stream_mode = match server.get_framing_mode().await {
+        FramingMode::Raw => StreamMode::Raw,
+        FramingMode::Framed => StreamMode::Framed(FrameDecoder::new()),
+        FramingMode::Auto => StreamMode::Detecting,
+    };
+
+    // Layout da conexão: perfil do PLC registado, único layout registado,
+    // ou deteção pelos primeiros dados (tamanho/assinatura)
+    let mut session_layout: Option<String> = None;
+    if let Some(layout) = server.profile_layout(&ip).await {
+        select_layout(server, &ip, conn_id, &layout, "profile").await;
+        session_layout = Some(layout.name.clone());
+    } else if server.get_layouts().await.len() <= 1 {
+        let layout = server.get_packet_layout().await;
+        select_layout(server, &ip, conn_id, &layout, "default").await;
+        session_layout = Some(layout.name.clone());
+    }
+
     println!("🔗 Conexão #{} ({}) estabelecida - modo SOMENTE RECEPÇÃO", conn_id, ip);
 
     loop {
@@ -841,12 +1706,13 @@ async fn handle_client_connection(
         }
 
         // ── Limpar fragmentos TCP antigos ──
-        if !accumulator.is_empty() && last_fragment_time.elapsed().as_secs() > FRAGMENT_WARN_SECS {
-            if last_fragment_time.elapsed().as_secs() > FRAGMENT_CLEAR_SECS {
-                println!("🗑️ #{}: Limpando fragmentos antigos ({} bytes)", conn_id, accumulator.len());
-                accumulator.clear();
-                last_fragment_time = Instant::now();
-            }
+        if !accumulator.is_empty()
+            && last_fragment_time.elapsed().as_secs() > FRAGMENT_WARN_SECS
+            && last_fragment_time.elapsed().as_secs() > FRAGMENT_CLEAR_SECS
+        {
+            println!("🗑️ #{}: Limpando fragmentos antigos ({} bytes)", conn_id, accumulator.len());
+            accumulator.clear();
+            last_fragment_time = Instant::now();
         }
 
         // ── Leitura com timeout ──
@@ -861,30 +1727,29 @@ async fn handle_client_connection(
                 total_bytes += n as u64;
                 bytes_since_stats += n as u64;
 
-                // Atualizar timestamp global
-                let now_ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-                server.last_data_time.store(now_ts, Ordering::SeqCst);
-
-                // Atualizar bytes recebidos
-                {
-                    let mut bytes = server.bytes_received.write().await;
-                    *bytes.entry(ip.clone()).or_insert(0) += n as u64;
-                }
-
-                // Atualizar saúde da conexão
-                {
-                    let mut health = server.connection_health.write().await;
-                    if let Some(h) = health.get_mut(&ip) {
-                        h.last_data_received = Instant::now();
-                        h.total_bytes = total_bytes;
-                        h.is_alive = true;
-                    }
-                }
+                server.record_received(&ip, n, total_bytes).await;
 
                 last_fragment_time = Instant::now();
 
+                // Layouts e nome atuais (podem ter sido alterados via API)
+                let default_layout = server.get_packet_layout().await;
+                let layouts = server.get_layouts().await;
+                let mut layout = match &session_layout {
+                    Some(name) => Some(server.layout_by_name(name).await.unwrap_or_else(|| default_layout.clone())),
+                    None => None,
+                };
+                let plc_name = server.get_plc_name(&ip).await;
+                let packet_size = match &layout {
+                    Some(layout) => layout.packet_size,
+                    None => layouts.iter().map(|l| l.packet_size).max().unwrap_or(default_layout.packet_size),
+                };
+                let frame_size = match stream_mode {
+                    StreamMode::Raw => packet_size,
+                    _ => packet_size + FRAME_OVERHEAD,
+                };
+
                 // Proteção contra overflow do accumulator
-                if accumulator.len() + n > MAX_ACCUMULATOR_SIZE {
+                if accumulator.len() + n > frame_size * MAX_ACCUMULATOR_PACKETS {
                     eprintln!("⚠️ #{}: Accumulator overflow ({} + {} bytes), limpando",
                         conn_id, accumulator.len(), n);
                     accumulator.clear();
@@ -893,47 +1758,95 @@ async fn handle_client_connection(
 
                 accumulator.extend_from_slice(&buffer[..n]);
 
-                // ── Processar pacotes completos (1288 bytes cada) ──
-                while accumulator.len() >= EXPECTED_PACKET_SIZE {
-                    let packet_data: Vec<u8> = accumulator.drain(..EXPECTED_PACKET_SIZE).collect();
-                    packet_count += 1;
-                    last_valid_packet = Instant::now();
+                // ── Modo auto: frames PLCF ou pacotes brutos ──
+                if matches!(stream_mode, StreamMode::Detecting) && accumulator.len() >= FRAME_MAGIC.len() {
+                    stream_mode = if accumulator.starts_with(&FRAME_MAGIC) {
+                        println!("🧩 #{}: protocolo enquadrado detetado (PLCF + seq + CRC32)", conn_id);
+                        StreamMode::Framed(FrameDecoder::new())
+                    } else {
+                        StreamMode::Raw
+                    };
+                }
 
-                    // Atualizar contador de pacotes no health
-                    {
-                        let mut health = server.connection_health.write().await;
-                        if let Some(h) = health.get_mut(&ip) {
-                            h.packet_count = packet_count;
+                // ── Extrair pacotes completos (com o layout de cada um) ──
+                let packets: Vec<(Vec<u8>, Arc<PacketLayout>)> = match &mut stream_mode {
+                    StreamMode::Detecting => Vec::new(),
+                    StreamMode::Raw => {
+                        // Sem layout escolhido: esperar por dados múltiplos de um tamanho registado
+                        if layout.is_none() {
+                            if let Some((detected, how)) = detect_raw_layout(&layouts, &accumulator, &default_layout) {
+                                select_layout(server, &ip, conn_id, &detected, how).await;
+                                session_layout = Some(detected.name.clone());
+                                layout = Some(detected);
+                            }
                         }
-                    }
 
-                    // Parsear dados binários PLC
-                    match parse_plc_packet(&packet_data) {
-                        Ok((plc_data, plc_variables)) => {
-                            // Enviar via broadcast channel (lib.rs subscreve e emite "plc-data")
-                            let _ = server.tx.send(plc_data);
-
-                            // Cachear no latest_data para API queries
-                            let packet = PlcDataPacket {
-                                ip: ip.clone(),
-                                timestamp: now_ts,
-                                size: packet_data.len(),
-                                variables: plc_variables,
-                            };
-                            server.latest_data.write().await.insert(ip.clone(), packet);
+                        // packet_size bytes cada, sem ressincronização
+                        let mut packets = Vec::new();
+                        if let Some(layout) = &layout {
+                            while accumulator.len() >= layout.packet_size {
+                                packets.push((accumulator.drain(..layout.packet_size).collect(), layout.clone()));
+                            }
                         }
-                        Err(e) => {
-                            if packet_count <= 3 {
-                                eprintln!("⚠️ #{} erro parsing pacote #{}: {}", conn_id, packet_count, e);
+                        packets
+                    }
+                    StreamMode::Framed(decoder) => {
+                        let resyncs_before = decoder.stats().resyncs;
+                        let mut packets = Vec::new();
+                        loop {
+                            let sizes: Vec<usize> = match &layout {
+                                Some(layout) => vec![layout.packet_size],
+                                None => layouts.iter().map(|l| l.packet_size).collect(),
+                            };
+                            let Some(payload) = decoder.next_frame(&mut accumulator, &sizes) else { break };
+
+                            if layout.is_none() {
+                                match detect_framed_layout(&layouts, &payload, &default_layout) {
+                                    Some((detected, how)) => {
+                                        select_layout(server, &ip, conn_id, &detected, how).await;
+                                        session_layout = Some(detected.name.clone());
+                                        layout = Some(detected);
+                                    }
+                                    None => {
+                                        eprintln!("⚠️ #{}: frame de {} bytes sem layout correspondente (assinatura)",
+                                            conn_id, payload.len());
+                                        continue;
+                                    }
+                                }
+                            }
+                            if let Some(layout) = &layout {
+                                packets.push((payload, layout.clone()));
                             }
                         }
+
+                        let stats = decoder.stats().clone();
+                        if stats.resyncs > resyncs_before && (stats.resyncs <= 10 || stats.resyncs.is_multiple_of(100)) {
+                            println!("🔧 #{}: ressincronização #{} ({} bytes descartados, CRC inválido: {}, frames perdidos: {})",
+                                conn_id, stats.resyncs, stats.discarded_bytes, stats.crc_errors, stats.lost_frames);
+                        }
+                        server.update_frame_stats(&ip, stats).await;
+                        packets
+                    }
+                };
+
+                for (packet_data, layout) in packets {
+                    packet_count += 1;
+                    last_valid_packet = Instant::now();
+
+                    if let Err(e) = server.publish_packet(
+                        &ip, conn_id, &plc_name, &packet_data, &layout, packet_count
+                    ).await {
+                        let errors = server.record_parse_error(&ip, &e).await;
+                        if errors <= 10 || errors.is_multiple_of(100) {
+                            eprintln!("⚠️ #{} erro parsing pacote #{} ({} erros): {}", conn_id, packet_count, errors, e);
+                        }
                     }
                 }
 
                 // ── Log quando está acumulando dados ──
                 if !accumulator.is_empty() && packet_count == 0 && total_bytes == n as u64 {
                     println!("📦 #{}: Recebido {} bytes, esperando {} (acumulando...)",
-                        conn_id, accumulator.len(), EXPECTED_PACKET_SIZE);
+                        conn_id, accumulator.len(), frame_size);
                 }
 
                 // ── Estatísticas periódicas (a cada 1s) ──
@@ -946,28 +1859,28 @@ async fn handle_client_connection(
                     } else {
                         0.0
                     };
-                    let avg_packet_size = if packet_count > 0 { total_bytes / packet_count } else { 0 };
-
-                    server.emit_event("plc-data-stats", serde_json::json!({
-                        "ip": ip,
-                        "id": conn_id,
-                        "bytesPerSecond": bytes_per_second,
-                        "packets": packet_count,
-                        "totalBytes": total_bytes,
-                        "transferRate": format!("{:.2} KB/s", bytes_per_second as f64 / 1024.0),
-                        "packetsPerSecond": packets_per_second as u64,
-                        "avgPacketSize": avg_packet_size,
-                        "connectionUptime": start_time.elapsed().as_secs()
-                    }));
+                    let avg_packet_size = total_bytes.checked_div(packet_count).unwrap_or(0);
+
+                    server.emit_event(TcpEvent::DataStats {
+                        ip: ip.clone(),
+                        id: conn_id,
+                        bytes_per_second,
+                        packets: packet_count,
+                        total_bytes,
+                        transfer_rate: format!("{:.2} KB/s", bytes_per_second as f64 / 1024.0),
+                        packets_per_second: packets_per_second as u64,
+                        avg_packet_size,
+                        connection_uptime: start_time.elapsed().as_secs(),
+                    });
 
                     bytes_since_stats = 0;
                     last_stats_time = Instant::now();
                 }
 
                 // ── Log periódico de progresso (a cada 500 pacotes) ──
-                if packet_count > 0 && packet_count % 500 == 0 {
+                if packet_count > 0 && packet_count.is_multiple_of(500) {
                     let elapsed = start_time.elapsed().as_secs();
-                    let rate = if elapsed > 0 { total_bytes / elapsed } else { 0 };
+                    let rate = total_bytes.checked_div(elapsed).unwrap_or(0);
                     println!("📊 #{}: {} pacotes, {} bytes, {}s ativo, {} B/s",
                         conn_id, packet_count, total_bytes, elapsed, rate);
                 }
@@ -977,13 +1890,7 @@ async fn handle_client_connection(
             Ok(Err(e)) => {
                 // Erro de I/O
                 let err_msg = e.to_string();
-                {
-                    let mut health = server.connection_health.write().await;
-                    if let Some(h) = health.get_mut(&ip) {
-                        h.is_alive = false;
-                        h.last_error = Some(err_msg.clone());
-                    }
-                }
+                server.mark_session_error(&ip, &err_msg).await;
                 return ConnectionResult::Error(err_msg);
             }
             Err(_) => {
@@ -992,13 +1899,7 @@ async fn handle_client_connection(
                 if consecutive_timeouts >= 3 {
                     let reason = format!("{} timeouts consecutivos de {}s",
                         consecutive_timeouts, READ_TIMEOUT_SECS);
-                    {
-                        let mut health = server.connection_health.write().await;
-                        if let Some(h) = health.get_mut(&ip) {
-                            h.is_alive = false;
-                            h.last_error = Some(reason.clone());
-                        }
-                    }
+                    server.mark_session_error(&ip, &reason).await;
                     return ConnectionResult::Timeout(reason);
                 }
                 // 🚫 NÃO ENVIAR NADA - TSEND_C não espera resposta
@@ -1007,82 +1908,143 @@ async fn handle_client_connection(
     }
 }
 
+// ============================================================================
+// ESCOLHA DO LAYOUT POR CONEXÃO
+// ============================================================================
+
+async fn select_layout(server: &TcpServer, ip: &str, conn_id: u64, layout: &PacketLayout, how: &str) {
+    println!("📐 #{}: layout {} ({} bytes) - escolhido por {}", conn_id, layout.name, layout.packet_size, how);
+    server.set_session_layout(ip, &layout.name, how).await;
+}
+
+/// Entre layouts de tamanho compatível: primeiro o que tem a assinatura do payload,
+/// depois um sem assinatura (o layout por omissão, ou o maior, se houver vários)
+fn choose_layout(
+    candidates: &[&Arc<PacketLayout>],
+    data: &[u8],
+    default: &Arc<PacketLayout>,
+) -> Option<(Arc<PacketLayout>, &'static str)> {
+    if let Some(layout) = candidates.iter().find(|l| l.matches_signature(data)) {
+        return Some(((*layout).clone(), "signature"));
+    }
+    let plain: Vec<&Arc<PacketLayout>> = candidates.iter().copied().filter(|l| l.signature.is_none()).collect();
+    plain.iter()
+        .find(|l| l.name == default.name)
+        .or_else(|| plain.iter().max_by_key(|l| l.packet_size))
+        .map(|layout| ((*layout).clone(), "size"))
+}
+
+/// Frame PLCF: o tamanho do payload é exato
+fn detect_framed_layout(
+    layouts: &[Arc<PacketLayout>],
+    payload: &[u8],
+    default: &Arc<PacketLayout>,
+) -> Option<(Arc<PacketLayout>, &'static str)> {
+    let candidates: Vec<&Arc<PacketLayout>> = layouts.iter().filter(|l| l.packet_size == payload.len()).collect();
+    choose_layout(&candidates, payload, default)
+}
+
+/// Stream bruto: os dados acumulados têm de ser múltiplos de um tamanho registado
+/// (TSEND_C envia um pacote por write). Sem correspondência após 2 pacotes do
+/// maior layout, usa o layout por omissão.
+fn detect_raw_layout(
+    layouts: &[Arc<PacketLayout>],
+    data: &[u8],
+    default: &Arc<PacketLayout>,
+) -> Option<(Arc<PacketLayout>, &'static str)> {
+    let candidates: Vec<&Arc<PacketLayout>> = layouts.iter()
+        .filter(|l| data.len() >= l.packet_size && data.len().is_multiple_of(l.packet_size))
+        .collect();
+    if let Some(found) = choose_layout(&candidates, data, default) {
+        return Some(found);
+    }
+
+    let max_size = layouts.iter().map(|l| l.packet_size).max().unwrap_or(default.packet_size);
+    if data.len() >= max_size * 2 {
+        Some((default.clone(), "default"))
+    } else {
+        None
+    }
+}
+
 // ============================================================================
 // PARSER PLC S7-1500 via TSEND_C
 // ============================================================================
-// Estrutura: UDT_TCP_Data
+// Estrutura definida pelo PacketLayout da conexão. Layout padrão: UDT_TCP_Data
 //   Word[0..64]  = 65 Words  (u16 big-endian) = 130 bytes  (offset 0)
 //   Int[0..64]   = 65 Ints   (i16 big-endian) = 130 bytes  (offset 130)
 //   Real[0..256] = 257 Reals (f32 big-endian) = 1028 bytes (offset 260)
 //   TOTAL = 1288 bytes
 // ============================================================================
 
-fn parse_plc_packet(data: &[u8]) -> Result<(PlcData, Vec<PlcVariable>), String> {
-    if data.len() < EXPECTED_PACKET_SIZE {
+fn parse_plc_packet(
+    data: &[u8],
+    layout: &PacketLayout,
+    source_ip: &str,
+    conn_id: u64,
+    plc_name: &str,
+) -> Result<(PlcData, Vec<PlcVariable>), String> {
+    if data.len() < layout.packet_size {
         return Err(format!(
             "Pacote incompleto: {} bytes (esperado {})",
-            data.len(), EXPECTED_PACKET_SIZE
+            data.len(), layout.packet_size
         ));
     }
 
-    let mut variables = HashMap::with_capacity(WORD_COUNT + INT_COUNT + REAL_COUNT + 4);
-    let mut plc_variables = Vec::with_capacity(WORD_COUNT + INT_COUNT + REAL_COUNT);
-
-    // ── Parse Word[0..64] - u16 big-endian (130 bytes) ──
-    for i in 0..WORD_COUNT {
-        let offset = WORD_OFFSET + i * 2;
-        let value = u16::from_be_bytes([data[offset], data[offset + 1]]);
-        let name = format!("Word[{}]", i);
-        variables.insert(name.clone(), value as f64);
-        plc_variables.push(PlcVariable {
-            name,
-            value: value.to_string(),
-            data_type: "Word".to_string(),
-            unit: None,
-        });
-    }
+    let var_count = layout.variable_count();
+    let mut variables = HashMap::with_capacity(var_count + layout.segments.len() + 1);
+    let mut texts = HashMap::new();
+    let mut quality = HashMap::new();
+    let mut plc_variables = Vec::with_capacity(var_count);
 
-    // ── Parse Int[0..64] - i16 big-endian (130 bytes) ──
-    for i in 0..INT_COUNT {
-        let offset = INT_OFFSET + i * 2;
-        let value = i16::from_be_bytes([data[offset], data[offset + 1]]);
-        let name = format!("Int[{}]", i);
-        variables.insert(name.clone(), value as f64);
-        plc_variables.push(PlcVariable {
-            name,
-            value: value.to_string(),
-            data_type: "Int".to_string(),
-            unit: None,
-        });
-    }
+    for segment in &layout.segments {
+        let has_text = matches!(segment.data_type, SegmentType::String | SegmentType::Time | SegmentType::Dtl);
 
-    // ── Parse Real[0..256] - f32 big-endian (1028 bytes) ──
-    for i in 0..REAL_COUNT {
-        let offset = REAL_OFFSET + i * 4;
-        let value = f32::from_be_bytes([
-            data[offset], data[offset + 1], data[offset + 2], data[offset + 3]
-        ]);
-        let name = format!("Real[{}]", i);
-        // Filtrar NaN e Infinito para segurança
-        let safe_value = if value.is_finite() { value as f64 } else { 0.0 };
-        variables.insert(name.clone(), safe_value);
-        plc_variables.push(PlcVariable {
-            name,
-            value: if value.is_finite() { format!("{:.4}", value) } else { "0.0".to_string() },
-            data_type: "Real".to_string(),
-            unit: None,
-        });
+        for i in 0..segment.count {
+            let name = format!("{}[{}]", segment.name, i);
+            let value = segment.decode(data, i, layout.clock_timezone);
+
+            if let Some(numeric) = value.numeric {
+                variables.insert(name.clone(), numeric);
+            }
+            if has_text {
+                texts.insert(name.clone(), value.text.clone());
+            }
+            if !value.quality.is_good() {
+                quality.insert(name.clone(), value.quality);
+            }
+            plc_variables.push(PlcVariable {
+                name,
+                value: value.text,
+                data_type: segment.data_type.as_str().to_string(),
+                unit: None,
+                quality: value.quality,
+            });
+        }
     }
 
     // ── Metadata ──
     variables.insert("_total_bytes".to_string(), data.len() as f64);
-    variables.insert("_word_count".to_string(), WORD_COUNT as f64);
-    variables.insert("_int_count".to_string(), INT_COUNT as f64);
-    variables.insert("_real_count".to_string(), REAL_COUNT as f64);
+    for segment in &layout.segments {
+        variables.insert(format!("_{}_count", segment.name.to_lowercase()), segment.count as f64);
+    }
+
+    // ── Hora da amostra: carimbo do PLC quando o layout o traz ──
+    let now = chrono::Utc::now();
+    let (timestamp, received_at) = match layout.plc_timestamp(data) {
+        Some(plc_time) => (plc_time.to_rfc3339(), Some(now.to_rfc3339())),
+        None => (now.to_rfc3339(), None),
+    };
 
     let plc_data = PlcData {
-        timestamp: chrono::Utc::now().to_rfc3339(),
+        timestamp,
+        received_at,
+        source_ip: source_ip.to_string(),
+        conn_id,
+        plc_name: plc_name.to_string(),
         variables,
+        texts,
+        quality,
     };
 
     Ok((plc_data, plc_variables))
@@ -1,11 +1,12 @@
-// web_server.rs - Servidor HTTP REST + SSE + Video Streaming + Static Files
+// web_server.rs - Servidor HTTP REST + SSE + WebSocket + Video Streaming + Static Files
 // Substitui completamente o Tauri como camada de comunicação com o frontend
 
 use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
 use std::convert::Infallible;
 use axum::{
     Router, Json,
-    extract::State,
+    extract::{DefaultBodyLimit, Multipart, Query, State},
     routing::{get, post},
     response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response},
     http::{StatusCode, HeaderMap, header},
@@ -13,13 +14,27 @@ use axum::{
 };
 use tower_http::cors::CorsLayer;
 use tower_http::services::{ServeDir, ServeFile};
-use tokio::sync::{Mutex, broadcast};
+use tokio::sync::{Mutex, RwLock, broadcast};
+use tokio::sync::broadcast::error::RecvError;
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt;
 use futures::stream::Stream;
 
-use crate::database::Database;
-use crate::tcp_server::{TcpServer, PlcData, ConnectionStats};
+use crate::database::{Database, Panel, PlcDevice};
+use crate::display_engine::{DisplayEngine, PanelState, DEFAULT_PANEL_ID, DISPLAY_PLC_KEY, IDLE_TEXT_KEY};
+use crate::capture::{self, FrameCapture};
+use crate::modbus_client::{self, ModbusDevice};
+use crate::polling_client::PollingManager;
+use crate::s7_client::{self, S7Device};
+use crate::packet_layout::PacketLayout;
+use crate::ip_filter::IpRule;
+use crate::framing::FramingMode;
+use crate::media_library::{MediaLibrary, MediaPathError};
+use crate::playlist::Playlist;
+use crate::proof_of_play::{self, AdGapReportRow, PlayReportRow, PLAY_ABORTED, PLAY_FINISHED, REASON_CONTROL_BIT};
+use crate::plc_stream::{DeltaEncoder, PlcFrame, ReplayBuffer, SseMode, DEFAULT_KEYFRAME_SECS, MAX_KEYFRAME_SECS};
+use crate::ws_server;
+use crate::tcp_server::{TcpServer, PlcData, ConnectionStats, TcpEvent, ALLOWLIST_ENABLED_KEY, FRAMING_MODE_KEY};
 
 // ============================================================================
 // APP STATE
@@ -29,7 +44,13 @@ use crate::tcp_server::{TcpServer, PlcData, ConnectionStats};
 pub struct AppState {
     pub database: Arc<Database>,
     pub tcp_server: Arc<Mutex<Option<Arc<TcpServer>>>>,
-    pub plc_broadcast: broadcast::Sender<PlcData>,
+    pub pollers: Arc<PollingManager>,
+    pub capture: Arc<FrameCapture>,
+    pub plc_broadcast: broadcast::Sender<PlcFrame>,
+    pub replay: Arc<RwLock<ReplayBuffer>>,
+    pub tcp_events: broadcast::Sender<TcpEvent>,
+    pub display: Arc<DisplayEngine>,
+    pub media: Arc<MediaLibrary>,
 }
 
 // ============================================================================
@@ -53,7 +74,13 @@ pub async fn start(state: Arc<AppState>, port: u16) {
     let api_routes = Router::new()
         .route("/api/invoke", post(handle_invoke))
         .route("/api/events/plc-data", get(handle_plc_sse))
+        .route("/api/events/tcp", get(handle_tcp_events_sse))
+        .route("/api/events/panel-state", get(handle_panel_state_sse))
+        .route("/api/ws", get(ws_server::handle_ws))
         .route("/api/video/*path", get(handle_video))
+        .route("/api/videos/upload", post(handle_video_upload).layer(DefaultBodyLimit::disable()))
+        .route("/api/reports/plays.csv", get(handle_plays_csv))
+        .route("/api/reports/ad-gaps.csv", get(handle_ad_gaps_csv))
         .with_state(state);
 
     // Fallback: serve frontend static files (SPA)
@@ -95,6 +122,14 @@ pub async fn start(state: Arc<AppState>, port: u16) {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// Comandos que alteram as regras do display_engine
+const DISPLAY_RULE_COMMANDS: &[&str] = &[
+    "add_video", "update_video", "delete_video", "reorder_video", "clear_all_videos",
+    "add_bit_config", "update_bit_config", "delete_bit_config",
+    "set_video_control_config", "set_panel_config", "update_text",
+    "save_panel", "delete_panel", "save_playlist", "delete_playlist",
+];
+
 // ============================================================================
 // GENERIC INVOKE HANDLER
 // Mapeia 1:1 com os comandos Tauri existentes
@@ -104,10 +139,18 @@ async fn handle_invoke(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<InvokePayload>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    let args = &payload.args;
+    invoke_command(&state, &payload.command, &payload.args).await.map(Json)
+}
+
+/// Executa um comando (POST /api/invoke e mensagens "invoke" do WebSocket)
+pub(crate) async fn invoke_command(
+    state: &Arc<AppState>,
+    command: &str,
+    args: &serde_json::Value,
+) -> Result<serde_json::Value, (StatusCode, String)> {
     let db = &state.database;
 
-    let result: Result<serde_json::Value, String> = match payload.command.as_str() {
+    let result: Result<serde_json::Value, String> = match command {
         // ── VÍDEOS ──
         "get_all_videos" => {
             db.get_all_videos().await
@@ -122,7 +165,9 @@ async fn handle_invoke(
         }
         "add_video" => {
             let name = args["name"].as_str().unwrap_or("");
-            let file_path = args["filePath"].as_str().unwrap_or("");
+            let file_path = state.media.library_path(args["filePath"].as_str().unwrap_or(""))
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            let file_path = file_path.as_str();
             let duration = args["duration"].as_i64().unwrap_or(30) as i32;
             let enabled = args["enabled"].as_bool().unwrap_or(true);
             let priority = args["priority"].as_i64().unwrap_or(50) as i32;
@@ -134,7 +179,9 @@ async fn handle_invoke(
         "update_video" => {
             let id = args["id"].as_i64().unwrap_or(0);
             let name = args["name"].as_str().unwrap_or("");
-            let file_path = args["filePath"].as_str().unwrap_or("");
+            let file_path = state.media.library_path(args["filePath"].as_str().unwrap_or(""))
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            let file_path = file_path.as_str();
             let duration = args["duration"].as_i64().unwrap_or(30) as i32;
             let enabled = args["enabled"].as_bool().unwrap_or(true);
             let priority = args["priority"].as_i64().unwrap_or(50) as i32;
@@ -146,9 +193,14 @@ async fn handle_invoke(
         }
         "delete_video" => {
             let id = args["id"].as_i64().unwrap_or(0);
-            db.delete_video(id).await
-                .map(|_| serde_json::json!("OK"))
-                .map_err(|e| e.to_string())
+            let video = db.get_video(id).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            match db.delete_video(id).await {
+                Ok(()) => {
+                    remove_media_files(state, video.into_iter().map(|v| v.file_path).collect()).await;
+                    Ok(serde_json::json!("OK"))
+                }
+                Err(e) => Err(e.to_string()),
+            }
         }
         "get_enabled_videos" => {
             db.get_enabled_videos().await
@@ -163,9 +215,14 @@ async fn handle_invoke(
                 .map_err(|e| e.to_string())
         }
         "clear_all_videos" => {
-            db.clear_all_videos().await
-                .map(|_| serde_json::json!("OK"))
-                .map_err(|e| e.to_string())
+            let videos = db.get_all_videos().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            match db.clear_all_videos().await {
+                Ok(()) => {
+                    remove_media_files(state, videos.into_iter().map(|v| v.file_path).collect()).await;
+                    Ok(serde_json::json!("OK"))
+                }
+                Err(e) => Err(e.to_string()),
+            }
         }
 
         // ── BIT CONFIGS ──
@@ -237,15 +294,9 @@ async fn handle_invoke(
 
         // ── VIDEO CONTROL CONFIG ──
         "get_video_control_config" => {
-            let word_index = db.get_display_config("video_control_word_index").await
-                .unwrap_or(None)
-                .and_then(|v| v.parse::<i32>().ok())
-                .unwrap_or(5);
-            let bit_index = db.get_display_config("video_control_bit_index").await
-                .unwrap_or(None)
-                .and_then(|v| v.parse::<i32>().ok())
-                .unwrap_or(3);
-            Ok(serde_json::json!([word_index, bit_index]))
+            db.get_video_control_bit().await
+                .map(|(word_index, bit_index)| serde_json::json!([word_index, bit_index]))
+                .map_err(|e| e.to_string())
         }
         "set_video_control_config" => {
             let wi = args["wordIndex"].as_i64().unwrap_or(5) as i32;
@@ -257,6 +308,137 @@ async fn handle_invoke(
             Ok(serde_json::json!("OK"))
         }
 
+        // ── ESTADO DO PAINEL (display_engine) ──
+        "get_panel_state" => {
+            let panel = args["panel"].as_str().filter(|p| !p.is_empty()).unwrap_or(DEFAULT_PANEL_ID);
+            match state.display.current(panel).await {
+                Some(current) => Ok(serde_json::to_value(current).unwrap()),
+                None => return Err((StatusCode::NOT_FOUND, format!("Painel desconhecido: {}", panel))),
+            }
+        }
+
+        // ── PAINÉIS (montante, jusante, sala de controlo) ──
+        "get_panels" => {
+            db.get_panels().await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+        "save_panel" => {
+            let panel = serde_json::from_value::<Panel>(args["panel"].clone())
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Painel inválido: {}", e)))?;
+            panel.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            check_panel_items(db, &panel).await?;
+            db.save_panel(&panel).await
+                .map(|_| serde_json::json!(panel.id.trim()))
+                .map_err(|e| e.to_string())
+        }
+        "delete_panel" => {
+            let id = args["id"].as_str().unwrap_or("");
+            match db.delete_panel(id).await {
+                Ok(true) => Ok(serde_json::json!("OK")),
+                Ok(false) => return Err((StatusCode::NOT_FOUND, format!("Painel desconhecido: {}", id))),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        "get_panel_content" => {
+            let id = args["panel"].as_str().unwrap_or("");
+            get_panel_content(db, id).await
+        }
+        "get_panel_playlist" => {
+            let panel = args["panel"].as_str().filter(|p| !p.is_empty()).unwrap_or(DEFAULT_PANEL_ID);
+            match state.display.current_playlist(panel).await {
+                Some(current) => Ok(serde_json::to_value(current).unwrap()),
+                None => return Err((StatusCode::NOT_FOUND, format!("Painel desconhecido: {}", panel))),
+            }
+        }
+
+        // ── REGISTO DE EXIBIÇÃO (proof of play) ──
+        "report_play_start" => {
+            let panel = args["panel"].as_str().filter(|p| !p.is_empty()).unwrap_or(DEFAULT_PANEL_ID);
+            let video_id = args["videoId"].as_i64().unwrap_or(0);
+            let playlist_id = args["playlistId"].as_i64();
+            let source = args["source"].as_str().unwrap_or("playlist");
+            if source != "playlist" && source != "bit" {
+                return Err((StatusCode::BAD_REQUEST, format!("Origem inválida: {} (playlist ou bit)", source)));
+            }
+            if state.display.current(panel).await.is_none() {
+                return Err((StatusCode::NOT_FOUND, format!("Painel desconhecido: {}", panel)));
+            }
+            let videos = db.get_all_videos().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let Some(video) = videos.iter().find(|v| v.id == video_id) else {
+                return Err((StatusCode::BAD_REQUEST, format!("Vídeo desconhecido: {}", video_id)));
+            };
+            db.start_video_play(panel, video, playlist_id, source).await
+                .map(|id| serde_json::json!(id))
+                .map_err(|e| e.to_string())
+        }
+        "report_play_end" => {
+            let id = args["id"].as_i64().unwrap_or(0);
+            let status = args["status"].as_str().unwrap_or(PLAY_FINISHED);
+            if status != PLAY_FINISHED && status != PLAY_ABORTED {
+                return Err((StatusCode::BAD_REQUEST, format!("Estado inválido: {} ({} ou {})", status, PLAY_FINISHED, PLAY_ABORTED)));
+            }
+            let mut reason = args["reason"].as_str().unwrap_or("").trim().to_string();
+            // Interrompido porque o bit de controle foi a 0: conta como supressão, não falha
+            let panel = args["panel"].as_str().filter(|p| !p.is_empty()).unwrap_or(DEFAULT_PANEL_ID);
+            if status == PLAY_ABORTED && state.display.ads_suppressed(panel).await {
+                reason = REASON_CONTROL_BIT.to_string();
+            }
+            match db.end_video_play(id, status, &reason).await {
+                Ok(Some(seconds)) => Ok(serde_json::json!(seconds)),
+                Ok(None) => return Err((StatusCode::NOT_FOUND, format!("Exibição desconhecida ou já terminada: {}", id))),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        "get_play_report" => {
+            let (plays, gaps) = play_reports(db, args["from"].as_str(), args["to"].as_str(), args["panel"].as_str()).await?;
+            Ok(serde_json::json!({ "plays": plays, "gaps": gaps }))
+        }
+
+        // ── PLAYLISTS (campanhas com horário) ──
+        "get_playlists" => {
+            db.get_playlists().await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+        "save_playlist" => {
+            let playlist = serde_json::from_value::<Playlist>(args["playlist"].clone())
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Playlist inválida: {}", e)))?;
+            playlist.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            let videos = db.get_all_videos().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            if let Some(item) = playlist.items.iter().find(|i| !videos.iter().any(|v| v.id == i.video_id)) {
+                return Err((StatusCode::BAD_REQUEST, format!("Vídeo desconhecido: {}", item.video_id)));
+            }
+            db.save_playlist(&playlist).await
+                .map(|id| serde_json::json!(id))
+                .map_err(|e| e.to_string())
+        }
+        "delete_playlist" => {
+            let id = args["id"].as_i64().unwrap_or(0);
+            match db.delete_playlist(id).await {
+                Ok(true) => Ok(serde_json::json!("OK")),
+                Ok(false) => return Err((StatusCode::NOT_FOUND, format!("Playlist desconhecida: {}", id))),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        "get_panel_config" => {
+            let plc = db.get_display_config(DISPLAY_PLC_KEY).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let idle_text_key = db.get_display_config(IDLE_TEXT_KEY).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            Ok(serde_json::json!({
+                "plc": plc.unwrap_or_default(),
+                "idleTextKey": idle_text_key.unwrap_or_default(),
+            }))
+        }
+        "set_panel_config" => {
+            let plc = args["plc"].as_str().unwrap_or("").trim();
+            let idle_text_key = args["idleTextKey"].as_str().unwrap_or("").trim();
+            db.set_display_config(DISPLAY_PLC_KEY, plc, "text").await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            db.set_display_config(IDLE_TEXT_KEY, idle_text_key, "text").await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            Ok(serde_json::json!("OK"))
+        }
+
         // ── TEXTOS ──
         "get_all_texts" => {
             db.get_all_texts().await
@@ -380,6 +562,340 @@ async fn handle_invoke(
             }
         }
 
+        "get_blocked_plcs" => {
+            db.get_blocked_plcs().await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+
+        // ── ALLOWLIST (só IPs/redes registados podem ligar-se) ──
+        "get_allowlist" => {
+            let entries = db.get_allowlist().await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let enabled = db.get_display_config(ALLOWLIST_ENABLED_KEY).await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                .map(|v| v == "true")
+                .unwrap_or(false);
+            Ok(serde_json::json!({ "enabled": enabled, "entries": entries }))
+        }
+        "add_allowlist_entry" => {
+            let entry = args["entry"].as_str().unwrap_or("").trim();
+            let description = args["description"].as_str().unwrap_or("");
+            IpRule::parse(entry).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            let id = db.add_allowlist_entry(entry, description).await
+                .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+            reload_allowlist(state).await?;
+            let _ = db.add_system_log("info", "security", &format!("Allowlist: {} adicionado", entry), description).await;
+            Ok(serde_json::json!(id))
+        }
+        "delete_allowlist_entry" => {
+            let id = args["id"].as_i64().unwrap_or(0);
+            db.delete_allowlist_entry(id).await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            reload_allowlist(state).await?;
+            let _ = db.add_system_log("info", "security", &format!("Allowlist: entrada #{} removida", id), "").await;
+            Ok(serde_json::json!("OK"))
+        }
+        "set_allowlist_enabled" => {
+            let enabled = args["enabled"].as_bool().unwrap_or(false);
+            db.set_display_config(ALLOWLIST_ENABLED_KEY, if enabled { "true" } else { "false" }, "boolean").await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            reload_allowlist(state).await?;
+            let message = if enabled { "Modo allowlist ativado" } else { "Modo allowlist desativado" };
+            let _ = db.add_system_log("warning", "security", message, "").await;
+            Ok(serde_json::json!(enabled))
+        }
+
+        // ── INVENTÁRIO DE PLCs ──
+        "get_plc_devices" => {
+            db.get_plc_devices().await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+        "save_plc_device" => {
+            match serde_json::from_value::<PlcDevice>(args["device"].clone()) {
+                Ok(device) => {
+                    if let Err(e) = device.validate() {
+                        return Err((StatusCode::BAD_REQUEST, e));
+                    }
+                    if let Some(layout) = device.layout_name.as_deref().filter(|n| !n.trim().is_empty()) {
+                        let exists = db.packet_layout_exists(layout.trim()).await
+                            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                        if !exists {
+                            return Err((StatusCode::BAD_REQUEST, format!("Layout desconhecido: {}", layout)));
+                        }
+                    }
+                    let id = db.save_plc_device(&device).await
+                        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+                    reload_plc_devices(state).await?;
+                    Ok(serde_json::json!(id))
+                }
+                Err(e) => Err(format!("PLC inválido: {}", e)),
+            }
+        }
+        "delete_plc_device" => {
+            let id = args["id"].as_i64().unwrap_or(0);
+            db.delete_plc_device(id).await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            reload_plc_devices(state).await?;
+            Ok(serde_json::json!("OK"))
+        }
+        "get_plc_inventory" => {
+            let server_guard = state.tcp_server.lock().await;
+            if let Some(server) = server_guard.as_ref() {
+                Ok(serde_json::to_value(server.get_inventory().await).unwrap())
+            } else {
+                Ok(serde_json::json!([]))
+            }
+        }
+
+        // ── HISTÓRICO DE SESSÕES / DISPONIBILIDADE ──
+        "get_plc_sessions" => {
+            let ip = args["ip"].as_str().filter(|ip| !ip.is_empty());
+            let limit = args["limit"].as_i64().unwrap_or(100).clamp(1, 5000);
+            db.get_plc_sessions(ip, limit).await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+        "get_plc_availability" => {
+            let days = args["days"].as_u64().unwrap_or(7);
+            if !(1..=366).contains(&days) {
+                return Err((StatusCode::BAD_REQUEST, "days deve estar entre 1 e 366".to_string()));
+            }
+            let last_day = match args["date"].as_str() {
+                Some(date) => match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                    Ok(date) => date,
+                    Err(_) => return Err((StatusCode::BAD_REQUEST, format!("Data inválida (YYYY-MM-DD): {}", date))),
+                },
+                None => chrono::Local::now().date_naive(),
+            };
+            db.get_plc_availability(last_day, days as u32).await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+
+        // ── MODBUS TCP (polling) ──
+        "get_modbus_devices" => {
+            db.get_modbus_devices().await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+        "save_modbus_device" => {
+            match serde_json::from_value::<ModbusDevice>(args["device"].clone()) {
+                Ok(mut device) => {
+                    if let Err(e) = device.validate() {
+                        return Err((StatusCode::BAD_REQUEST, e));
+                    }
+                    device.id = db.save_modbus_device(&device).await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                    let id = device.id;
+                    modbus_client::apply(&state.pollers, device).await;
+                    Ok(serde_json::json!(id))
+                }
+                Err(e) => Err(format!("Equipamento Modbus inválido: {}", e)),
+            }
+        }
+        "delete_modbus_device" => {
+            let id = args["id"].as_i64().unwrap_or(0);
+            modbus_client::stop(&state.pollers, id).await;
+            db.delete_modbus_device(id).await
+                .map(|_| serde_json::json!("OK"))
+                .map_err(|e| e.to_string())
+        }
+
+        // ── S7 ISO-on-TCP (polling de DB) ──
+        "get_s7_devices" => {
+            db.get_s7_devices().await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+        "save_s7_device" => {
+            match serde_json::from_value::<S7Device>(args["device"].clone()) {
+                Ok(mut device) => {
+                    if let Err(e) = device.validate() {
+                        return Err((StatusCode::BAD_REQUEST, e));
+                    }
+                    device.id = db.save_s7_device(&device).await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                    let id = device.id;
+                    s7_client::apply(&state.pollers, device).await;
+                    Ok(serde_json::json!(id))
+                }
+                Err(e) => Err(format!("Equipamento S7 inválido: {}", e)),
+            }
+        }
+        "delete_s7_device" => {
+            let id = args["id"].as_i64().unwrap_or(0);
+            s7_client::stop(&state.pollers, id).await;
+            db.delete_s7_device(id).await
+                .map(|_| serde_json::json!("OK"))
+                .map_err(|e| e.to_string())
+        }
+
+        // ── GRAVAÇÃO / REPRODUÇÃO DE FRAMES ──
+        "get_capture_status" => {
+            Ok(serde_json::to_value(state.capture.status()).unwrap())
+        }
+        "start_capture" => {
+            let max_file_bytes = args["maxFileMb"].as_u64()
+                .map(|mb| mb * 1024 * 1024)
+                .unwrap_or(capture::DEFAULT_MAX_FILE_BYTES);
+            let max_files = args["maxFiles"].as_u64()
+                .map(|n| n as usize)
+                .unwrap_or(capture::DEFAULT_MAX_FILES);
+            state.capture.start_recording(max_file_bytes, max_files)
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            let _ = db.add_system_log("info", "capture", "Gravação de frames iniciada", "").await;
+            Ok(serde_json::to_value(state.capture.status()).unwrap())
+        }
+        "stop_capture" => {
+            let stopped = state.capture.stop_recording();
+            if stopped {
+                let _ = db.add_system_log("info", "capture", "Gravação de frames parada", "").await;
+            }
+            Ok(serde_json::json!(stopped))
+        }
+        "list_captures" => {
+            state.capture.list_files()
+                .map(|v| serde_json::to_value(v).unwrap())
+        }
+        "replay_capture" => {
+            let file = args["file"].as_str().unwrap_or("");
+            let speed = args["speed"].as_f64().unwrap_or(1.0);
+            let repeat = args["loop"].as_bool().unwrap_or(false);
+            let server = state.tcp_server.lock().await.clone()
+                .ok_or((StatusCode::SERVICE_UNAVAILABLE, "Servidor TCP não está ativo".to_string()))?;
+            let frames = state.capture.start_replay(server, file, speed, repeat).await
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            Ok(serde_json::json!({ "file": file, "frames": frames, "speed": speed, "loop": repeat }))
+        }
+        "stop_replay" => {
+            Ok(serde_json::json!(state.capture.stop_replay()))
+        }
+
+        // ── LAYOUT DO PACOTE PLC ──
+        "get_packet_layout" => {
+            db.get_active_packet_layout().await
+                .map(|layout| serde_json::to_value(layout.unwrap_or_else(PacketLayout::default_udt)).unwrap())
+                .map_err(|e| e.to_string())
+        }
+        "update_packet_layout" => {
+            match serde_json::from_value::<PacketLayout>(args["layout"].clone()) {
+                Ok(layout) => {
+                    if let Err(e) = layout.validate() {
+                        return Err((StatusCode::BAD_REQUEST, e));
+                    }
+                    db.save_packet_layout(&layout, true).await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                    let server_guard = state.tcp_server.lock().await;
+                    if let Some(server) = server_guard.as_ref() {
+                        server.set_packet_layout(layout).await
+                            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+                    }
+                    Ok(serde_json::json!("OK"))
+                }
+                Err(e) => Err(format!("Layout inválido: {}", e)),
+            }
+        }
+        "get_packet_layouts" => {
+            db.get_packet_layouts().await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+        "save_packet_layout" => {
+            // Regista um layout adicional (não altera o layout ativo)
+            match serde_json::from_value::<PacketLayout>(args["layout"].clone()) {
+                Ok(layout) => {
+                    if let Err(e) = layout.validate() {
+                        return Err((StatusCode::BAD_REQUEST, e));
+                    }
+                    db.save_packet_layout(&layout, false).await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                    let server_guard = state.tcp_server.lock().await;
+                    if let Some(server) = server_guard.as_ref() {
+                        if server.get_packet_layout().await.name == layout.name {
+                            server.set_packet_layout(layout).await
+                                .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+                        } else {
+                            server.register_layout(Arc::new(layout)).await;
+                        }
+                    }
+                    Ok(serde_json::json!("OK"))
+                }
+                Err(e) => Err(format!("Layout inválido: {}", e)),
+            }
+        }
+        "delete_packet_layout" => {
+            let name = args["name"].as_str().unwrap_or("");
+            let devices = db.get_plc_devices().await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let used_by: Vec<&str> = devices.iter()
+                .filter(|d| d.layout_name.as_deref() == Some(name))
+                .map(|d| d.name.as_str())
+                .collect();
+            if !used_by.is_empty() {
+                return Err((StatusCode::BAD_REQUEST, format!("Layout {} usado pelos PLCs: {}", name, used_by.join(", "))));
+            }
+            let deleted = db.delete_packet_layout(name).await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            if !deleted {
+                return Err((StatusCode::BAD_REQUEST, format!("Layout {} não existe ou é o layout ativo", name)));
+            }
+            let server_guard = state.tcp_server.lock().await;
+            if let Some(server) = server_guard.as_ref() {
+                server.unregister_layout(name).await;
+            }
+            Ok(serde_json::json!("OK"))
+        }
+
+        // ── ENQUADRAMENTO TSEND_C (raw / framed / auto) ──
+        "get_framing_mode" => {
+            let server_guard = state.tcp_server.lock().await;
+            match server_guard.as_ref() {
+                Some(server) => Ok(serde_json::json!(server.get_framing_mode().await.as_str())),
+                None => Ok(serde_json::json!(FramingMode::Auto.as_str())),
+            }
+        }
+        "set_framing_mode" => {
+            let value = args["mode"].as_str().unwrap_or("");
+            let Some(mode) = FramingMode::parse(value) else {
+                return Err((StatusCode::BAD_REQUEST, format!("Modo inválido: {} (raw, framed, auto)", value)));
+            };
+            db.set_display_config(FRAMING_MODE_KEY, mode.as_str(), "text").await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let server_guard = state.tcp_server.lock().await;
+            if let Some(server) = server_guard.as_ref() {
+                server.set_framing_mode(mode).await;
+            }
+            Ok(serde_json::json!(mode.as_str()))
+        }
+
+        // ── BANDA MORTA DO STREAM SSE EM MODO DELTA ──
+        "get_sse_deadbands" => {
+            db.get_sse_deadbands().await
+                .map(|v| serde_json::to_value(v).unwrap())
+                .map_err(|e| e.to_string())
+        }
+        "set_sse_deadband" => {
+            let tag = args["tag"].as_str().unwrap_or("").trim();
+            let deadband = args["deadband"].as_f64().unwrap_or(-1.0);
+            if tag.is_empty() {
+                return Err((StatusCode::BAD_REQUEST, "Tag não pode ser vazia".to_string()));
+            }
+            if !deadband.is_finite() || deadband < 0.0 {
+                return Err((StatusCode::BAD_REQUEST, "Banda morta tem de ser >= 0".to_string()));
+            }
+            db.set_sse_deadband(tag, deadband).await
+                .map(|_| serde_json::json!("OK"))
+                .map_err(|e| e.to_string())
+        }
+        "delete_sse_deadband" => {
+            let tag = args["tag"].as_str().unwrap_or("");
+            db.delete_sse_deadband(tag).await
+                .map(|v| serde_json::json!(v))
+                .map_err(|e| e.to_string())
+        }
+
         // ── VIDEO SERVER PORT (agora é a porta do próprio web server) ──
         "get_video_server_port" => {
             // Vídeos são servidos pelo mesmo servidor web
@@ -391,36 +907,484 @@ async fn handle_invoke(
             Ok(serde_json::json!("OK"))
         }
 
-        _ => Err(format!("Comando desconhecido: {}", payload.command)),
+        _ => Err(format!("Comando desconhecido: {}", command)),
     };
 
-    match result {
-        Ok(value) => Ok(Json(value)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e)),
+    // Configuração de exibição alterada: o motor do painel relê as regras
+    if result.is_ok() && DISPLAY_RULE_COMMANDS.contains(&command) {
+        state.display.reload().await;
     }
+
+    result.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+/// Apaga da biblioteca os ficheiros de vídeos removidos que já nenhum vídeo usa
+async fn remove_media_files(state: &AppState, file_paths: Vec<String>) {
+    let in_use = match state.database.get_all_videos().await {
+        Ok(videos) => videos.into_iter().map(|v| v.file_path).collect::<Vec<_>>(),
+        Err(e) => {
+            eprintln!("⚠️ Ficheiros de vídeo mantidos (erro ao ler vídeos): {:?}", e);
+            return;
+        }
+    };
+    for path in file_paths {
+        if !in_use.contains(&path) {
+            state.media.remove(&path).await;
+        }
+    }
+}
+
+/// Relatórios de exibição e de supressão do período [from, to] (dias locais, AAAA-MM-DD)
+async fn play_reports(
+    db: &Database,
+    from: Option<&str>,
+    to: Option<&str>,
+    panel: Option<&str>,
+) -> Result<(Vec<PlayReportRow>, Vec<AdGapReportRow>), (StatusCode, String)> {
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let (start, end) = proof_of_play::report_range(from.unwrap_or(&today), to.unwrap_or(&today))
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let panel = panel.filter(|p| !p.is_empty());
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let plays = db.get_video_plays(&start, &end, panel).await.map_err(internal)?;
+    let gaps = db.get_ad_gaps(&start, &end, panel).await.map_err(internal)?;
+    Ok((proof_of_play::play_report(&plays), proof_of_play::gap_report(&gaps)))
+}
+
+/// Rejeita painéis que referem bits, vídeos, textos ou playlists inexistentes
+async fn check_panel_items(db: &Database, panel: &Panel) -> Result<(), (StatusCode, String)> {
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    if let Some(ids) = &panel.bit_ids {
+        let bits = db.get_all_bit_configs().await.map_err(internal)?;
+        if let Some(id) = ids.iter().find(|id| !bits.iter().any(|b| b.id == **id)) {
+            return Err((StatusCode::BAD_REQUEST, format!("Bit desconhecido: {}", id)));
+        }
+    }
+    if let Some(ids) = &panel.video_ids {
+        let videos = db.get_all_videos().await.map_err(internal)?;
+        if let Some(id) = ids.iter().find(|id| !videos.iter().any(|v| v.id == **id)) {
+            return Err((StatusCode::BAD_REQUEST, format!("Vídeo desconhecido: {}", id)));
+        }
+    }
+    let texts = db.get_all_texts().await.map_err(internal)?;
+    let keys = panel.text_keys.iter().flatten().chain(panel.idle_text_key.as_ref());
+    if let Some(key) = keys.into_iter().find(|k| !texts.iter().any(|t| &t.key == *k)) {
+        return Err((StatusCode::BAD_REQUEST, format!("Texto desconhecido: {}", key)));
+    }
+    if let Some(ids) = &panel.playlist_ids {
+        let playlists = db.get_playlists().await.map_err(internal)?;
+        if let Some(id) = ids.iter().find(|id| !playlists.iter().any(|p| p.id == **id)) {
+            return Err((StatusCode::BAD_REQUEST, format!("Playlist desconhecida: {}", id)));
+        }
+    }
+    Ok(())
+}
+
+/// Configuração efetiva de um painel: o painel e os bits, vídeos, textos e playlists que lhe pertencem
+async fn get_panel_content(db: &Database, id: &str) -> Result<serde_json::Value, String> {
+    let panel = db.get_panels().await.map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|p| p.id == id)
+        .ok_or_else(|| format!("Painel desconhecido: {}", id))?;
+    let bits: Vec<_> = db.get_all_bit_configs().await.map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|b| panel.bit_ids.as_ref().is_none_or(|ids| ids.contains(&b.id)))
+        .collect();
+    let videos: Vec<_> = db.get_all_videos().await.map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|v| panel.video_ids.as_ref().is_none_or(|ids| ids.contains(&v.id)))
+        .collect();
+    let texts: Vec<_> = db.get_all_texts().await.map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|t| panel.text_keys.as_ref().is_none_or(|keys| keys.contains(&t.key)))
+        .collect();
+    let playlists: Vec<_> = db.get_playlists().await.map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|p| panel.playlist_ids.as_ref().is_none_or(|ids| ids.contains(&p.id)))
+        .collect();
+    Ok(serde_json::json!({ "panel": panel, "bits": bits, "videos": videos, "texts": texts, "playlists": playlists }))
+}
+
+/// Reaplica o inventário de PLCs da base de dados no servidor TCP ativo
+async fn reload_plc_devices(state: &AppState) -> Result<(), (StatusCode, String)> {
+    let devices = state.database.get_plc_devices().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let server_guard = state.tcp_server.lock().await;
+    if let Some(server) = server_guard.as_ref() {
+        server.set_devices(devices).await;
+    }
+    Ok(())
+}
+
+/// Reaplica a allowlist da base de dados no servidor TCP ativo
+async fn reload_allowlist(state: &AppState) -> Result<(), (StatusCode, String)> {
+    let server_guard = state.tcp_server.lock().await;
+    if let Some(server) = server_guard.as_ref() {
+        server.reload_allowlist().await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+    Ok(())
 }
 
 // ============================================================================
 // SSE - PLC DATA STREAM
+// ?plc=<ip|nome|id> restringe o stream aos frames de um único PLC
+// ?mode=delta&keyframe=<s> envia só as mudanças entre keyframes (ver plc_stream.rs)
+// Ao ligar recebe logo o último frame de cada PLC; ao religar com
+// Last-Event-ID recebe os frames perdidos (buffer de replay). Se ficar para
+// trás além do buffer recebe um evento "resync" seguido de um snapshot completo
 // ============================================================================
 
+#[derive(serde::Deserialize)]
+struct PlcSseQuery {
+    plc: Option<String>,
+    mode: Option<String>,     // "full" (padrão) ou "delta"
+    keyframe: Option<u64>,    // Segundos entre keyframes no modo delta
+}
+
+/// Cliente do stream de dados PLC: snapshot em cache ou replay + frames do broadcast
+struct PlcSseClient {
+    state: Arc<AppState>,
+    rx: broadcast::Receiver<PlcFrame>,
+    plc_filter: Option<String>,
+    delta: Option<DeltaEncoder>,
+    last_id: u64, // Último frame entregue (frames repetidos do broadcast são ignorados)
+    pending: VecDeque<Event>,
+}
+
+impl PlcSseClient {
+    fn encode(&mut self, data: &PlcData) -> Option<Event> {
+        if let Some(filter) = &self.plc_filter {
+            if !data.matches_plc(filter) {
+                return None;
+            }
+        }
+        let payload = match self.delta.as_mut() {
+            Some(encoder) => encoder.encode(data)?,
+            None => serde_json::json!({ "message": data }),
+        };
+        Event::default().json_data(payload).ok()
+    }
+
+    /// Frame publicado: evento com id (o browser reenvia-o em Last-Event-ID)
+    async fn encode_frame(&mut self, frame: &PlcFrame) -> Option<Event> {
+        self.last_id = frame.id;
+        let event = self.encode(&frame.data)?;
+        Some(event.id(self.state.replay.read().await.event_id(frame.id)))
+    }
+
+    /// Enfileira o último frame de cada PLC (cache latest_data). No modo delta
+    /// os baselines são descartados, por isso o snapshot segue como keyframes.
+    async fn queue_snapshot(&mut self) {
+        // Ler o id antes da cache: frames até aqui já estão nela, os seguintes vêm pelo broadcast
+        self.last_id = self.state.replay.read().await.last_id();
+        let server = self.state.tcp_server.lock().await.clone();
+        let frames = match server {
+            Some(server) => server.get_latest_frames().await,
+            None => Vec::new(),
+        };
+        if let Some(encoder) = self.delta.as_mut() {
+            encoder.reset();
+        }
+        for data in frames {
+            if let Some(event) = self.encode(&data) {
+                self.pending.push_back(event);
+            }
+        }
+    }
+
+    /// Enfileira os frames publicados depois de `last_id`; false se já não estão no buffer
+    async fn queue_replay(&mut self, last_id: u64) -> bool {
+        let Some(frames) = self.state.replay.read().await.since(last_id) else {
+            return false;
+        };
+        self.last_id = last_id;
+        for frame in frames {
+            if let Some(event) = self.encode_frame(&frame).await {
+                self.pending.push_back(event);
+            }
+        }
+        true
+    }
+
+    async fn next_event(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            match self.rx.recv().await {
+                Ok(frame) => {
+                    if frame.id <= self.last_id {
+                        continue; // Já enviado no replay
+                    }
+                    if let Some(event) = self.encode_frame(&frame).await {
+                        return Some(event);
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    // Cliente lento perdeu frames: repor a partir do buffer ou,
+                    // se já lá não estão, avisar e reenviar o estado completo
+                    if self.queue_replay(self.last_id).await {
+                        continue;
+                    }
+                    println!("⚠️ SSE: cliente atrasado perdeu {} frames - ressincronizar", missed);
+                    if let Ok(event) = Event::default().event("resync").json_data(serde_json::json!({ "missed": missed })) {
+                        self.pending.push_back(event);
+                    }
+                    self.queue_snapshot().await;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
 async fn handle_plc_sse(
     State(state): State<Arc<AppState>>,
+    Query(query): Query<PlcSseQuery>,
+    headers: HeaderMap,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // Subscrever antes de ler a cache/buffer: nenhum frame se perde entre os dois
     let rx = state.plc_broadcast.subscribe();
+    let plc_filter = query.plc.filter(|p| !p.is_empty());
 
-    let stream = BroadcastStream::new(rx)
-        .filter_map(|msg| {
-            match msg {
-                Ok(data) => {
-                    let payload = serde_json::json!({ "message": data });
-                    match Event::default().json_data(payload) {
-                        Ok(event) => Some(Ok(event)),
-                        Err(_) => None,
-                    }
+    let mode = query.mode.as_deref().and_then(SseMode::parse).unwrap_or(SseMode::Full);
+    let delta = match mode {
+        SseMode::Full => None,
+        SseMode::Delta => {
+            let keyframe = query.keyframe.unwrap_or(DEFAULT_KEYFRAME_SECS).clamp(1, MAX_KEYFRAME_SECS);
+            let deadbands = state.database.get_sse_deadbands().await.unwrap_or_default();
+            Some(DeltaEncoder::new(std::time::Duration::from_secs(keyframe), deadbands))
+        }
+    };
+
+    // Reconexão do browser: Last-Event-ID do último frame recebido
+    let last_event_id = match headers.get("last-event-id").and_then(|v| v.to_str().ok()) {
+        Some(value) => state.replay.read().await.parse_event_id(value),
+        None => None,
+    };
+
+    let mut client = PlcSseClient { state, rx, plc_filter, delta, last_id: 0, pending: VecDeque::new() };
+    let replayed = match last_event_id {
+        Some(last_id) => client.queue_replay(last_id).await,
+        None => false,
+    };
+    if !replayed {
+        client.queue_snapshot().await;
+    }
+
+    let stream = futures::stream::unfold(client, |mut client| async move {
+        client.next_event().await.map(|event| (Ok::<_, Infallible>(event), client))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// ============================================================================
+// SSE - ESTADO DO PAINEL (display_engine)
+// ?panel=<id> (padrão: "default", configuração global)
+// Estado atual ao ligar e depois cada mudança; um cliente atrasado recebe
+// diretamente o estado atual (só o último interessa). O stream termina se o
+// painel for apagado.
+// ============================================================================
+
+#[derive(serde::Deserialize)]
+struct PanelStateQuery {
+    panel: Option<String>,
+}
+
+async fn handle_panel_state_sse(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PanelStateQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let panel_id = query.panel.filter(|p| !p.is_empty()).unwrap_or_else(|| DEFAULT_PANEL_ID.to_string());
+    let rx = state.display.subscribe();
+    let Some(initial) = state.display.current(&panel_id).await else {
+        return Err((StatusCode::NOT_FOUND, format!("Painel desconhecido: {}", panel_id)));
+    };
+
+    let stream = futures::stream::unfold((state, rx, panel_id, Some(initial)), |(state, mut rx, panel_id, initial)| async move {
+        let panel: PanelState = match initial {
+            Some(panel) => panel,
+            None => loop {
+                match rx.recv().await {
+                    Ok(panel) if panel.panel == panel_id => break panel.stamped(),
+                    Ok(_) => continue, // Outro painel
+                    Err(RecvError::Lagged(_)) => break state.display.current(&panel_id).await?,
+                    Err(RecvError::Closed) => return None,
                 }
-                Err(_) => None,
+            },
+        };
+        let event = Event::default().json_data(&panel).unwrap_or_default();
+        Some((Ok::<_, Infallible>(event), (state, rx, panel_id, None)))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+// ============================================================================
+// UPLOAD DE VÍDEOS (multipart) → biblioteca + video_configs num só passo
+// POST /api/videos/upload
+//   file         ficheiro de vídeo (obrigatório)
+//   name         nome (padrão: nome do ficheiro sem extensão)
+//   duration, enabled, priority, description   como em add_video
+// Responde com o VideoConfig criado. 415 extensão não suportada, 413 ficheiro
+// acima de MEDIA_MAX_UPLOAD_MB.
+// ============================================================================
+
+async fn handle_video_upload(
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let bad_request = |e: axum::extract::multipart::MultipartError| (StatusCode::BAD_REQUEST, format!("Upload inválido: {}", e));
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut stored: Option<(String, String)> = None;
+
+    while let Some(mut field) = multipart.next_field().await.map_err(bad_request)? {
+        let field_name = field.name().unwrap_or("").to_string();
+        if field_name != "file" {
+            fields.insert(field_name, field.text().await.map_err(bad_request)?);
+            continue;
+        }
+        if stored.is_some() {
+            return Err((StatusCode::BAD_REQUEST, "Só é aceite um ficheiro por upload".to_string()));
+        }
+
+        let original_name = field.file_name().unwrap_or("").to_string();
+        let mut upload = state.media.begin_upload(&original_name).await
+            .map_err(|e| (StatusCode::UNSUPPORTED_MEDIA_TYPE, e))?;
+        loop {
+            let chunk = match field.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    upload.discard().await;
+                    return Err(bad_request(e));
+                }
+            };
+            if upload.written() + chunk.len() as u64 > state.media.max_upload_bytes() {
+                upload.discard().await;
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, format!(
+                    "Ficheiro acima do máximo de {} MB: {}", state.media.max_upload_bytes() / 1024 / 1024, original_name
+                )));
+            }
+            if let Err(e) = upload.write(&chunk).await {
+                upload.discard().await;
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, e));
             }
+        }
+        if upload.written() == 0 {
+            upload.discard().await;
+            return Err((StatusCode::BAD_REQUEST, format!("Ficheiro vazio: {}", original_name)));
+        }
+        let size = upload.written();
+        let file_path = upload.finish().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        println!("📤 Vídeo recebido: {} ({} bytes) → {}", original_name, size, file_path);
+        stored = Some((original_name, file_path));
+    }
+
+    let Some((original_name, file_path)) = stored else {
+        return Err((StatusCode::BAD_REQUEST, "Campo 'file' em falta".to_string()));
+    };
+    let default_name = std::path::Path::new(&original_name).file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let field = |key: &str| fields.get(key).map(|v| v.trim()).filter(|v| !v.is_empty());
+    let name = field("name").unwrap_or(&default_name);
+    let duration = field("duration").and_then(|v| v.parse().ok()).unwrap_or(30);
+    let enabled = field("enabled").map(|v| v == "true" || v == "1").unwrap_or(true);
+    let priority = field("priority").and_then(|v| v.parse().ok()).unwrap_or(50);
+    let description = field("description").unwrap_or("");
+
+    let video = match state.database.add_video(name, &file_path, duration, enabled, priority, description).await {
+        Ok(id) => state.database.get_video(id).await,
+        Err(e) => Err(e),
+    };
+    match video {
+        Ok(video) => {
+            state.display.reload().await;
+            Ok(Json(serde_json::to_value(video).unwrap()))
+        }
+        Err(e) => {
+            state.media.remove(&file_path).await;
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        }
+    }
+}
+
+// ============================================================================
+// RELATÓRIOS CSV (proof of play)
+// /api/reports/plays.csv e /api/reports/ad-gaps.csv ?from=AAAA-MM-DD&to=AAAA-MM-DD&panel=<id>
+// (sem datas: hoje; sem panel: todos)
+// ============================================================================
+
+#[derive(serde::Deserialize)]
+struct ReportQuery {
+    from: Option<String>,
+    to: Option<String>,
+    panel: Option<String>,
+}
+
+async fn handle_plays_csv(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let (plays, _) = play_reports(&state.database, query.from.as_deref(), query.to.as_deref(), query.panel.as_deref()).await?;
+    Ok(csv_response("plays", &query, proof_of_play::play_report_csv(&plays)))
+}
+
+async fn handle_ad_gaps_csv(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let (_, gaps) = play_reports(&state.database, query.from.as_deref(), query.to.as_deref(), query.panel.as_deref()).await?;
+    Ok(csv_response("ad-gaps", &query, proof_of_play::gap_report_csv(&gaps)))
+}
+
+fn csv_response(name: &str, query: &ReportQuery, csv: String) -> Response {
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let filename = format!(
+        "{}_{}_{}.csv",
+        name,
+        query.from.as_deref().unwrap_or(&today),
+        query.to.as_deref().unwrap_or(&today),
+    );
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/csv; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(Body::from(csv))
+        .unwrap()
+}
+
+// ============================================================================
+// SSE - EVENTOS DO SERVIDOR TCP (eventos nomeados)
+// plc-connected, plc-disconnected, tcp-stats, tcp-connection-slow, ...
+// Envia um tcp-stats inicial para o cliente não depender de get_tcp_stats
+// ============================================================================
+
+async fn handle_tcp_events_sse(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let rx = state.tcp_events.subscribe();
+
+    let initial = {
+        let server_guard = state.tcp_server.lock().await;
+        match server_guard.as_ref() {
+            Some(server) => Some(TcpEvent::Stats(server.get_connection_stats().await)),
+            None => None,
+        }
+    };
+
+    let stream = tokio_stream::iter(initial)
+        .chain(BroadcastStream::new(rx).filter_map(|msg| msg.ok()))
+        .filter_map(|event| {
+            Event::default()
+                .event(event.name())
+                .json_data(&event)
+                .ok()
+                .map(Ok)
         });
 
     Sse::new(stream).keep_alive(KeepAlive::default())
@@ -428,19 +1392,27 @@ async fn handle_plc_sse(
 
 // ============================================================================
 // VIDEO FILE SERVING (com Range requests para streaming)
+// /api/video/<caminho relativo às raízes de media> (ver media_library.rs)
 // ============================================================================
 
 async fn handle_video(
+    State(state): State<Arc<AppState>>,
     axum::extract::Path(path): axum::extract::Path<String>,
     headers: HeaderMap,
 ) -> Response {
-    // Reconstruir path absoluto (o path vem sem a / inicial)
-    let file_path = format!("/{}", path);
-    let file_path = std::path::Path::new(&file_path);
-
-    if !file_path.exists() {
-        return (StatusCode::NOT_FOUND, "File not found").into_response();
-    }
+    let file_path = match state.media.resolve(&path) {
+        Ok(file_path) => file_path,
+        Err(MediaPathError::Invalid(e)) => {
+            eprintln!("🚫 /api/video recusado: {}", e);
+            return (StatusCode::BAD_REQUEST, "Invalid video path").into_response();
+        }
+        Err(MediaPathError::Escapes(e)) => {
+            eprintln!("🚫 /api/video recusado (fora das raízes de media): {}", e);
+            return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+        }
+        Err(MediaPathError::NotFound) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+    };
+    let file_path = file_path.as_path();
 
     let file_size = match tokio::fs::metadata(file_path).await {
         Ok(m) => m.len(),
@@ -473,8 +1445,17 @@ async fn handle_video(
         let start: u64 = parts[0].parse().unwrap_or(0);
         let end: u64 = parts.get(1)
             .and_then(|v| if v.is_empty() { None } else { v.parse().ok() })
-            .unwrap_or_else(|| (start + 2 * 1024 * 1024).min(file_size - 1)) // 2MB chunks
+            .unwrap_or_else(|| start.saturating_add(2 * 1024 * 1024)) // 2MB chunks
             .min(file_size - 1);
+        if start > end {
+            // Início depois do fim do ficheiro ou intervalo invertido
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", file_size))
+                .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                .body(Body::empty())
+                .unwrap();
+        }
         let length = end - start + 1;
 
         let file_path_owned = file_path.to_path_buf();
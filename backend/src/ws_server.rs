@@ -0,0 +1,514 @@
+// ws_server.rs - WEBSOCKET /api/ws (SUBSCRIÇÃO POR TAGS + COMANDOS)
+// ============================================================================
+// Uma única ligação bidirecional para a UI de administração:
+//   → {"type":"subscribe","tags":["Real[10..20]","Word[0]","Nivel*"],"plc":"10.0.0.5","maxRate":2}
+//   ← {"type":"subscribed","tags":[...],"plc":...,"maxRate":2}
+//   ← {"type":"data","delta":PlcDelta}   (só as tags subscritas que mudaram)
+//   → {"type":"unsubscribe"}
+//   → {"type":"invoke","id":7,"command":"get_plc_devices","args":{}}
+//   ← {"type":"result","id":7,"ok":true,"data":...} / {"type":"result","id":7,"ok":false,"error":"..."}
+//
+// Padrões de tag: "Word[3]" (exata), "Real[10..20]" (índices inclusivos),
+// "Real" ou "Real[*]" (segmento inteiro), "Nivel*" (prefixo), "*" (tudo).
+// Ao subscrever o cliente recebe logo o último valor de cada tag; depois só as
+// mudanças. maxRate (mensagens/s por PLC) funde os frames intermédios: segue
+// o estado mais recente no próximo envio permitido.
+// Os comandos são os mesmos de POST /api/invoke e correm em paralelo com o
+// stream (um comando lento não atrasa os dados), no máximo
+// MAX_INFLIGHT_INVOKES de cada vez por ligação; acima disso a resposta é
+// ok:false e o cliente tem de esperar pelas anteriores.
+// ============================================================================
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use axum::{
+    extract::{State, ws::{Message, WebSocket, WebSocketUpgrade}},
+    response::Response,
+};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::sync::{broadcast::error::RecvError, mpsc, OwnedSemaphorePermit, Semaphore};
+
+use crate::packet_layout::Quality;
+use crate::plc_stream::PlcDelta;
+use crate::tcp_server::PlcData;
+use crate::web_server::{invoke_command, AppState};
+
+const FLUSH_INTERVAL_MS: u64 = 50;   // Cadência de verificação dos envios pendentes
+const MAX_RATE_HZ: f64 = 20.0;       // Limite superior (= 1000 / FLUSH_INTERVAL_MS)
+const MIN_RATE_HZ: f64 = 0.1;        // Pelo menos uma mensagem a cada 10 s
+const OUTBOX_CAPACITY: usize = 256;  // Mensagens à espera de escrita no socket
+const MAX_INFLIGHT_INVOKES: usize = 8; // Comandos em curso por ligação
+
+// ============================================================================
+// PADRÕES DE TAG
+// ============================================================================
+
+#[derive(Debug, Clone)]
+enum TagPattern {
+    Exact(String),                                        // "Word[3]"
+    Range { segment: String, from: usize, to: usize },    // "Real[10..20]"
+    Segment(String),                                      // "Real" / "Real[*]"
+    Prefix(String),                                       // "Nivel*" / "*"
+}
+
+impl TagPattern {
+    fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("Padrão de tag vazio".to_string());
+        }
+        if let Some(prefix) = value.strip_suffix('*') {
+            if !prefix.ends_with('[') {
+                return Ok(TagPattern::Prefix(prefix.to_string()));
+            }
+        }
+        let Some((segment, rest)) = value.split_once('[') else {
+            return Ok(TagPattern::Segment(value.to_string()));
+        };
+        let invalid = || format!("Padrão de tag inválido: {}", value);
+        let inner = rest.strip_suffix(']').ok_or_else(invalid)?;
+        if segment.is_empty() {
+            return Err(invalid());
+        }
+        if inner == "*" {
+            return Ok(TagPattern::Segment(segment.to_string()));
+        }
+        if let Some((from, to)) = inner.split_once("..") {
+            let from: usize = from.trim().parse().map_err(|_| invalid())?;
+            let to: usize = to.trim().parse().map_err(|_| invalid())?;
+            if from > to {
+                return Err(format!("Intervalo invertido: {}", value));
+            }
+            return Ok(TagPattern::Range { segment: segment.to_string(), from, to });
+        }
+        inner.trim().parse::<usize>().map_err(|_| invalid())?;
+        Ok(TagPattern::Exact(value.to_string()))
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            TagPattern::Exact(tag) => name == tag,
+            TagPattern::Prefix(prefix) => name.starts_with(prefix.as_str()),
+            TagPattern::Segment(segment) => match name.split_once('[') {
+                Some((s, _)) => s == segment,
+                None => name == segment,
+            },
+            TagPattern::Range { segment, from, to } => match name.split_once('[') {
+                Some((s, index)) if s == segment => index.strip_suffix(']')
+                    .and_then(|i| i.parse::<usize>().ok())
+                    .is_some_and(|i| (*from..=*to).contains(&i)),
+                _ => false,
+            },
+        }
+    }
+}
+
+// ============================================================================
+// MENSAGENS DO CLIENTE
+// ============================================================================
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe {
+        tags: Vec<String>,
+        #[serde(default)]
+        plc: Option<String>,
+        #[serde(default, rename = "maxRate")]
+        max_rate: Option<f64>, // Mensagens por segundo por PLC (padrão e máximo: 20)
+    },
+    Unsubscribe,
+    Invoke {
+        #[serde(default)]
+        id: serde_json::Value,
+        command: String,
+        #[serde(default)]
+        args: serde_json::Value,
+    },
+}
+
+struct Subscription {
+    patterns: Vec<TagPattern>,
+    plc: Option<String>,
+    min_interval: Duration,
+}
+
+impl Subscription {
+    fn wants(&self, name: &str) -> bool {
+        self.patterns.iter().any(|p| p.matches(name))
+    }
+}
+
+// ============================================================================
+// SESSÃO
+// ============================================================================
+
+/// Estado de um PLC para este cliente: último frame por enviar e valores já enviados
+#[derive(Default)]
+struct PlcView {
+    pending: Option<Arc<PlcData>>,
+    sent_at: Option<Instant>,
+    conn_id: u64,
+    variables: HashMap<String, f64>,
+    texts: HashMap<String, String>,
+    quality: HashMap<String, Quality>, // Só qualidades não "good"
+}
+
+#[derive(Default)]
+struct WsSession {
+    subscription: Option<Subscription>,
+    plcs: HashMap<String, PlcView>,
+}
+
+impl WsSession {
+    /// Substitui a subscrição; `frames` (cache latest_data) seguem no próximo envio
+    fn subscribe(&mut self, subscription: Subscription, frames: Vec<PlcData>) {
+        self.plcs.clear();
+        self.subscription = Some(subscription);
+        for data in frames {
+            self.on_frame(Arc::new(data));
+        }
+    }
+
+    fn unsubscribe(&mut self) {
+        self.subscription = None;
+        self.plcs.clear();
+    }
+
+    fn on_frame(&mut self, data: Arc<PlcData>) {
+        let Some(subscription) = &self.subscription else {
+            return;
+        };
+        if subscription.plc.as_deref().is_some_and(|filter| !data.matches_plc(filter)) {
+            return;
+        }
+        let ip = data.source_ip.clone();
+        self.plcs.entry(ip).or_default().pending = Some(data);
+    }
+
+    /// Mensagens "data" dos PLCs cujo intervalo mínimo já passou
+    fn flush(&mut self) -> Vec<String> {
+        let Some(subscription) = &self.subscription else {
+            return Vec::new();
+        };
+        let mut messages = Vec::new();
+        for view in self.plcs.values_mut() {
+            if view.sent_at.is_some_and(|t| t.elapsed() < subscription.min_interval) {
+                continue;
+            }
+            let Some(data) = view.pending.take() else {
+                continue;
+            };
+            if let Some(delta) = view.diff(subscription, &data) {
+                view.sent_at = Some(Instant::now());
+                messages.push(serde_json::json!({ "type": "data", "delta": delta }).to_string());
+            }
+        }
+        messages
+    }
+}
+
+impl PlcView {
+    /// Tags subscritas que mudaram desde o último envio (None = nada a enviar)
+    fn diff(&mut self, subscription: &Subscription, data: &PlcData) -> Option<PlcDelta> {
+        if self.conn_id != data.conn_id {
+            // Nova conexão do PLC: reenviar tudo
+            self.conn_id = data.conn_id;
+            self.variables.clear();
+            self.texts.clear();
+            self.quality.clear();
+        }
+
+        let mut delta = PlcDelta {
+            timestamp: data.timestamp.clone(),
+            received_at: data.received_at.clone(),
+            source_ip: data.source_ip.clone(),
+            conn_id: data.conn_id,
+            plc_name: data.plc_name.clone(),
+            variables: HashMap::new(),
+            texts: HashMap::new(),
+            quality: HashMap::new(),
+            removed: Vec::new(),
+        };
+
+        for name in data.quality.keys().chain(self.quality.keys()) {
+            let new = data.quality.get(name).copied().unwrap_or_default();
+            let old = self.quality.get(name).copied().unwrap_or_default();
+            if new != old && subscription.wants(name) {
+                delta.quality.insert(name.clone(), new);
+            }
+        }
+        self.quality = data.quality.iter()
+            .filter(|(name, _)| subscription.wants(name))
+            .map(|(name, &q)| (name.clone(), q))
+            .collect();
+
+        for (name, &value) in &data.variables {
+            if !subscription.wants(name) {
+                continue;
+            }
+            if self.variables.get(name) != Some(&value) || delta.quality.contains_key(name) {
+                delta.variables.insert(name.clone(), value);
+                self.variables.insert(name.clone(), value);
+            }
+        }
+        for (name, text) in &data.texts {
+            if !subscription.wants(name) {
+                continue;
+            }
+            if self.texts.get(name) != Some(text) || delta.quality.contains_key(name) {
+                delta.texts.insert(name.clone(), text.clone());
+                self.texts.insert(name.clone(), text.clone());
+            }
+        }
+
+        // Variáveis que deixaram de existir (ex: Real com NaN fica sem valor)
+        self.variables.retain(|name, _| {
+            let keep = data.variables.contains_key(name);
+            if !keep {
+                delta.removed.push(name.clone());
+            }
+            keep
+        });
+        self.texts.retain(|name, _| {
+            let keep = data.texts.contains_key(name);
+            if !keep && !delta.removed.contains(name) {
+                delta.removed.push(name.clone());
+            }
+            keep
+        });
+
+        let empty = delta.variables.is_empty() && delta.texts.is_empty()
+            && delta.quality.is_empty() && delta.removed.is_empty();
+        if empty { None } else { Some(delta) }
+    }
+}
+
+// ============================================================================
+// HANDLER
+// ============================================================================
+
+pub async fn handle_ws(State(state): State<Arc<AppState>>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| run_session(state, socket))
+}
+
+async fn run_session(state: Arc<AppState>, socket: WebSocket) {
+    let (mut sink, mut stream) = socket.split();
+
+    // Escrita no socket numa task própria: dados e respostas de comandos
+    // (que correm em paralelo) partilham a mesma fila
+    let (outbox, mut outbox_rx) = mpsc::channel::<String>(OUTBOX_CAPACITY);
+    let writer = tokio::spawn(async move {
+        while let Some(text) = outbox_rx.recv().await {
+            if sink.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut rx = state.plc_broadcast.subscribe();
+    let mut session = WsSession::default();
+    let invokes = Arc::new(Semaphore::new(MAX_INFLIGHT_INVOKES));
+    let mut ticker = tokio::time::interval(Duration::from_millis(FLUSH_INTERVAL_MS));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    println!("🔌 WebSocket: cliente ligado");
+
+    loop {
+        tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    if let Some(reply) = handle_client_message(&state, &mut session, &outbox, &invokes, &text).await {
+                        if outbox.send(reply.to_string()).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {} // Ping/pong tratados pelo axum; binário ignorado
+            },
+            frame = rx.recv() => match frame {
+                Ok(frame) => session.on_frame(frame.data),
+                Err(RecvError::Lagged(missed)) => {
+                    // Cliente lento: repor a partir da cache (só seguem as diferenças)
+                    println!("⚠️ WebSocket: cliente atrasado perdeu {} frames - ressincronizar", missed);
+                    for data in latest_frames(&state).await {
+                        session.on_frame(Arc::new(data));
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = ticker.tick() => {
+                let mut closed = false;
+                for text in session.flush() {
+                    if outbox.send(text).await.is_err() {
+                        closed = true;
+                        break;
+                    }
+                }
+                if closed {
+                    break;
+                }
+            }
+        }
+    }
+
+    drop(outbox);
+    let _ = writer.await;
+    println!("🔌 WebSocket: cliente desligado");
+}
+
+/// Processa uma mensagem do cliente; devolve a resposta imediata (se houver)
+async fn handle_client_message(
+    state: &Arc<AppState>,
+    session: &mut WsSession,
+    outbox: &mpsc::Sender<String>,
+    invokes: &Arc<Semaphore>,
+    text: &str,
+) -> Option<serde_json::Value> {
+    let message: ClientMessage = match serde_json::from_str(text) {
+        Ok(m) => m,
+        Err(e) => return Some(error_reply(format!("Mensagem inválida: {}", e))),
+    };
+
+    match message {
+        ClientMessage::Subscribe { tags, plc, max_rate } => {
+            let patterns = match tags.iter().map(|t| TagPattern::parse(t)).collect::<Result<Vec<_>, _>>() {
+                Ok(p) => p,
+                Err(e) => return Some(error_reply(e)),
+            };
+            let rate = match subscription_rate(max_rate) {
+                Ok(rate) => rate,
+                Err(e) => return Some(error_reply(e)),
+            };
+            let plc = plc.filter(|p| !p.is_empty());
+            let subscription = Subscription {
+                patterns,
+                plc: plc.clone(),
+                min_interval: Duration::from_secs_f64(1.0 / rate),
+            };
+            session.subscribe(subscription, latest_frames(state).await);
+            Some(serde_json::json!({ "type": "subscribed", "tags": tags, "plc": plc, "maxRate": rate }))
+        }
+        ClientMessage::Unsubscribe => {
+            session.unsubscribe();
+            Some(serde_json::json!({ "type": "unsubscribed" }))
+        }
+        ClientMessage::Invoke { id, command, args } => {
+            let permit = match start_invoke(invokes, &id) {
+                Ok(permit) => permit,
+                Err(reply) => return Some(reply),
+            };
+            let state = state.clone();
+            let outbox = outbox.clone();
+            tokio::spawn(async move {
+                let _permit = permit;
+                let reply = match invoke_command(&state, &command, &args).await {
+                    Ok(data) => serde_json::json!({ "type": "result", "id": id, "ok": true, "data": data }),
+                    Err((_, error)) => serde_json::json!({ "type": "result", "id": id, "ok": false, "error": error }),
+                };
+                let _ = outbox.send(reply.to_string()).await;
+            });
+            None
+        }
+    }
+}
+
+/// maxRate pedido (padrão: o máximo), limitado a [MIN_RATE_HZ, MAX_RATE_HZ]
+fn subscription_rate(max_rate: Option<f64>) -> Result<f64, String> {
+    let rate = max_rate.unwrap_or(MAX_RATE_HZ);
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(format!("maxRate inválido: {}", rate));
+    }
+    Ok(rate.clamp(MIN_RATE_HZ, MAX_RATE_HZ))
+}
+
+/// Reserva um lugar para o comando; sem lugar devolve logo a resposta de erro
+fn start_invoke(invokes: &Arc<Semaphore>, id: &serde_json::Value) -> Result<OwnedSemaphorePermit, serde_json::Value> {
+    invokes.clone().try_acquire_owned().map_err(|_| serde_json::json!({
+        "type": "result", "id": id, "ok": false,
+        "error": format!("Demasiados comandos em curso (máximo {})", MAX_INFLIGHT_INVOKES),
+    }))
+}
+
+fn error_reply(error: String) -> serde_json::Value {
+    serde_json::json!({ "type": "error", "error": error })
+}
+
+/// Último frame de cada PLC (vazio se o servidor TCP não está a correr)
+async fn latest_frames(state: &AppState) -> Vec<PlcData> {
+    let server = state.tcp_server.lock().await.clone();
+    match server {
+        Some(server) => server.get_latest_frames().await,
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        TagPattern::parse(pattern).unwrap().matches(name)
+    }
+
+    #[test]
+    fn exact_and_range_patterns() {
+        assert!(matches("Word[3]", "Word[3]"));
+        assert!(!matches("Word[3]", "Word[30]"));
+
+        assert!(matches("Real[10..20]", "Real[10]"));
+        assert!(matches("Real[10..20]", "Real[20]"));
+        assert!(matches("Real[ 5 .. 5 ]", "Real[5]"));
+        assert!(!matches("Real[10..20]", "Real[21]"));
+        assert!(!matches("Real[10..20]", "Int[15]"));
+        assert!(!matches("Real[10..20]", "Real"));
+    }
+
+    #[test]
+    fn segment_and_prefix_patterns() {
+        for pattern in ["Real", "Real[*]"] {
+            assert!(matches(pattern, "Real[0]"));
+            assert!(matches(pattern, "Real"));
+            assert!(!matches(pattern, "RealX[0]"));
+        }
+        assert!(matches("Nivel*", "Nivel_Tanque"));
+        assert!(!matches("Nivel*", "Word[0]"));
+        assert!(matches("*", "Word[0]"));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for pattern in ["", "  ", "Real[20..10]", "Real[0..99999999999999999999999]", "Word[99999999999999999999999]",
+                        "Real[", "[3]", "Real[x]", "Real[1..]", "Real[*"] {
+            assert!(TagPattern::parse(pattern).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn max_rate_is_clamped() {
+        assert_eq!(subscription_rate(None), Ok(MAX_RATE_HZ));
+        assert_eq!(subscription_rate(Some(1000.0)), Ok(MAX_RATE_HZ));
+        assert_eq!(subscription_rate(Some(0.01)), Ok(MIN_RATE_HZ));
+        assert_eq!(subscription_rate(Some(2.0)), Ok(2.0));
+        assert!(subscription_rate(Some(0.0)).is_err());
+        assert!(subscription_rate(Some(-1.0)).is_err());
+        assert!(subscription_rate(Some(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn invokes_limited_per_session() {
+        let invokes = Arc::new(Semaphore::new(MAX_INFLIGHT_INVOKES));
+        let mut permits: Vec<_> = (0..MAX_INFLIGHT_INVOKES)
+            .map(|_| start_invoke(&invokes, &serde_json::json!(1)).unwrap())
+            .collect();
+
+        let reply = start_invoke(&invokes, &serde_json::json!("x")).unwrap_err();
+        assert_eq!(reply["id"], "x");
+        assert_eq!(reply["ok"], false);
+
+        permits.pop();
+        assert!(start_invoke(&invokes, &serde_json::json!(2)).is_ok());
+    }
+}
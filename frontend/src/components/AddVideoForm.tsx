@@ -232,7 +232,7 @@ export const AddVideoForm: React.FC<AddVideoFormProps> = ({ onAdd, onCancel }) =
                 value={formData.filePath}
                 onChange={(e) => setFormData({ ...formData, filePath: e.target.value })}
                 className="flex-1 p-3 border border-gray-300 rounded-md text-sm"
-                placeholder="Caminho completo do arquivo ou URL"
+                placeholder="Caminho na biblioteca de vídeos (ex: campanha.mp4)"
               />
               <button
                 onClick={selectVideoFile}
@@ -242,7 +242,7 @@ export const AddVideoForm: React.FC<AddVideoFormProps> = ({ onAdd, onCancel }) =
               </button>
             </div>
             <p className="text-xs text-edp-slate mt-1">
-              Formatos suportados: MP4, AVI, MOV, MKV, WEBM, FLV
+              Formatos suportados: MP4, WEBM, MKV, MOV, AVI, OGG
             </p>
           </div>
         </div>
@@ -49,7 +49,7 @@ export const TemplateEditor: React.FC<TemplateEditorProps> = ({
   const wordIndices = extractWordIndices(value);
   const hasRealData = plcData && plcData.variables;
   const previewText = value
-    ? (hasRealData ? parseTemplate(value, plcData.variables) : previewTemplate(value))
+    ? (hasRealData ? parseTemplate(value, plcData.variables, plcData.texts, plcData.quality) : previewTemplate(value))
     : '';
 
   const tagPreview = varType === 'int'
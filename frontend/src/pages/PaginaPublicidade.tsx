@@ -1,5 +1,5 @@
-import React, { useState, useEffect } from 'react';
-import { invoke, getVideoUrl } from '../services/api';
+import React, { useState, useEffect, useRef } from 'react';
+import { invoke, getVideoUrl, uploadVideo } from '../services/api';
 import { Video, Upload, Trash2, Play, Edit, X, Check, Clock, RefreshCw, Folder, Zap, AlertCircle, Settings, MemoryStick, ChevronUp, ChevronDown } from 'lucide-react';
 import type { VideoConfig } from '../types';
 import { AddVideoForm } from '../components/AddVideoForm';
@@ -21,6 +21,8 @@ export const PaginaPublicidade: React.FC = () => {
   const [paginaAtual, setPaginaAtual] = useState(1);
   const itensPorPagina = 10;
   const { toast, confirm } = useToast();
+  const fileInputRef = useRef<HTMLInputElement>(null);
+  const [isUploading, setIsUploading] = useState(false);
 
   useEffect(() => {
     loadVideos();
@@ -48,25 +50,31 @@ export const PaginaPublicidade: React.FC = () => {
     }
   };
 
-  const handleSelectVideos = async () => {
-    const filePath = window.prompt('Introduza o caminho completo do vídeo no servidor:\n(ex: /home/rls/videos/publicidade.mp4)');
-    if (!filePath) return;
+  const handleSelectVideos = () => {
+    fileInputRef.current?.click();
+  };
 
-    try {
-      const fileName = filePath.split('/').pop() || 'video';
-      await invoke('add_video', {
-        name: fileName,
-        filePath: filePath,
-        duration: 30,
-        enabled: true,
-        priority: 50,
-        description: ''
-      });
-      await loadVideos();
-      toast('success', 'Vídeo adicionado com sucesso!');
-    } catch (error) {
-      console.error('Erro ao adicionar vídeo:', error);
-      toast('error', `Erro ao adicionar vídeo: ${error}`);
+  // Envia os ficheiros escolhidos para a biblioteca do servidor (um VideoConfig por ficheiro)
+  const handleFilesSelected = async (event: React.ChangeEvent<HTMLInputElement>) => {
+    const files = Array.from(event.target.files || []);
+    event.target.value = '';
+    if (files.length === 0) return;
+
+    setIsUploading(true);
+    let enviados = 0;
+    for (const file of files) {
+      try {
+        await uploadVideo(file, { duration: 30, enabled: true, priority: 50 });
+        enviados++;
+      } catch (error) {
+        console.error('Erro ao enviar vídeo:', error);
+        toast('error', `Erro ao enviar ${file.name}: ${error}`);
+      }
+    }
+    setIsUploading(false);
+    await loadVideos();
+    if (enviados > 0) {
+      toast('success', enviados === 1 ? 'Vídeo adicionado com sucesso!' : `${enviados} vídeos adicionados com sucesso!`);
     }
   };
 
@@ -293,13 +301,22 @@ export const PaginaPublicidade: React.FC = () => {
                 <RefreshCw size={16} className={isLoading ? "animate-spin" : ""} />
               </button>
 
-              {/* Botão Selecionar */}
+              {/* Botão Selecionar (upload para a biblioteca do servidor) */}
+              <input
+                ref={fileInputRef}
+                type="file"
+                accept=".mp4,.webm,.mkv,.mov,.avi,.ogg,.ogv,video/*"
+                multiple
+                className="hidden"
+                onChange={handleFilesSelected}
+              />
               <button
                 onClick={handleSelectVideos}
-                className="flex items-center gap-2 px-4 py-2.5 text-sm font-medium bg-edp-marine text-white rounded-lg hover:bg-edp-marine-100 transition-colors"
+                disabled={isUploading}
+                className="flex items-center gap-2 px-4 py-2.5 text-sm font-medium bg-edp-marine text-white rounded-lg hover:bg-edp-marine-100 transition-colors disabled:opacity-50"
               >
-                <Upload size={16} />
-                Selecionar
+                <Upload size={16} className={isUploading ? "animate-pulse" : ""} />
+                {isUploading ? 'A enviar...' : 'Selecionar'}
               </button>
 
               {/* Botão Manual */}
@@ -693,7 +710,7 @@ const VideoTableRow: React.FC<{
                     value={formData.file_path}
                     onChange={(e) => setFormData({ ...formData, file_path: e.target.value })}
                     className="w-full px-4 py-3 pl-10 border border-gray-300 rounded-xl focus:ring-2 focus:ring-edp-marine focus:border-edp-marine transition-edp bg-gray-50 focus:bg-white text-sm font-mono"
-                    placeholder="C:\\Videos\\publicidade.mp4"
+                    placeholder="publicidade.mp4"
                   />
                   <Folder size={18} className="absolute left-3 top-1/2 -translate-y-1/2 text-gray-400" />
                 </div>
@@ -1,6 +1,10 @@
 // api.ts - Camada de comunicação com o backend Rust
 // Substitui invoke() do Tauri por fetch() REST
 // Substitui listen() do Tauri por EventSource (SSE)
+// connectPlcSocket(): WebSocket com subscrição por tags e comandos na mesma ligação
+// uploadVideo(): envio de vídeos para a biblioteca do servidor (multipart)
+
+import type { PlcData, VariableQuality, VideoConfig } from '../types';
 
 const API_BASE = `http://${window.location.hostname}:3001`;
 
@@ -23,6 +27,19 @@ export async function invoke<T>(command: string, args?: Record<string, unknown>)
   return response.json();
 }
 
+/**
+ * Invoke ao fechar a página (pagehide): o pedido sobrevive ao descarregamento
+ * (fetch keepalive); não há resposta a tratar
+ */
+export function invokeOnUnload(command: string, args?: Record<string, unknown>): void {
+  fetch(`${API_BASE}/api/invoke`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ command, args: args || {} }),
+    keepalive: true,
+  }).catch(() => {});
+}
+
 /**
  * Listen - subscrever a eventos SSE do backend
  * Retorna função de cleanup (compatível com Tauri listen)
@@ -49,9 +66,231 @@ export async function listen<T>(
   return () => eventSource.close();
 }
 
+/**
+ * Mudanças enviadas entre keyframes no modo delta
+ */
+interface PlcDelta {
+  timestamp: string;
+  received_at?: string;
+  source_ip: string;
+  conn_id: number;
+  plc_name: string;
+  variables?: Record<string, number>;
+  texts?: Record<string, string>;
+  quality?: Record<string, VariableQuality>;
+  removed?: string[];
+}
+
+/**
+ * Stream de dados PLC em modo delta (menos tráfego em ligações fracas).
+ * Recebe keyframes completos e deltas, e entrega sempre o PlcData completo.
+ * Deltas que chegam antes do primeiro keyframe de um PLC são ignorados.
+ */
+export function listenPlcData(
+  handler: (data: PlcData) => void,
+  options: { plc?: string | null; keyframeSecs?: number } = {}
+): () => void {
+  const params = new URLSearchParams({ mode: 'delta' });
+  if (options.plc) params.set('plc', options.plc);
+  if (options.keyframeSecs) params.set('keyframe', String(options.keyframeSecs));
+
+  const eventSource = new EventSource(`${API_BASE}/api/events/plc-data?${params}`);
+  const frames = new Map<string, PlcData>();
+
+  // O servidor perdeu frames para este cliente: esquecer o estado e esperar pelos keyframes
+  eventSource.addEventListener('resync', () => frames.clear());
+
+  eventSource.onmessage = (e) => {
+    let payload: { message?: PlcData; delta?: PlcDelta };
+    try {
+      payload = JSON.parse(e.data);
+    } catch {
+      return; // Ignorar mensagens inválidas
+    }
+
+    if (payload.message) {
+      frames.set(payload.message.source_ip, payload.message);
+      handler(payload.message);
+      return;
+    }
+
+    const delta = payload.delta;
+    const base = delta && frames.get(delta.source_ip);
+    if (!delta || !base) return;
+
+    const next: PlcData = {
+      ...base,
+      timestamp: delta.timestamp,
+      received_at: delta.received_at,
+      conn_id: delta.conn_id,
+      plc_name: delta.plc_name,
+      variables: { ...base.variables, ...delta.variables },
+      texts: { ...base.texts, ...delta.texts },
+      quality: { ...base.quality },
+    };
+    for (const [name, quality] of Object.entries(delta.quality ?? {})) {
+      if (quality === 'good') delete next.quality![name];
+      else next.quality![name] = quality;
+    }
+    for (const name of delta.removed ?? []) {
+      delete next.variables[name];
+      delete next.texts![name];
+    }
+
+    frames.set(delta.source_ip, next);
+    handler(next);
+  };
+
+  return () => eventSource.close();
+}
+
+/**
+ * Ligação WebSocket /api/ws: dados só das tags subscritas + comandos invoke
+ */
+export interface PlcSocket {
+  subscribe(tags: string[], options?: { plc?: string | null; maxRate?: number }): void;
+  unsubscribe(): void;
+  invoke<T>(command: string, args?: Record<string, unknown>): Promise<T>;
+  close(): void;
+}
+
+/**
+ * Abre o WebSocket e entrega o PlcData (só com as tags subscritas) a cada mudança.
+ * A subscrição é reenviada automaticamente quando a ligação é reposta.
+ */
+export function connectPlcSocket(handler: (data: PlcData) => void): PlcSocket {
+  const url = `ws://${window.location.hostname}:3001/api/ws`;
+  const frames = new Map<string, PlcData>();
+  const pending = new Map<number, { resolve: (v: unknown) => void; reject: (e: Error) => void }>();
+  let subscription: Record<string, unknown> | null = null;
+  let nextId = 1;
+  let closed = false;
+  let socket: WebSocket;
+
+  const send = (message: Record<string, unknown>) => {
+    if (socket.readyState === WebSocket.OPEN) socket.send(JSON.stringify(message));
+  };
+
+  const open = () => {
+    socket = new WebSocket(url);
+    socket.onopen = () => {
+      if (subscription) send(subscription);
+    };
+    socket.onclose = () => {
+      for (const { reject } of pending.values()) reject(new Error('Ligação WebSocket fechada'));
+      pending.clear();
+      if (!closed) setTimeout(open, 2000);
+    };
+    socket.onmessage = (e) => {
+      let message: { type: string; id?: number; ok?: boolean; data?: unknown; error?: string; delta?: PlcDelta };
+      try {
+        message = JSON.parse(e.data);
+      } catch {
+        return; // Ignorar mensagens inválidas
+      }
+
+      if (message.type === 'result' && message.id !== undefined) {
+        const call = pending.get(message.id);
+        pending.delete(message.id);
+        if (message.ok) call?.resolve(message.data);
+        else call?.reject(new Error(message.error));
+        return;
+      }
+      if (message.type !== 'data' || !message.delta) return;
+
+      const delta = message.delta;
+      // Nova conexão do PLC: o servidor reenvia tudo, o estado anterior é descartado
+      const previous = frames.get(delta.source_ip);
+      const base = previous?.conn_id === delta.conn_id ? previous : undefined;
+      const next: PlcData = {
+        timestamp: delta.timestamp,
+        received_at: delta.received_at,
+        source_ip: delta.source_ip,
+        conn_id: delta.conn_id,
+        plc_name: delta.plc_name,
+        variables: { ...base?.variables, ...delta.variables },
+        texts: { ...base?.texts, ...delta.texts },
+        quality: { ...base?.quality },
+      };
+      for (const [name, quality] of Object.entries(delta.quality ?? {})) {
+        if (quality === 'good') delete next.quality![name];
+        else next.quality![name] = quality;
+      }
+      for (const name of delta.removed ?? []) {
+        delete next.variables[name];
+        delete next.texts![name];
+      }
+
+      frames.set(delta.source_ip, next);
+      handler(next);
+    };
+  };
+  open();
+
+  return {
+    subscribe(tags, options = {}) {
+      frames.clear();
+      subscription = { type: 'subscribe', tags, plc: options.plc ?? null, maxRate: options.maxRate };
+      send(subscription);
+    },
+    unsubscribe() {
+      subscription = null;
+      frames.clear();
+      send({ type: 'unsubscribe' });
+    },
+    invoke<T>(command: string, args?: Record<string, unknown>): Promise<T> {
+      const id = nextId++;
+      return new Promise<T>((resolve, reject) => {
+        if (socket.readyState !== WebSocket.OPEN) {
+          reject(new Error('Ligação WebSocket não está aberta'));
+          return;
+        }
+        pending.set(id, { resolve: resolve as (v: unknown) => void, reject });
+        send({ type: 'invoke', id, command, args: args || {} });
+      });
+    },
+    close() {
+      closed = true;
+      socket.close();
+    },
+  };
+}
+
 /**
  * Gera URL para vídeo servido pelo backend
  */
 export function getVideoUrl(filePath: string): string {
-  return `${API_BASE}/api/video${encodeURI(filePath)}`;
+  // file_path é relativo à biblioteca de vídeos ("campanha.mp4", "edp/verao.mp4")
+  const path = filePath.split('/').filter(Boolean).map(encodeURIComponent).join('/');
+  return `${API_BASE}/api/video/${path}`;
+}
+
+/**
+ * Envia um vídeo para a biblioteca do servidor e cria o VideoConfig (POST /api/videos/upload)
+ */
+export async function uploadVideo(
+  file: File,
+  fields: { name?: string; duration?: number; enabled?: boolean; priority?: number; description?: string } = {},
+): Promise<VideoConfig> {
+  const form = new FormData();
+  Object.entries(fields).forEach(([key, value]) => {
+    if (value !== undefined) form.append(key, String(value));
+  });
+  form.append('file', file);
+
+  const response = await fetch(`${API_BASE}/api/videos/upload`, { method: 'POST', body: form });
+  if (!response.ok) {
+    const errorText = await response.text();
+    throw new Error(errorText || `Erro ${response.status}`);
+  }
+  return response.json();
+}
+
+/**
+ * URL de um relatório CSV de exibição (plays = vídeos, ad-gaps = publicidade suprimida)
+ */
+export function getReportCsvUrl(report: 'plays' | 'ad-gaps', params: { from?: string; to?: string; panel?: string }): string {
+  const query = new URLSearchParams();
+  Object.entries(params).forEach(([key, value]) => { if (value) query.set(key, value); });
+  return `${API_BASE}/api/reports/${report}.csv?${query}`;
 }
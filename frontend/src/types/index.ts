@@ -1,8 +1,17 @@
 export interface PlcData {
-  timestamp: string;
+  timestamp: string;       // Carimbo do PLC (se o layout o trouxer) ou hora de receção
+  received_at?: string;    // Hora de receção, só quando timestamp vem do PLC
+  source_ip: string;       // IP do PLC de origem
+  conn_id: number;         // ID da conexão TCP
+  plc_name: string;        // Nome amigável do PLC
   variables: Record<string, number>;
+  texts?: Record<string, string>;  // String, Time e Dtl formatados
+  quality?: Record<string, VariableQuality>;  // Só variáveis que não estão boas
 }
 
+// Qualidade de uma variável PLC (ausente no mapa = 'good')
+export type VariableQuality = 'good' | 'non_finite' | 'stale' | 'out_of_range' | 'substituted';
+
 export interface EclusaStatus {
   velocidadeMontante: number;
   velocidadeCaldeira: number;
@@ -70,6 +79,110 @@ export interface VideoConfig {
   display_order: number;   // Ordem de exibição
 }
 
+// Playlist de publicidade com horário (roda quando o bit de controle de vídeo está a 1)
+export interface PlaylistItem {
+  video_id: number;
+  weight: number;          // Vezes por ciclo (1..100)
+}
+
+export interface Playlist {
+  id: number;              // 0 = nova
+  name: string;
+  enabled: boolean;
+  priority: number;        // Maior ganha quando várias estão em vigor
+  valid_from: string | null;   // "AAAA-MM-DD" (inclusivo)
+  valid_until: string | null;
+  days: number[];          // 1 = segunda .. 7 = domingo; vazio = todos
+  start_time: string | null;   // "HH:MM"; fim < início atravessa a meia-noite
+  end_time: string | null;
+  items: PlaylistItem[];
+}
+
+// Playlist em vigor de um painel (get_panel_playlist)
+export interface PanelPlaylist {
+  panel: string;
+  playlist: Playlist | null;   // null = sem playlist em vigor: todos os vídeos ativos
+  videos: VideoConfig[];       // Um ciclo, já expandido pelos pesos
+  server_time: string;
+}
+
+// Painel físico (panel.html?panel=<id>); listas a null = todos os itens globais
+export interface Panel {
+  id: string;
+  name: string;
+  width: number;           // Resolução (px)
+  height: number;
+  plc: string | null;      // PLC que alimenta o painel (IP, nome ou conn_id)
+  idle_text_key: string | null;
+  bit_ids: number[] | null;
+  video_ids: number[] | null;
+  text_keys: string[] | null;
+  playlist_ids: number[] | null;
+}
+
+// Estado oficial do painel decidido pelo servidor (/api/events/panel-state)
+export interface PanelMessage {
+  text: string;            // Mensagem com o template já resolvido
+  source: 'bit' | 'idle';
+  word_index: number | null;
+  bit_index: number | null;
+  name: string;
+  priority: number;
+  color: string;
+  font_size: number;
+  position: string;
+  font_family: string;
+  font_weight: string;
+  text_shadow: boolean;
+  letter_spacing: number;
+}
+
+export interface PanelVideo {
+  id: number;
+  name: string;
+  file_path: string;
+  duration: number;
+  source: 'playlist' | 'bit';  // Rotação pelo bit de controle ou vídeo de um bit
+  playlist_id: number | null;  // Playlist em vigor (null = todos os vídeos ativos)
+  started_at: string;          // Início no relógio do servidor
+}
+
+export interface PanelState {
+  panel: string;           // Id do painel ('default' = configuração global)
+  seq: number;
+  mode: 'idle' | 'message' | 'video';
+  message: PanelMessage | null;
+  video: PanelVideo | null;
+  source_ip: string | null;
+  updated_at: string;
+  server_time: string;         // Hora do envio (posição do vídeo = server_time - started_at)
+}
+
+// Relatórios de exibição (get_play_report; CSV em /api/reports/*.csv)
+export interface PlayReportRow {
+  day: string;             // AAAA-MM-DD (hora local do servidor)
+  panel: string;
+  video_id: number;
+  video_name: string;
+  plays: number;           // Exibições iniciadas
+  finished: number;
+  aborted: number;
+  seconds: number;         // Tempo total no ecrã
+}
+
+// Publicidade suprimida pelo bit de controle de vídeo (a 0 com vídeos para rodar)
+export interface AdGapReportRow {
+  day: string;
+  panel: string;
+  gaps: number;
+  seconds: number;
+}
+
+export interface PlayReport {
+  plays: PlayReportRow[];
+  gaps: AdGapReportRow[];
+}
+
 export interface SystemLog {
   id: number;
   timestamp: string;       // Data/hora do evento
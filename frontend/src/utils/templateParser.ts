@@ -16,16 +16,53 @@
  *   {Real[N]:D}            → float com D casas decimais
  *   {Word[N]}              → valor unsigned do endereço N (retrocompatível)
  *   {Word[N]/D}            → valor unsigned dividido por D
+ *
+ * Segmentos de outros layouts (DInt, UDInt, LReal, Bool, String, Time, Dtl)
+ * usam o nome do segmento tal como chega do backend:
+ *
+ *   {Nome[N]}              → valor do elemento N (número ou texto)
+ *   {Nome[N]:D}            → número com D casas decimais
+ *   {Nome[N]/D}            → número dividido por D
+ *   {Nome[N]*M}            → número multiplicado por M
+ *
+ *   ex: {Contador[0]}, {Temperatura[2]:1}, {Receita[0]}, {Carimbo[0]}
+ *
+ * Variáveis com qualidade má (NaN/Inf, dados parados, fora da faixa) são
+ * mostradas como BAD_QUALITY_PLACEHOLDER. Valores substituídos pelo valor de
+ * substituição do layout são mostrados normalmente.
  */
 
+import type { VariableQuality } from '../types';
+
 export interface PlcVariables {
   [key: string]: number;
 }
 
+export interface PlcTexts {
+  [key: string]: string;
+}
+
+export interface PlcQuality {
+  [key: string]: VariableQuality;
+}
+
+export const BAD_QUALITY_PLACEHOLDER = '---';
+
+/**
+ * Verdadeiro se alguma das variáveis não pode ser mostrada
+ */
+function isBad(quality: PlcQuality, ...keys: string[]): boolean {
+  return keys.some(k => {
+    const q = quality[k];
+    return q !== undefined && q !== 'good' && q !== 'substituted';
+  });
+}
+
 // Regex patterns
 const INT_RE = /\{Int\[(\d+)\](?:\/([.\d]+)|\*([.\d]+))?\}/g;
 const REAL_RE = /\{Real\[(\d+)\](?::(\d+))?\}/g;
 const WORD_RE = /\{Word\[(\d+)\](?:\/([.\d]+)|\*([.\d]+))?\}/g;
+const SEGMENT_RE = /\{([A-Za-z_][\w]*)\[(\d+)\](?::(\d+)|\/([.\d]+)|\*([.\d]+))?\}/g;
 
 /**
  * Converte 2 Words (hi + lo) em float IEEE 754
@@ -60,7 +97,12 @@ function formatDivided(val: number, divStr: string): string {
 /**
  * Substitui todas as tags do template pelos valores reais do PLC
  */
-export function parseTemplate(template: string, variables: PlcVariables): string {
+export function parseTemplate(
+  template: string,
+  variables: PlcVariables,
+  texts: PlcTexts = {},
+  quality: PlcQuality = {},
+): string {
   if (!template) return '';
 
   let result = template;
@@ -68,6 +110,7 @@ export function parseTemplate(template: string, variables: PlcVariables): string
   // Real[N] primeiro (usa 2 words consecutivos)
   result = result.replace(REAL_RE, (_m, idx, decimals) => {
     const n = parseInt(idx);
+    if (isBad(quality, `Word[${n}]`, `Word[${n + 1}]`)) return BAD_QUALITY_PLACEHOLDER;
     const hi = variables[`Word[${n}]`];
     const lo = variables[`Word[${n + 1}]`];
     if (hi === undefined || lo === undefined) return '{...}';
@@ -78,6 +121,7 @@ export function parseTemplate(template: string, variables: PlcVariables): string
 
   // Int[N] (signed)
   result = result.replace(INT_RE, (_m, idx, div, mul) => {
+    if (isBad(quality, `Word[${idx}]`)) return BAD_QUALITY_PLACEHOLDER;
     const raw = variables[`Word[${idx}]`];
     if (raw === undefined) return '{...}';
     const val = toSigned16(raw);
@@ -88,6 +132,7 @@ export function parseTemplate(template: string, variables: PlcVariables): string
 
   // Word[N] (unsigned, retrocompatível)
   result = result.replace(WORD_RE, (_m, idx, div, mul) => {
+    if (isBad(quality, `Word[${idx}]`)) return BAD_QUALITY_PLACEHOLDER;
     const val = variables[`Word[${idx}]`];
     if (val === undefined) return '{...}';
     if (div) return formatDivided(val, div);
@@ -95,6 +140,20 @@ export function parseTemplate(template: string, variables: PlcVariables): string
     return String(val);
   });
 
+  // Nome[N] genérico (restantes segmentos do layout)
+  result = result.replace(SEGMENT_RE, (_m, name, idx, decimals, div, mul) => {
+    const key = `${name}[${idx}]`;
+    if (isBad(quality, key)) return BAD_QUALITY_PLACEHOLDER;
+    const text = texts[key];
+    if (text !== undefined) return text;
+    const val = variables[key];
+    if (val === undefined) return '{...}';
+    if (decimals) return val.toFixed(parseInt(decimals));
+    if (div) return formatDivided(val, div);
+    if (mul) return String(Math.round(val * parseFloat(mul)));
+    return String(val);
+  });
+
   return result;
 }
 